@@ -0,0 +1,91 @@
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ComposeFileError, utils};
+
+pub const COMPOSE_FILE_NAME: &str = "thorc-compose.toml";
+
+/// Several templates generated into subdirectories of one project.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ComposeFile {
+    /// variables shared by every template in the file.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+
+    #[serde(default, rename = "template")]
+    pub templates: Vec<ComposeEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ComposeEntry {
+    pub name: String,
+    #[serde(default)]
+    pub index: Option<String>,
+
+    /// target directory, relative to the compose root.
+    pub directory: PathBuf,
+
+    #[serde(default)]
+    pub project_name: Option<String>,
+
+    /// variables for this template only, overriding the shared ones.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl ComposeFile {
+    pub fn load(path: &Path) -> Result<Self, ComposeFileError> {
+        let contents = fs::read_to_string(path)?;
+        let compose = toml::from_str(&contents)?;
+
+        Ok(compose)
+    }
+}
+
+impl ComposeEntry {
+    /// Where to generate this template under the compose `root`; `None` if
+    /// its directory is absolute or leads out of `root` with `..`.
+    pub fn target(&self, root: &Path) -> Option<PathBuf> {
+        utils::join_within(root, &self.directory)
+    }
+
+    pub fn variables(&self, shared: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+        let mut variables = shared.clone();
+        variables.extend(self.variables.clone());
+        variables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(directory: &str) -> ComposeEntry {
+        toml::from_str(&format!("name = \"t\"\ndirectory = \"{}\"", directory)).unwrap()
+    }
+
+    #[test]
+    fn targets_stay_in_the_root() {
+        let root = Path::new("root");
+
+        assert_eq!(entry("app").target(root), Some(root.join("app")));
+        assert_eq!(
+            entry("services/api").target(root),
+            Some(root.join("services/api"))
+        );
+        assert_eq!(entry("./web").target(root), Some(root.join("./web")));
+    }
+
+    #[test]
+    fn targets_cannot_leave_the_root() {
+        for directory in ["../elsewhere", "app/../../elsewhere", "/etc"] {
+            assert_eq!(
+                entry(directory).target(Path::new("root")),
+                None,
+                "{}",
+                directory
+            );
+        }
+    }
+}
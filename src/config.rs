@@ -1,16 +1,405 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{error::GetIndexError, index::TemplateIndex, remote_index::RemoteIndex};
+#[cfg(feature = "network")]
+use crate::{error::GetIndexError, index::TemplateIndex};
+use crate::{
+    error::{ConfigFileError, NoSuchProfileError, SecretError},
+    hooks::{self, HookPolicy},
+    remote_index::{IndexSource, RemoteIndex},
+    repo_def::GitProvider,
+    schema::{self, Migration},
+    secrets,
+    template::Template,
+    utils,
+};
 
+/// Version of the config layout this thorc reads and writes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// `MIGRATIONS[i]` upgrades a config from version `i` to `i + 1`. Version 0
+/// is the layout from before configs had a `schema_version`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    // 0 -> 1: only adds `schema_version` itself
+    |_| {},
+    // 1 -> 2: `hooks = "run" | "skip"` moves to `[security]`, as "always" |
+    // "never"
+    |table| {
+        if let Some(hooks) = table.remove("hooks") {
+            let hooks = match hooks.as_str() {
+                Some("run") => toml::Value::from("always"),
+                Some("skip") => toml::Value::from("never"),
+                _ => hooks,
+            };
+
+            if let toml::Value::Table(security) = table
+                .entry("security")
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+            {
+                security.entry("hooks").or_insert(hooks);
+            }
+        }
+    },
+];
+
+/// Name of the project config, looked for in the current directory and
+/// every directory above it.
+pub const PROJECT_CONFIG_FILE_NAME: &str = ".thorc.toml";
+
+// plain values come before tables, which toml can only write last
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
+    #[serde(default)]
+    pub schema_version: u32,
+    /// the index templates are looked up in first when none is named:
+    /// `local` or a remote index's name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_index: Option<String>,
+    /// look templates up only in `default_index`, not in the others after it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default_index_only: bool,
+    /// where templates that don't say otherwise keep their hooks and
+    /// manifest; [`hooks::DEFAULT_HOOKS_DIR`] if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks_dir: Option<String>,
+    /// leave out the `.git` directories of local templates when copying
+    /// them into new projects.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skip_git_dirs: bool,
+    #[serde(default, rename = "remote_index")]
+    pub remote_indexes: Vec<RemoteIndex>,
+    /// defaults for the variables of every generated project, like `author`,
+    /// `email` or `license`, so they needn't be given each time.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "RepoDefaults::is_empty")]
+    pub defaults: RepoDefaults,
+    #[serde(default, skip_serializing_if = "SecurityConfig::is_empty")]
+    pub security: SecurityConfig,
+    #[serde(
+        default,
+        rename = "profile",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "SecretsConfig::is_empty")]
+    pub secrets: SecretsConfig,
+    /// names of the variables [`Config::decrypt_secrets`] decrypted.
+    #[serde(skip)]
+    pub(crate) secret_variables: BTreeSet<String>,
+}
+
+/// How to decrypt encrypted values, as `[secrets]`; see [`crate::secrets`].
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SecretsConfig {
+    /// age identity file to decrypt with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<PathBuf>,
+    /// `age` if not set; `rage` takes the same arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+}
+
+impl SecretsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.identity.is_none() && self.program.is_none()
+    }
+}
+
+/// A named set of indexes, variables, defaults and the credentials to
+/// decrypt them with, as `[profile.<name>]`, layered over the rest of the
+/// config when selected; e.g. one for work and one for open source.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_index: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default_index_only: bool,
     #[serde(default, rename = "remote_index")]
     pub remote_indexes: Vec<RemoteIndex>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "RepoDefaults::is_empty")]
+    pub defaults: RepoDefaults,
+    /// as `[profile.<name>.secrets]`, e.g. the identity for the work
+    /// profile's encrypted index urls and tokens.
+    #[serde(default, skip_serializing_if = "SecretsConfig::is_empty")]
+    pub secrets: SecretsConfig,
+}
+
+/// Whether templates may run code, as `[security]`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SecurityConfig {
+    /// whether to run setup hooks; [`HookPolicy::Always`] if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<HookPolicy>,
+    /// `hooks` for templates from particular indexes (`local` or a remote
+    /// index's name), e.g. to trust a company index but not public ones.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub indexes: BTreeMap<String, HookPolicy>,
+}
+
+impl SecurityConfig {
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_none() && self.indexes.is_empty()
+    }
+}
+
+/// What `add-to-index` and `add-remote-index` use for repo flags that
+/// aren't given, for people adding many templates from the same place.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RepoDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_provider: Option<GitProvider>,
+    /// user or organization owning the repos.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+}
+
+impl RepoDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.git_provider.is_none() && self.user.is_none() && self.git_ref.is_none()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            remote_indexes: Vec::new(),
+            default_index: None,
+            default_index_only: false,
+            hooks_dir: None,
+            skip_git_dirs: false,
+            variables: BTreeMap::new(),
+            defaults: RepoDefaults::default(),
+            security: SecurityConfig::default(),
+            profiles: BTreeMap::new(),
+            secrets: SecretsConfig::default(),
+            secret_variables: BTreeSet::new(),
+        }
+    }
+}
+
+/// The nearest [`PROJECT_CONFIG_FILE_NAME`] in `dir` or above it.
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|it| it.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|it| it.is_file())
 }
 
 impl Config {
+    /// Loads the config, migrating it from older schema versions. A migrated
+    /// config is saved back in place, with the original kept next to it as
+    /// `<file>.v<version>.bak`.
+    pub fn load(path: &Path) -> Result<Self, ConfigFileError> {
+        let (config, migrated) = Self::load_migrating(path)?;
+
+        if let Some((_, backup)) = migrated {
+            tracing::info!(
+                "Migrated {} to schema version {}; the original is in {}",
+                path.display(),
+                SCHEMA_VERSION,
+                backup.display()
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Migrates the config at `path` to [`SCHEMA_VERSION`] as [`Config::load`]
+    /// does, project configs included. Returns the version it was at and
+    /// where the original was kept, or `None` if it was already current.
+    pub fn migrate(path: &Path) -> Result<Option<(u32, PathBuf)>, ConfigFileError> {
+        Ok(Self::load_migrating(path)?.1)
+    }
+
+    fn load_migrating(path: &Path) -> Result<(Self, Option<(u32, PathBuf)>), ConfigFileError> {
+        let contents = fs::read_to_string(path)?;
+        let (config, version) = schema::from_toml::<Config>(&contents, &MIGRATIONS)?;
+
+        if version == SCHEMA_VERSION {
+            return Ok((config, None));
+        }
+
+        let backup = schema::backup(path, version, &contents)?;
+        config.save(path)?;
+
+        Ok((config, Some((version, backup))))
+    }
+
+    /// Loads a project config; like [`Config::load`], but never writes back,
+    /// as the file likely belongs to a repository.
+    pub fn load_project(path: &Path) -> Result<Self, ConfigFileError> {
+        let contents = fs::read_to_string(path)?;
+
+        Ok(schema::from_toml(&contents, &MIGRATIONS)?.0)
+    }
+
+    /// Layers a project config over this one: its indexes are added,
+    /// replacing ours by the same name, and its default index, hooks
+    /// directory, variables and defaults win over ours, as does
+    /// `skip_git_dirs` if set. Its hook policies only apply where stricter,
+    /// so that a checked-out repository can't turn hooks back on; nor can it
+    /// borrow the trust put in one of our indexes by pointing its name
+    /// elsewhere, as an index it lists is only trusted as much as any.
+    pub fn layer(&mut self, project: Config) {
+        let global = self.hook_policy(None);
+        for index in project.remote_indexes {
+            if let Some(ours) = self.security.indexes.get_mut(&index.name) {
+                *ours = (*ours).max(global);
+            }
+            match self
+                .remote_indexes
+                .iter_mut()
+                .find(|it| it.name == index.name)
+            {
+                Some(existing) => *existing = index,
+                None => self.remote_indexes.push(index),
+            }
+        }
+
+        if project.default_index.is_some() {
+            self.default_index = project.default_index;
+            self.default_index_only = project.default_index_only;
+        }
+
+        if project.hooks_dir.is_some() {
+            self.hooks_dir = project.hooks_dir;
+        }
+
+        self.skip_git_dirs |= project.skip_git_dirs;
+
+        self.variables.extend(project.variables);
+
+        let defaults = project.defaults;
+        self.defaults.git_provider = defaults.git_provider.or(self.defaults.git_provider.take());
+        self.defaults.user = defaults.user.or(self.defaults.user.take());
+        self.defaults.git_ref = defaults.git_ref.or(self.defaults.git_ref.take());
+
+        let security = project.security;
+        if let Some(theirs) = security.hooks {
+            for ours in self.security.indexes.values_mut() {
+                *ours = (*ours).max(theirs);
+            }
+            self.security.hooks = Some(self.hook_policy(None).max(theirs));
+        }
+        for (index, theirs) in security.indexes {
+            let ours = self.hook_policy(Some(&index));
+            self.security.indexes.insert(index, ours.max(theirs));
+        }
+    }
+
+    /// Layers the profile called `name` over this config, like a project
+    /// config (see [`Config::layer`]). Unlike a project config, it can also
+    /// choose how secrets are decrypted.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), NoSuchProfileError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| NoSuchProfileError(name.to_string()))?;
+
+        self.layer(Config {
+            default_index: profile.default_index,
+            default_index_only: profile.default_index_only,
+            remote_indexes: profile.remote_indexes,
+            variables: profile.variables,
+            defaults: profile.defaults,
+            ..Config::default()
+        });
+
+        let secrets = profile.secrets;
+        self.secrets.identity = secrets.identity.or(self.secrets.identity.take());
+        self.secrets.program = secrets.program.or(self.secrets.program.take());
+
+        Ok(())
+    }
+
+    /// Decrypts the variables and index urls that are encrypted, see
+    /// [`crate::secrets`]. Only for using the config: saved, it would have
+    /// them in the clear.
+    pub fn decrypt_secrets(&mut self) -> Result<(), SecretError> {
+        let program = self
+            .secrets
+            .program
+            .as_deref()
+            .unwrap_or(secrets::DEFAULT_PROGRAM);
+        let identity = self.secrets.identity.as_deref();
+        let decrypt = |value: &mut String| -> Result<(), SecretError> {
+            if secrets::is_encrypted(value) {
+                let identity = identity.ok_or(SecretError::NoIdentity)?;
+                *value = secrets::decrypt(value, program, identity)?;
+            }
+            Ok(())
+        };
+
+        for (name, value) in &mut self.variables {
+            if secrets::is_encrypted(value) {
+                decrypt(value)?;
+                self.secret_variables.insert(name.clone());
+            }
+        }
+        for index in &mut self.remote_indexes {
+            match &mut index.source {
+                IndexSource::Url { url } | IndexSource::Registry { registry: url } => decrypt(url)?,
+                IndexSource::Repo(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where `template` keeps its hooks: where it says, or else where the
+    /// config says templates do.
+    pub fn hooks_dir<'a>(&'a self, template: &'a Template) -> &'a str {
+        template
+            .hooks_dir()
+            .or(self.hooks_dir.as_deref())
+            .unwrap_or(hooks::DEFAULT_HOOKS_DIR)
+    }
+
+    /// The hook policy in effect for templates from `index`, or for those
+    /// from no index in particular.
+    pub fn hook_policy(&self, index: Option<&str>) -> HookPolicy {
+        index
+            .and_then(|it| self.security.indexes.get(it).copied())
+            .or(self.security.hooks)
+            .unwrap_or_default()
+    }
+
+    /// Loads the config at `path`, lets `f` change it and saves it, holding a
+    /// lock throughout (see [`utils::with_lock`]) so that concurrent edits
+    /// aren't lost.
+    pub fn edit<T, F>(path: &Path, f: F) -> Result<T, ConfigFileError>
+    where
+        F: FnOnce(&mut Config) -> T,
+    {
+        utils::with_lock(path, || {
+            let mut config = Self::load(path)?;
+            let result = f(&mut config);
+            config.save(path)?;
+
+            Ok(result)
+        })
+    }
+
+    /// Writes the config atomically, see [`utils::write_atomic`].
+    pub fn save(&self, path: &Path) -> Result<(), ConfigFileError> {
+        let contents = toml::to_string_pretty(self)?;
+        utils::write_atomic(path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "network")]
     pub fn get_all_remote_indexes_and_names<'a>(
         &'a self,
         cache: &Path,
@@ -21,8 +410,24 @@ impl Config {
             .collect()
     }
 
-    pub fn get_all_remote_indexes<'a>(
-        &'a self,
+    /// Like `get_all_remote_indexes_and_names`, fetching all the indexes
+    /// concurrently.
+    #[cfg(feature = "async")]
+    pub async fn get_all_remote_indexes_and_names_async(
+        &self,
+        cache: &Path,
+    ) -> Result<Vec<(&str, TemplateIndex)>, GetIndexError> {
+        futures::future::try_join_all(
+            self.remote_indexes
+                .iter()
+                .map(|it| async move { Ok((it.name.as_str(), it.get_index_async(cache).await?)) }),
+        )
+        .await
+    }
+
+    #[cfg(feature = "network")]
+    pub fn get_all_remote_indexes(
+        &self,
         cache: &Path,
     ) -> Result<Vec<TemplateIndex>, GetIndexError> {
         self.remote_indexes
@@ -31,3 +436,147 @@ impl Config {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: &str = r#"
+[security]
+hooks = "prompt"
+
+[security.indexes]
+corp = "always"
+public = "never"
+
+[[remote_index]]
+name = "corp"
+url = "https://templates.corp.example/index.toml"
+"#;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn projects_only_tighten_hook_policies() {
+        let mut user = config(USER);
+        user.layer(config(
+            r#"
+[security]
+hooks = "always"
+
+[security.indexes]
+corp = "prompt"
+public = "always"
+"#,
+        ));
+
+        assert_eq!(user.hook_policy(None), HookPolicy::Prompt);
+        assert_eq!(user.hook_policy(Some("corp")), HookPolicy::Prompt);
+        assert_eq!(user.hook_policy(Some("public")), HookPolicy::Never);
+    }
+
+    #[test]
+    fn projects_turning_hooks_off_turn_them_off_everywhere() {
+        let mut user = config(USER);
+        user.layer(config("[security]\nhooks = \"never\"\n"));
+
+        assert_eq!(user.hook_policy(None), HookPolicy::Never);
+        assert_eq!(user.hook_policy(Some("corp")), HookPolicy::Never);
+    }
+
+    #[test]
+    fn projects_cannot_borrow_the_trust_of_an_index() {
+        let mut user = config(USER);
+        user.layer(config(
+            r#"
+[[remote_index]]
+name = "corp"
+url = "https://evil.example/index.toml"
+"#,
+        ));
+
+        assert_eq!(user.remote_indexes.len(), 1);
+        assert!(matches!(
+            &user.remote_indexes[0].source,
+            IndexSource::Url { url } if url == "https://evil.example/index.toml"
+        ));
+        assert_eq!(user.hook_policy(Some("corp")), HookPolicy::Prompt);
+        assert_eq!(user.hook_policy(Some("public")), HookPolicy::Never);
+    }
+
+    #[test]
+    fn profiles_cannot_borrow_the_trust_of_an_index() {
+        let mut user = config(&format!(
+            "{}\n{}",
+            USER,
+            r#"
+[profile.work]
+
+[[profile.work.remote_index]]
+name = "corp"
+url = "https://elsewhere.example/index.toml"
+"#
+        ));
+        user.apply_profile("work").unwrap();
+
+        assert_eq!(user.hook_policy(Some("corp")), HookPolicy::Prompt);
+    }
+
+    #[test]
+    fn unrelated_projects_keep_our_trust() {
+        let mut user = config(USER);
+        user.layer(config("hooks_dir = \"scripts\"\n"));
+
+        assert_eq!(user.hook_policy(Some("corp")), HookPolicy::Always);
+    }
+
+    #[test]
+    fn finds_the_nearest_project_config() {
+        let scratch = crate::utils::tests::Scratch::new("find-project-config");
+        scratch.write("repo/.thorc.toml", "");
+        scratch.write("repo/tools/.thorc.toml", "");
+        scratch.write("repo/tools/cli/src/main.rs", "");
+        scratch.write("repo/docs/index.md", "");
+
+        let find = |dir: &str| find_project_config(&scratch.path().join(dir));
+        assert_eq!(
+            find("repo/tools/cli/src"),
+            Some(scratch.path().join("repo/tools/.thorc.toml"))
+        );
+        assert_eq!(
+            find("repo/docs"),
+            Some(scratch.path().join("repo/.thorc.toml"))
+        );
+    }
+
+    #[test]
+    fn profiles_bring_their_credentials() {
+        let mut user = config(
+            r#"
+[secrets]
+identity = "/home/me/.age/key.txt"
+
+[profile.work.secrets]
+identity = "/home/me/.age/work.txt"
+program = "rage"
+"#,
+        );
+        user.apply_profile("work").unwrap();
+
+        assert_eq!(
+            user.secrets.identity.as_deref(),
+            Some(Path::new("/home/me/.age/work.txt"))
+        );
+        assert_eq!(user.secrets.program.as_deref(), Some("rage"));
+    }
+
+    #[test]
+    fn projects_cannot_choose_credentials() {
+        let mut user = config("[secrets]\nidentity = \"/home/me/.age/key.txt\"\n");
+        user.layer(config("[secrets]\nprogram = \"./decrypt.sh\"\n"));
+
+        assert_eq!(user.secrets.program, None);
+    }
+}
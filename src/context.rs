@@ -0,0 +1,971 @@
+//! The orchestration behind the `thorc` binary: loading the config and the
+//! local index, finding and resolving templates across indexes, and
+//! generating projects from them.
+
+use std::{
+    cell::OnceCell,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+use directories::{BaseDirs, ProjectDirs};
+
+use crate::{
+    cancel::CancelToken,
+    config::{self, Config},
+    error::{Error, NoSuchDirsStrategyError},
+    find_result::{FindResultComposite, MatchMode},
+    hooks::{self, HookExecutor, HookPolicy, PolicyExecutor, ProcessExecutor},
+    http::Downloader,
+    index::TemplateIndex,
+    lock::{LockFile, LOCK_FILE_NAME},
+    observer::{Observer, Phase},
+    template::{QualifiedName, Template},
+    utils::{self, Conflict, OnConflict},
+};
+#[cfg(feature = "network")]
+use crate::{
+    error::DownloadError,
+    http::{self, HttpSettings, ReqwestDownloader},
+    remote_index::DEFAULT_REFRESH,
+    repo_def,
+};
+
+const NAME: &str = env!("CARGO_PKG_NAME");
+const CONFIG_FILE_NAME: &str = concat!(env!("CARGO_PKG_NAME"), ".conf");
+
+/// How the config, local index and cache directory are located when neither
+/// they nor their environment variables are given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirsStrategy {
+    /// the platform's own directories: the XDG ones on Linux,
+    /// `~/Library/Application Support` and `~/Library/Caches` on macOS, and
+    /// `%APPDATA%` and `%LOCALAPPDATA%` on Windows.
+    #[default]
+    Native,
+    /// `$XDG_CONFIG_HOME/thorc` and `$XDG_CACHE_HOME/thorc` (`~/.config/thorc`
+    /// and `~/.cache/thorc` if unset) on every platform.
+    Xdg,
+    /// next to the running executable, with the cache in `thorc-cache`, so
+    /// that thorc can be carried around on a USB stick.
+    Portable,
+}
+
+impl FromStr for DirsStrategy {
+    type Err = NoSuchDirsStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let strategy = match s {
+            "native" => DirsStrategy::Native,
+            "xdg" => DirsStrategy::Xdg,
+            "portable" => DirsStrategy::Portable,
+            _ => return Err(NoSuchDirsStrategyError),
+        };
+
+        Ok(strategy)
+    }
+}
+
+/// `$THORC_DIRS` (`native`, `xdg` or `portable`), or [`DirsStrategy::Native`]
+/// if it is unset or invalid.
+pub fn default_dirs_strategy() -> DirsStrategy {
+    match std::env::var("THORC_DIRS") {
+        Ok(dirs) if !dirs.is_empty() => dirs.parse().unwrap_or_else(|_| {
+            tracing::warn!("Ignoring THORC_DIRS={:?}: no such dirs strategy", dirs);
+            DirsStrategy::Native
+        }),
+        _ => DirsStrategy::Native,
+    }
+}
+
+/// thorc's config and cache directories, as located by
+/// [`default_dirs_strategy`].
+struct ProjDirs {
+    config_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+fn proj_dirs() -> ProjDirs {
+    match default_dirs_strategy() {
+        DirsStrategy::Native => {
+            let dirs = ProjectDirs::from("", "", NAME).unwrap();
+            ProjDirs {
+                config_dir: dirs.config_dir().to_owned(),
+                cache_dir: dirs.cache_dir().to_owned(),
+            }
+        }
+        DirsStrategy::Xdg => {
+            let home = BaseDirs::new().unwrap().home_dir().to_owned();
+            // relative paths are invalid per the spec, and ignored
+            let xdg = |var: &str, default: &str| {
+                env_path(var)
+                    .filter(|it| it.is_absolute())
+                    .unwrap_or_else(|| home.join(default))
+                    .join(NAME)
+            };
+            ProjDirs {
+                config_dir: xdg("XDG_CONFIG_HOME", ".config"),
+                cache_dir: xdg("XDG_CACHE_HOME", ".cache"),
+            }
+        }
+        DirsStrategy::Portable => {
+            let exe = std::env::current_exe().expect("Cannot locate the running executable");
+            let dir = exe.parent().unwrap();
+            ProjDirs {
+                config_dir: dir.to_owned(),
+                cache_dir: dir.join(format!("{}-cache", NAME)),
+            }
+        }
+    }
+}
+
+/// The path in the environment variable `var`, unless it's unset or empty.
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `$THORC_CONFIG`, or `thorc.conf` in the config directory, see
+/// [`DirsStrategy`].
+pub fn default_config_file() -> PathBuf {
+    env_path("THORC_CONFIG").unwrap_or_else(|| proj_dirs().config_dir.join(CONFIG_FILE_NAME))
+}
+
+/// `$THORC_LOCAL_INDEX`, or `local_templates.toml` in the config directory.
+pub fn default_local_index_file() -> PathBuf {
+    env_path("THORC_LOCAL_INDEX")
+        .unwrap_or_else(|| proj_dirs().config_dir.join("local_templates.toml"))
+}
+
+/// `$THORC_CACHE_DIR`, or thorc's cache directory, see [`DirsStrategy`].
+pub fn default_cache_dir() -> PathBuf {
+    env_path("THORC_CACHE_DIR").unwrap_or_else(|| proj_dirs().cache_dir)
+}
+
+/// `$THORC_PROFILE`, if set; no profile otherwise.
+pub fn default_profile() -> Option<String> {
+    std::env::var("THORC_PROFILE")
+        .ok()
+        .filter(|it| !it.is_empty())
+}
+
+#[cfg(feature = "network")]
+fn default_downloader() -> Box<dyn Downloader> {
+    Box::new(ReqwestDownloader)
+}
+
+#[cfg(not(feature = "network"))]
+fn default_downloader() -> Box<dyn Downloader> {
+    Box::new(crate::http::OfflineDownloader)
+}
+
+/// Which index to look a template up in.
+#[derive(Debug, Clone)]
+pub enum IndexName {
+    Local,
+    Remote(String),
+}
+
+impl<'a> From<&'a str> for IndexName {
+    fn from(s: &'a str) -> Self {
+        match s {
+            "local" => IndexName::Local,
+            s => IndexName::Remote(s.to_string()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ThorcBuilder {
+    config_file: Option<PathBuf>,
+    local_index_file: Option<PathBuf>,
+    cache_dir: Option<PathBuf>,
+    profile: Option<String>,
+    project_dir: Option<PathBuf>,
+    #[cfg(feature = "network")]
+    http: Option<HttpSettings>,
+    downloader: Option<Box<dyn Downloader>>,
+    thorc_bin: Option<PathBuf>,
+    hook_executor: Option<Box<dyn HookExecutor>>,
+    observer: Option<Box<dyn Observer>>,
+    cancel: Option<CancelToken>,
+}
+
+impl ThorcBuilder {
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    pub fn local_index_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.local_index_file = Some(path.into());
+        self
+    }
+
+    pub fn cache_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(path.into());
+        self
+    }
+
+    /// Layers the config's profile called `name` over it, see
+    /// [`Config::apply_profile`]; [`default_profile`] if not given.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = Some(name.into());
+        self
+    }
+
+    /// Layers the nearest [`config::PROJECT_CONFIG_FILE_NAME`] in `dir` or
+    /// above it over the config, see [`Config::layer`]; none is looked for
+    /// otherwise.
+    pub fn project_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.project_dir = Some(dir.into());
+        self
+    }
+
+    /// Settings for all HTTP requests; these apply process-wide, see
+    /// [`http::configure`].
+    #[cfg(feature = "network")]
+    pub fn http(mut self, settings: HttpSettings) -> Self {
+        self.http = Some(settings);
+        self
+    }
+
+    /// Fetches indexes and templates with `downloader` instead of HTTP.
+    pub fn downloader(mut self, downloader: impl Downloader + 'static) -> Self {
+        self.downloader = Some(Box::new(downloader));
+        self
+    }
+
+    /// Binary hooks call back into as `$THORC`; the current executable by
+    /// default.
+    pub fn thorc_bin(mut self, path: impl Into<PathBuf>) -> Self {
+        self.thorc_bin = Some(path.into());
+        self
+    }
+
+    /// Runs hooks with `executor` instead of as child processes.
+    pub fn hook_executor(mut self, executor: impl HookExecutor + 'static) -> Self {
+        self.hook_executor = Some(Box::new(executor));
+        self
+    }
+
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Aborts downloads, extraction and copying once `cancel` is cancelled;
+    /// see [`Thorc::cancel_token`].
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// The config [`ThorcBuilder::build`] would use: the config file, with
+    /// the profile and then the project config layered over it. Its secrets
+    /// are still encrypted.
+    pub fn load_config(&self) -> Result<Config, Error> {
+        Ok(self.layered_config()?.0)
+    }
+
+    /// [`ThorcBuilder::load_config`], with the profile in effect and the
+    /// project config file layered over it, if any.
+    fn layered_config(&self) -> Result<(Config, Option<String>, Option<PathBuf>), Error> {
+        let config_file = self.config_file.clone().unwrap_or_else(default_config_file);
+        let mut config = if config_file.exists() {
+            Config::load(&config_file).map_err(|source| Error::ConfigFile {
+                path: config_file.clone(),
+                source,
+            })?
+        } else {
+            Config::default()
+        };
+        let profile = self.profile.clone().or_else(default_profile);
+        if let Some(profile) = &profile {
+            config.apply_profile(profile)?;
+            tracing::debug!(profile = %profile, "Using profile");
+        }
+        let project_config_file = self
+            .project_dir
+            .as_deref()
+            .and_then(config::find_project_config);
+        if let Some(path) = &project_config_file {
+            let project = Config::load_project(path).map_err(|source| Error::ConfigFile {
+                path: path.clone(),
+                source,
+            })?;
+            tracing::debug!(path = %path.display(), "Using project config");
+            config.layer(project);
+        }
+
+        Ok((config, profile, project_config_file))
+    }
+
+    /// Loads the config and the local index; either may not exist yet, in
+    /// which case it is empty.
+    pub fn build(self) -> Result<Thorc, Error> {
+        let (mut config, profile, project_config_file) = self.layered_config()?;
+        let config_file = self.config_file.unwrap_or_else(default_config_file);
+        let local_index_file = self
+            .local_index_file
+            .unwrap_or_else(default_local_index_file);
+        let cache_dir = self.cache_dir.unwrap_or_else(default_cache_dir);
+        let thorc_bin = match self.thorc_bin {
+            Some(thorc_bin) => thorc_bin,
+            None => std::env::current_exe().map_err(Error::NoThorcBin)?,
+        };
+
+        config.decrypt_secrets()?;
+        let local_index = if local_index_file.exists() {
+            TemplateIndex::load(&local_index_file).map_err(|source| Error::IndexFile {
+                path: local_index_file.clone(),
+                source,
+            })?
+        } else {
+            TemplateIndex::default()
+        };
+
+        #[cfg(feature = "network")]
+        if let Some(settings) = self.http {
+            http::configure(settings);
+        }
+
+        Ok(Thorc {
+            remote_indexes: config
+                .remote_indexes
+                .iter()
+                .map(|_| OnceCell::new())
+                .collect(),
+            config_file,
+            profile,
+            project_config_file,
+            local_index_file,
+            cache_dir,
+            thorc_bin,
+            config,
+            local_index,
+            downloader: self.downloader.unwrap_or_else(default_downloader),
+            hook_executor: self
+                .hook_executor
+                .unwrap_or_else(|| Box::new(ProcessExecutor)),
+            observer: self.observer.unwrap_or_else(|| Box::new(())),
+            cancel: self.cancel.unwrap_or_default(),
+        })
+    }
+}
+
+/// Everything needed to find templates and generate projects from them.
+/// Remote indexes are fetched the first time they are needed, then kept.
+pub struct Thorc {
+    config_file: PathBuf,
+    profile: Option<String>,
+    project_config_file: Option<PathBuf>,
+    local_index_file: PathBuf,
+    cache_dir: PathBuf,
+    thorc_bin: PathBuf,
+    config: Config,
+    local_index: TemplateIndex,
+    /// parallel to `config.remote_indexes`.
+    remote_indexes: Vec<OnceCell<TemplateIndex>>,
+    downloader: Box<dyn Downloader>,
+    hook_executor: Box<dyn HookExecutor>,
+    observer: Box<dyn Observer>,
+    cancel: CancelToken,
+}
+
+impl Thorc {
+    pub fn builder() -> ThorcBuilder {
+        ThorcBuilder::default()
+    }
+
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
+    }
+
+    /// The profile layered over the config, if one was selected.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// The project config layered over the config, if one was found.
+    pub fn project_config_file(&self) -> Option<&Path> {
+        self.project_config_file.as_deref()
+    }
+
+    pub fn local_index_file(&self) -> &Path {
+        &self.local_index_file
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// The config, with the profile and the project config layered over it.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn local_index(&self) -> &TemplateIndex {
+        &self.local_index
+    }
+
+    /// Cancelling this makes the current and all later downloads and
+    /// generations fail with [`Error::Cancelled`], cleaning up after
+    /// themselves.
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// Fetches the remote indexes whose names pass `filter`, in config order.
+    pub fn remote_indexes<F>(&self, filter: F) -> Result<Vec<(&str, &TemplateIndex)>, Error>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let selected = self
+            .config
+            .remote_indexes
+            .iter()
+            .zip(&self.remote_indexes)
+            .filter(|(remote_index, _)| filter(&remote_index.name))
+            .collect::<Vec<_>>();
+        let total = selected.len();
+
+        let indexes = selected
+            .into_iter()
+            .enumerate()
+            .map(|(i, (remote_index, cell))| {
+                let index = match cell.get() {
+                    Some(index) => index,
+                    None => {
+                        self.observer.phase(Phase::FetchingIndex {
+                            name: &remote_index.name,
+                        });
+                        self.observer.progress(i + 1, total);
+                        let index = remote_index
+                            .get_index_with(&self.cache_dir, &*self.downloader)
+                            .map_err(|source| Error::GetIndex {
+                                index: remote_index.name.clone(),
+                                source,
+                            })?;
+                        cell.get_or_init(|| index)
+                    }
+                };
+
+                Ok((remote_index.name.as_str(), index))
+            })
+            .collect();
+
+        self.observer.done();
+
+        indexes
+    }
+
+    /// Searches for `term` in the local index (named `local`) and the remote
+    /// ones; only in `indexes` if it isn't empty. Results are ranked.
+    pub fn find(&self, term: &str, indexes: &[String]) -> Result<FindResultComposite<'_>, Error> {
+        self.find_with(term, indexes, MatchMode::default())
+    }
+
+    /// Like [`Thorc::find`], matching and ranking according to `mode`.
+    pub fn find_with(
+        &self,
+        term: &str,
+        indexes: &[String],
+        mode: MatchMode,
+    ) -> Result<FindResultComposite<'_>, Error> {
+        let searched = |name: &str| indexes.is_empty() || indexes.iter().any(|it| it == name);
+
+        for name in indexes.iter() {
+            if name != "local" && !self.config.remote_indexes.iter().any(|it| &it.name == name) {
+                return Err(Error::InvalidIndex(name.clone()));
+            }
+        }
+
+        let mut result = if searched("local") {
+            self.local_index.find_with(term, mode).compose("<local>")
+        } else {
+            FindResultComposite::default()
+        };
+
+        for (remote_name, index) in self.remote_indexes(searched)? {
+            result.merge_ref(index.find_with(term, mode).compose(remote_name));
+        }
+
+        result.rank_with(term, mode);
+
+        Ok(result)
+    }
+
+    /// Looks up a template by its (possibly `index:`-qualified) name, in
+    /// `index` if given, otherwise in the config's default index, the local
+    /// index and then the remote ones (only the default index if the config
+    /// says so).
+    pub fn resolve(
+        &self,
+        index: Option<IndexName>,
+        template_name: &str,
+    ) -> Result<Template, Error> {
+        Ok(self.resolve_with_index(index, template_name)?.1)
+    }
+
+    /// Like [`Thorc::resolve`], with the name of the index the template is
+    /// in.
+    pub fn resolve_with_index(
+        &self,
+        index: Option<IndexName>,
+        template_name: &str,
+    ) -> Result<(String, Template), Error> {
+        self.observer.phase(Phase::Resolving {
+            template: template_name,
+        });
+        let found = self.resolve_inner(index, template_name, false);
+        self.observer.done();
+
+        Ok(found?.remove(0))
+    }
+
+    /// Like [`Thorc::resolve`], but returns every template by that name,
+    /// with the name of the index it's in, in the order `resolve` looks
+    /// for them. Fetches every remote index unless one is named.
+    pub fn resolve_all(
+        &self,
+        index: Option<IndexName>,
+        template_name: &str,
+    ) -> Result<Vec<(String, Template)>, Error> {
+        self.observer.phase(Phase::Resolving {
+            template: template_name,
+        });
+        let found = self.resolve_inner(index, template_name, true);
+        self.observer.done();
+
+        found
+    }
+
+    /// Never returns an empty `Vec`; only the first match unless `all`.
+    fn resolve_inner(
+        &self,
+        index: Option<IndexName>,
+        template_name: &str,
+        all: bool,
+    ) -> Result<Vec<(String, Template)>, Error> {
+        let qualified = QualifiedName::parse(template_name);
+        let (template_name, version) = (qualified.name, qualified.version);
+
+        let index = match (index, qualified.index) {
+            (Some(_), Some(qualifier)) => {
+                return Err(Error::IndexAlreadyNamed(qualifier.to_string()))
+            }
+            (index, None) => index,
+            (None, Some(qualifier)) => Some(IndexName::from(qualifier)),
+        };
+        let index = match (index, &self.config.default_index) {
+            (None, Some(default_index)) if self.config.default_index_only => {
+                Some(IndexName::from(default_index.as_str()))
+            }
+            (index, _) => index,
+        };
+
+        let find_in = |name: &str, index: &TemplateIndex| {
+            index
+                .find_version(template_name, version)
+                .map(|template| (name.to_string(), template.clone()))
+        };
+
+        let mut found = Vec::new();
+
+        match index {
+            Some(IndexName::Local) => found.extend(find_in("local", &self.local_index)),
+            Some(IndexName::Remote(name)) => {
+                if !self.config.remote_indexes.iter().any(|it| it.name == name) {
+                    return Err(Error::InvalidIndex(name));
+                }
+
+                let (name, index) = self.remote_indexes(|it| it == name)?.remove(0);
+                found.extend(find_in(name, index));
+            }
+            None => {
+                let preferred = match self.config.default_index.as_deref() {
+                    None | Some("local") => None,
+                    Some(name) => {
+                        if !self.config.remote_indexes.iter().any(|it| it.name == name) {
+                            return Err(Error::InvalidIndex(name.to_string()));
+                        }
+
+                        Some(name)
+                    }
+                };
+
+                if let Some(preferred) = preferred {
+                    let (name, index) = self.remote_indexes(|it| it == preferred)?.remove(0);
+                    found.extend(find_in(name, index));
+                }
+
+                if all || found.is_empty() {
+                    found.extend(find_in("local", &self.local_index));
+                }
+
+                if all || found.is_empty() {
+                    for (name, index) in self.remote_indexes(|it| Some(it) != preferred)? {
+                        found.extend(find_in(name, index));
+                        if !all && !found.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if found.is_empty() {
+            return Err(Error::UnknownTemplate(match version {
+                Some(version) => format!("{}@{}", template_name, version),
+                None => template_name.to_string(),
+            }));
+        }
+
+        Ok(found)
+    }
+
+    /// Downloads `template` into the cache, returning where it was extracted.
+    pub fn download(&self, template: &Template) -> Result<PathBuf, Error> {
+        let template_path = self.download_inner(template);
+        self.observer.done();
+
+        template_path
+    }
+
+    #[cfg(feature = "network")]
+    #[tracing::instrument(level = "debug", skip_all, fields(template = %template.versioned_name()))]
+    fn download_inner(&self, template: &Template) -> Result<PathBuf, Error> {
+        let download_error = |source| match source {
+            DownloadError::Cancelled => Error::Cancelled,
+            source => Error::Download {
+                template: template.name().to_string(),
+                source,
+            },
+        };
+
+        // the default branch and the commit are part of where the repo is
+        // cached
+        let mut resolved = template.clone();
+        if let Template::Repo { repo, .. } = &mut resolved {
+            *repo = repo
+                .resolve(&self.cache_dir, &*self.downloader)
+                .map_err(download_error)?
+                .into_owned();
+        }
+        let template = &resolved;
+        if let Template::Repo { repo, .. } = template {
+            if let Some(commit) = &repo.commit {
+                tracing::info!("Using {} at {}", repo.link(), commit);
+            }
+        }
+
+        if let Template::Repo { repo, .. } = template {
+            if repo.fetch.uses_git() {
+                self.observer.phase(Phase::Downloading {
+                    template: template.name(),
+                });
+                return repo
+                    .clone_with_git(&self.cache_dir, DEFAULT_REFRESH, &self.cancel)
+                    .map_err(download_error);
+            }
+        }
+
+        let (file, link) = match template.archive() {
+            Some(archive) => archive,
+            None => {
+                return template
+                    .download_with(&self.cache_dir, &*self.downloader)
+                    .map_err(download_error)
+            }
+        };
+
+        let fetch = || -> Result<Option<String>, Error> {
+            self.observer.phase(Phase::Downloading {
+                template: template.name(),
+            });
+            let downloaded = repo_def::fetch_archive(
+                &self.cache_dir,
+                &file,
+                &link,
+                &*self.downloader,
+                DEFAULT_REFRESH,
+                &self.cancel,
+            )
+            .map_err(download_error)?;
+
+            if let (Template::Repo { repo, .. }, Some(url)) = (
+                template,
+                downloaded.as_ref().and_then(|it| it.url.as_deref()),
+            ) {
+                if let Some(moved) = repo.moved_to(url) {
+                    tracing::warn!(
+                        "{} was redirected to {}: {}/{} is now {}/{}",
+                        link,
+                        url,
+                        repo.user,
+                        repo.repo,
+                        moved.user,
+                        moved.repo
+                    );
+                    self.observer.moved(template.name(), repo, &moved);
+                }
+            }
+
+            Ok(downloaded.map(|it| it.digest))
+        };
+        let extract = |digest: Option<String>| {
+            self.observer.phase(Phase::Extracting {
+                template: template.name(),
+            });
+            repo_def::extract_archive(&self.cache_dir, &file, digest.as_deref(), &self.cancel)
+        };
+
+        match extract(fetch()?) {
+            Err(err) => {
+                repo_def::discard_unextractable(&self.cache_dir, &file, err)
+                    .map_err(download_error)?;
+                extract(fetch()?).map_err(download_error)
+            }
+            Ok(extracted) => Ok(extracted),
+        }
+    }
+
+    /// Only local templates can be used without the network feature.
+    #[cfg(not(feature = "network"))]
+    fn download_inner(&self, template: &Template) -> Result<PathBuf, Error> {
+        match (template, template.archive()) {
+            (Template::Local { path, .. }, _) => Ok(path.clone()),
+            (_, archive) => Err(Error::Download {
+                template: template.name().to_string(),
+                source: crate::error::DownloadError::Offline(
+                    archive.map(|(_, link)| link).unwrap_or_default(),
+                ),
+            }),
+        }
+    }
+
+    /// Generates a project from `template` into `directory`, overwriting
+    /// existing files, runs its setup (as far as the config's hook policy
+    /// allows) and writes the lock file. `variables` are added to the
+    /// config's.
+    pub fn generate(
+        &self,
+        template: &Template,
+        directory: &Path,
+        project_name: &str,
+        variables: &BTreeMap<String, String>,
+        expected_checksum: Option<&str>,
+    ) -> Result<LockFile, Error> {
+        self.generate_from(
+            None,
+            template,
+            directory,
+            project_name,
+            variables,
+            expected_checksum,
+            None,
+        )
+    }
+
+    /// Like [`Thorc::generate`], but asks `on_conflict` what to do about
+    /// existing files, as [`utils::copy_with`] does.
+    pub fn generate_with<F>(
+        &self,
+        template: &Template,
+        directory: &Path,
+        project_name: &str,
+        variables: &BTreeMap<String, String>,
+        expected_checksum: Option<&str>,
+        mut on_conflict: F,
+    ) -> Result<LockFile, Error>
+    where
+        F: FnMut(&Path, &Path) -> io::Result<Conflict>,
+    {
+        self.generate_from(
+            None,
+            template,
+            directory,
+            project_name,
+            variables,
+            expected_checksum,
+            Some(&mut on_conflict),
+        )
+    }
+
+    /// Like [`Thorc::generate_with`], for a template from the index named
+    /// `index`, whose hook policy then applies. Without `on_conflict`,
+    /// existing files are overwritten like [`Thorc::generate`] does.
+    #[tracing::instrument(
+        skip_all,
+        fields(template = %template.versioned_name(), directory = %directory.display())
+    )]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn generate_from(
+        &self,
+        index: Option<&str>,
+        template: &Template,
+        directory: &Path,
+        project_name: &str,
+        variables: &BTreeMap<String, String>,
+        expected_checksum: Option<&str>,
+        on_conflict: Option<OnConflict<'_>>,
+    ) -> Result<LockFile, Error> {
+        template.check_thorc_version()?;
+
+        // the config's variables are defaults for the ones given
+        let mut variables = variables.clone();
+        for (name, value) in &self.config.variables {
+            variables
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+
+        let start = Instant::now();
+        let template_path = self.download(template)?;
+        tracing::debug!(
+            path = %template_path.display(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "Template ready"
+        );
+        template.check_contents(&template_path)?;
+
+        let checksum = utils::hash_dir(&template_path).map_err(Error::io(&template_path))?;
+        if let Some(expected_checksum) = expected_checksum {
+            if checksum != expected_checksum {
+                return Err(Error::ChecksumMismatch {
+                    name: template.name().to_string(),
+                    expected: expected_checksum.to_string(),
+                    actual: checksum,
+                });
+            }
+        }
+
+        let commit = template
+            .commit(&self.cache_dir)
+            .map_err(Error::io(&self.cache_dir))?;
+
+        self.observer.phase(Phase::Copying { directory });
+        let start = Instant::now();
+        let created = !directory.exists();
+        fs::create_dir_all(directory).map_err(Error::io(directory))?;
+        let skip_git = self.config.skip_git_dirs && matches!(template, Template::Local { .. });
+        // the lock file written below would replace the template's own
+        let lock_in_template = template_path.join(LOCK_FILE_NAME).exists();
+        if lock_in_template {
+            tracing::warn!(
+                "{} has a {} of its own, leaving it out",
+                template.name(),
+                LOCK_FILE_NAME
+            );
+        }
+        let copied = utils::copy_filtered(
+            &template_path,
+            directory,
+            on_conflict,
+            |path| {
+                let left_out = (skip_git && path.file_name() == Some(".git".as_ref()))
+                    || (lock_in_template && path == Path::new(LOCK_FILE_NAME));
+                !left_out
+            },
+            &self.cancel,
+        );
+        self.observer.done();
+        if self.cancel.is_cancelled() {
+            // only a directory we made is ours to remove; files already
+            // copied into an existing one are left for the user to sort out
+            if created {
+                let _ = fs::remove_dir_all(directory);
+            }
+            return Err(Error::Cancelled);
+        }
+        let copied = copied?;
+        tracing::debug!(
+            entries = copied,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "Copied"
+        );
+
+        match self.config.hook_policy(index) {
+            HookPolicy::Never => tracing::info!("Hooks are disabled, not running the setup"),
+            policy => {
+                let setup = hooks::finish_setup(
+                    &PolicyExecutor::new(&*self.hook_executor, policy),
+                    &self.thorc_bin,
+                    &*self.observer,
+                    template,
+                    directory,
+                    self.config.hooks_dir(template),
+                    project_name,
+                    &variables,
+                );
+                self.observer.done();
+                setup.map_err(|source| Error::Hook {
+                    template: template.name().to_string(),
+                    hook: hooks::SETUP_HOOK_NAME.to_string(),
+                    source,
+                })?;
+            }
+        }
+
+        // decrypted values stay out of the lock file, which is likely to be
+        // committed; regenerating gets them from the config again
+        variables.retain(|name, value| {
+            !self.config.secret_variables.contains(name)
+                || self.config.variables.get(name) != Some(value)
+        });
+
+        let lock = LockFile {
+            project_name: project_name.to_string(),
+            commit,
+            checksum,
+            variables,
+            template: template.clone(),
+        };
+
+        let lock_file = directory.join(LOCK_FILE_NAME);
+        lock.save(&lock_file).map_err(|source| Error::LockFile {
+            path: lock_file,
+            source,
+        })?;
+
+        Ok(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::Scratch;
+
+    #[test]
+    fn loads_config_with_profile_and_project_over_it() {
+        let scratch = Scratch::new("context-load-config");
+        scratch.write(
+            "config.toml",
+            r#"
+[defaults]
+user = "me"
+git_ref = "main"
+
+[profile.work.defaults]
+user = "work"
+"#,
+        );
+        scratch.write("project/.thorc.toml", "[defaults]\ngit_ref = \"dev\"\n");
+
+        let config = Thorc::builder()
+            .config_file(scratch.path().join("config.toml"))
+            .profile("work")
+            .project_dir(scratch.path().join("project"))
+            .load_config()
+            .unwrap();
+
+        assert_eq!(config.defaults.user.as_deref(), Some("work"));
+        assert_eq!(config.defaults.git_ref.as_deref(), Some("dev"));
+    }
+}
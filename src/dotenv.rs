@@ -0,0 +1,56 @@
+//! Line-based editing of dotenv (`.env`) files, keeping comments and the
+//! order of the existing keys.
+
+/// Splits `[export ]KEY=value` into the key and everything up to the value.
+fn key_of(line: &str) -> Option<(&str, &str)> {
+    if line.trim_start().starts_with('#') {
+        return None;
+    }
+
+    let eq = line.find('=')?;
+    let key = line[..eq].trim();
+    let key = key.strip_prefix("export ").map_or(key, str::trim_start);
+
+    Some((key, &line[..eq + 1]))
+}
+
+/// Quotes `value` if it wouldn't survive being read back unquoted.
+fn quote(value: &str) -> String {
+    if value.is_empty()
+        || value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@,+".contains(c))
+    {
+        return value.to_string();
+    }
+
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+/// Sets `key` to `value`, appending it at the end of the file if it isn't
+/// already there.
+pub fn set(doc: &str, key: &str, value: &str) -> String {
+    let value = quote(value);
+    let mut lines = doc.lines().map(str::to_string).collect::<Vec<_>>();
+
+    match lines
+        .iter()
+        .position(|line| key_of(line).is_some_and(|(k, _)| k == key))
+    {
+        Some(i) => {
+            let prefix = key_of(&lines[i]).unwrap().1.to_string();
+            lines[i] = format!("{}{}", prefix, value);
+        }
+        None => lines.push(format!("{}={}", key, value)),
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
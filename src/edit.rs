@@ -0,0 +1,640 @@
+//! Structured edits of TOML, JSON and YAML documents: the engine behind the
+//! edit commands and `apply-patches`, usable from hooks written in Rust too.
+
+use std::str::FromStr;
+
+use crate::{error::EditError, jsonc};
+
+/// An edit of the value at a path.
+#[derive(Debug, Clone)]
+pub enum Op<V> {
+    /// Replace the value; the last key may be new.
+    Set(V),
+    /// Remove the value; removing something that isn't there is not an error.
+    Delete,
+    /// Push onto the end of the array at the path.
+    Append(V),
+    /// Insert into the array at the path, before the given index.
+    Insert(usize, V),
+    /// Recursively merge a table / object into the one at the path.
+    Merge(V),
+}
+
+impl<V> Op<V> {
+    pub fn as_ref(&self) -> Op<&V> {
+        match self {
+            Op::Set(v) => Op::Set(v),
+            Op::Delete => Op::Delete,
+            Op::Append(v) => Op::Append(v),
+            Op::Insert(index, v) => Op::Insert(*index, v),
+            Op::Merge(v) => Op::Merge(v),
+        }
+    }
+
+    pub fn map<W>(self, f: impl FnOnce(V) -> W) -> Op<W> {
+        match self {
+            Op::Set(v) => Op::Set(f(v)),
+            Op::Delete => Op::Delete,
+            Op::Append(v) => Op::Append(f(v)),
+            Op::Insert(index, v) => Op::Insert(index, f(v)),
+            Op::Merge(v) => Op::Merge(f(v)),
+        }
+    }
+}
+
+/// How edits treat paths that don't exist yet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Create missing intermediate tables / objects (and the array, for
+    /// appends and inserts) instead of failing.
+    pub create_missing: bool,
+    /// Edit YAML even if it has comments, which are dropped, instead of
+    /// failing with [`EditError::DropsComments`].
+    pub drop_comments: bool,
+}
+
+impl<V> Op<V> {
+    /// How many segments of `path` have to exist already for the edit to
+    /// work without creating anything.
+    fn required_len(&self, path: &[&str]) -> usize {
+        match self {
+            Op::Set(_) | Op::Merge(_) => path.len().saturating_sub(1),
+            Op::Append(_) | Op::Insert(..) => path.len(),
+            Op::Delete => 0,
+        }
+    }
+
+    /// Fails with the first missing prefix of the path, unless missing keys
+    /// are to be created.
+    fn check_path<F>(&self, path: &[&str], options: Options, exists: F) -> Result<(), EditError>
+    where
+        F: Fn(&[&str]) -> Result<bool, EditError>,
+    {
+        if options.create_missing {
+            return Ok(());
+        }
+
+        for len in 1..=self.required_len(path) {
+            if !exists(&path[..len])? {
+                return Err(EditError::NotFound(path[..len].join("/")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses an object path: either `a/b/0`, or an RFC 6901 JSON Pointer
+/// (`/a/b/0`, with `~1` for `/` and `~0` for `~` inside keys). Only `/`
+/// separates segments, on every platform, so a `\` is part of a key; the
+/// edit commands and patch specs all parse paths with this.
+pub fn parse_path(s: &str) -> Result<Vec<String>, EditError> {
+    let pointer = match s.strip_prefix('/') {
+        Some(pointer) => pointer,
+        None => {
+            return Ok(s
+                .split('/')
+                .filter(|it| !it.is_empty() && *it != ".")
+                .map(str::to_string)
+                .collect())
+        }
+    };
+
+    pointer
+        .split('/')
+        .map(|segment| {
+            let mut out = String::new();
+            let mut chars = segment.chars();
+            while let Some(c) = chars.next() {
+                if c != '~' {
+                    out.push(c);
+                    continue;
+                }
+
+                match chars.next() {
+                    Some('0') => out.push('~'),
+                    Some('1') => out.push('/'),
+                    _ => return Err(EditError::InvalidPointer(s.to_string())),
+                }
+            }
+            Ok(out)
+        })
+        .collect()
+}
+
+/// Splits `.../<index>` into the path of the array and the index.
+pub fn split_index<S: AsRef<str>>(path: &[S]) -> Result<(&[S], usize), EditError> {
+    match path.split_last() {
+        Some((index, parent)) => usize::from_str(index.as_ref())
+            .map(|index| (parent, index))
+            .map_err(|_| EditError::ExpectedIndex),
+        None => Err(EditError::ExpectedIndex),
+    }
+}
+
+pub fn edit_toml(
+    doc: &str,
+    path: &[&str],
+    op: Op<toml_edit::Item>,
+    options: Options,
+) -> Result<String, EditError> {
+    let mut document = doc.parse::<toml_edit::Document>()?;
+    edit_toml_item(&mut document.root, path, op, options)?;
+
+    Ok(document.to_string())
+}
+
+pub fn edit_toml_item(
+    root: &mut toml_edit::Item,
+    path: &[&str],
+    op: Op<toml_edit::Item>,
+    options: Options,
+) -> Result<(), EditError> {
+    op.check_path(path, options, |prefix| {
+        let item = prefix
+            .iter()
+            .fold(&*root, |item, segment| toml_get(item, segment));
+        Ok(!item.is_none())
+    })?;
+
+    // missing top-level keys would otherwise become inline tables at the
+    // start of the document; make them `[sections]` instead
+    if let (Some(first), true) = (path.first(), path.len() > 1) {
+        if root[*first].is_none() && root.is_table() && !matches!(op, Op::Delete) {
+            root[*first] = toml_edit::table();
+        }
+    }
+
+    match op {
+        Op::Set(value) => *toml_at(root, path)? = value,
+        Op::Delete => delete_toml(root, path)?,
+        Op::Append(value) => insert_toml(toml_at(root, path)?, path, None, value)?,
+        Op::Insert(index, value) => insert_toml(toml_at(root, path)?, path, Some(index), value)?,
+        Op::Merge(value) => merge_toml(toml_at(root, path)?, path, value)?,
+    }
+
+    Ok(())
+}
+
+/// The item at `segment` in `item`, or `Item::None` if there is none; numbers
+/// are indexes into arrays and keys in tables.
+fn toml_get<'a>(item: &'a toml_edit::Item, segment: &str) -> &'a toml_edit::Item {
+    match usize::from_str(segment) {
+        Ok(int) if !item.is_table_like() => &item[int],
+        _ => &item[segment],
+    }
+}
+
+/// The item at `path`, adding missing keys like `IndexMut` does, but failing
+/// with the segment where it would panic: on an index out of bounds, or when
+/// looking into a value that is neither a table nor an array.
+fn toml_at<'a>(
+    item: &'a mut toml_edit::Item,
+    path: &[&str],
+) -> Result<&'a mut toml_edit::Item, EditError> {
+    let (segment, rest) = match path.split_first() {
+        Some(it) => it,
+        None => return Ok(item),
+    };
+
+    let child = if item.is_none() || item.is_table_like() {
+        &mut item[*segment]
+    } else if item.is_array() || item.is_array_of_tables() {
+        let index = usize::from_str(segment)
+            .ok()
+            .filter(|it| !item[*it].is_none())
+            .ok_or_else(|| EditError::NotFound(segment.to_string()))?;
+        &mut item[index]
+    } else {
+        return Err(EditError::NotAContainer(segment.to_string()));
+    };
+
+    toml_at(child, rest)
+}
+
+fn delete_toml(item: &mut toml_edit::Item, path: &[&str]) -> Result<(), EditError> {
+    let (last, parent) = path.split_last().ok_or(EditError::DeleteRoot)?;
+
+    // nothing to delete, and walking there would add the missing keys
+    let exists = parent
+        .iter()
+        .try_fold(&*item, |item, segment| {
+            Some(toml_get(item, segment)).filter(|it| !it.is_none())
+        })
+        .is_some();
+    if !exists {
+        return Ok(());
+    }
+    let parent = toml_at(item, parent)?;
+
+    if let Ok(int) = usize::from_str(last) {
+        if let Some(array) = parent.as_array_mut() {
+            if int < array.len() {
+                array.remove(int);
+            }
+            return Ok(());
+        }
+        if let Some(array) = parent.as_array_of_tables_mut() {
+            if int < array.len() {
+                array.remove(int);
+            }
+            return Ok(());
+        }
+    }
+
+    if let Some(table) = parent.as_table_like_mut() {
+        table.remove(last);
+    }
+
+    Ok(())
+}
+
+fn insert_toml(
+    array: &mut toml_edit::Item,
+    path: &[&str],
+    index: Option<usize>,
+    new_value: toml_edit::Item,
+) -> Result<(), EditError> {
+    if array.is_none() {
+        *array = toml_edit::value(toml_edit::Array::new());
+    }
+
+    if let Some(tables) = array.as_array_of_tables_mut() {
+        let mut table = new_value
+            .into_table()
+            .map_err(|_| EditError::ExpectedTable(path.join("/")))?;
+
+        let index = index.unwrap_or(tables.len());
+        if index > tables.len() {
+            return Err(EditError::IndexOutOfBounds(index));
+        }
+
+        // tables are written out in document position order, so take the
+        // position and spacing of the element it goes in front of / after
+        let neighbour = tables
+            .get(index)
+            .or_else(|| tables.get(index.wrapping_sub(1)));
+        if let Some(neighbour) = neighbour {
+            *table.decor_mut() = neighbour.decor().clone();
+            if let Some(position) = neighbour.position() {
+                table.set_position(position);
+            }
+        }
+
+        // there's no insert for arrays of tables, so rebuild the array
+        let mut rest = tables.iter().skip(index).cloned().collect::<Vec<_>>();
+        while tables.len() > index {
+            tables.remove(index);
+        }
+        rest.insert(0, table);
+        for table in rest {
+            tables.push(table);
+        }
+
+        return Ok(());
+    }
+
+    let array = array
+        .as_array_mut()
+        .ok_or_else(|| EditError::NotAnArray(path.join("/")))?;
+    let mut new_value = new_value
+        .into_value()
+        .map_err(|_| EditError::MissingValue)?;
+
+    let index = index.unwrap_or(array.len());
+    if index > array.len() {
+        return Err(EditError::IndexOutOfBounds(index));
+    }
+
+    // keep the array's layout by copying the spacing of the element the new
+    // one displaces (or of the last one, when appending)
+    let multi_line = |value: &toml_edit::Value| {
+        value
+            .decor()
+            .prefix()
+            .is_some_and(|prefix| prefix.contains('\n'))
+    };
+    let neighbour = match index {
+        _ if index < array.len() => array.get(index),
+        _ if array.len() > 1 => array.get(index - 1),
+        _ => array.get(index.wrapping_sub(1)).filter(|it| multi_line(it)),
+    };
+    match neighbour {
+        Some(neighbour) => {
+            *new_value.decor_mut() = neighbour.decor().clone();
+            array.insert_formatted(index, new_value);
+
+            // the old first element is no longer first: `[a, b]` -> `[z, a, b]`
+            if index == 0 {
+                let decor = array
+                    .get(2)
+                    .map(|it| it.decor().clone())
+                    .unwrap_or_else(|| toml_edit::Decor::new(" ", ""));
+                *array.get_mut(1).unwrap().decor_mut() = decor;
+            }
+        }
+        None => array.insert(index, new_value),
+    }
+
+    Ok(())
+}
+
+fn merge_toml(
+    target: &mut toml_edit::Item,
+    path: &[&str],
+    value: toml_edit::Item,
+) -> Result<(), EditError> {
+    let entries: Vec<(String, toml_edit::Item)> = match &value {
+        toml_edit::Item::Table(table) => table
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect(),
+        toml_edit::Item::Value(toml_edit::Value::InlineTable(table)) => table
+            .iter()
+            .map(|(k, v)| {
+                let v = v.clone().decorated(" ", "");
+                (k.to_string(), toml_edit::Item::Value(v))
+            })
+            .collect(),
+        _ => return Err(EditError::ExpectedTable(path.join("/"))),
+    };
+
+    let table = match target.as_table_like_mut() {
+        Some(table) => table,
+        None => {
+            *target = value;
+            return Ok(());
+        }
+    };
+
+    for (key, value) in entries {
+        if value.is_table_like() && table.get(&key).is_some_and(|it| it.is_table_like()) {
+            merge_toml(table.get_mut(&key).unwrap(), path, value)?;
+        } else {
+            table.insert(&key, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Edits JSON (or JSON with comments) in place, so key order, formatting and
+/// comments outside the edited value are kept. Values are JSON text.
+pub fn edit_json(
+    doc: &str,
+    path: &[&str],
+    op: Op<&str>,
+    options: Options,
+) -> Result<String, EditError> {
+    op.check_path(path, options, |prefix| {
+        Ok(jsonc::get(doc, prefix)?.is_some())
+    })?;
+
+    let created;
+    let doc = match op {
+        Op::Append(_) | Op::Insert(..) if jsonc::get(doc, path)?.is_none() => {
+            created = jsonc::set(doc, path, "[]")?;
+            &created
+        }
+        _ => doc,
+    };
+
+    let doc = match op {
+        Op::Set(value) => jsonc::set(doc, path, value)?,
+        Op::Delete => jsonc::delete(doc, path)?,
+        Op::Append(value) => jsonc::insert(doc, path, None, value)?,
+        Op::Insert(index, value) => jsonc::insert(doc, path, Some(index), value)?,
+        Op::Merge(value) => merge_json(doc.to_string(), path, &serde_json::from_str(value)?)?,
+    };
+
+    Ok(doc)
+}
+
+fn merge_json(doc: String, path: &[&str], value: &serde_json::Value) -> Result<String, EditError> {
+    let object = value
+        .as_object()
+        .ok_or_else(|| EditError::ExpectedTable(path.join("/")))?;
+
+    if !jsonc::get(&doc, path)?.is_some_and(|it| it.starts_with('{')) {
+        return Ok(jsonc::set(&doc, path, &value.to_string())?);
+    }
+
+    let mut doc = doc;
+    for (key, value) in object {
+        let mut child = path.to_vec();
+        child.push(key);
+
+        doc = if value.is_object() {
+            merge_json(doc, &child, value)?
+        } else {
+            jsonc::set(&doc, &child, &value.to_string())?
+        };
+    }
+
+    Ok(doc)
+}
+
+/// Edits YAML by rewriting the whole document, which normalizes its
+/// formatting and drops its comments; so documents with comments are only
+/// edited with [`Options::drop_comments`].
+pub fn edit_yaml(
+    doc: &str,
+    path: &[&str],
+    op: Op<serde_yaml::Value>,
+    options: Options,
+) -> Result<String, EditError> {
+    let mut document = serde_yaml::from_str::<serde_yaml::Value>(doc)?;
+
+    if has_yaml_comments(doc) {
+        if !options.drop_comments {
+            return Err(EditError::DropsComments);
+        }
+        tracing::warn!("Dropping the comments of the YAML being edited");
+    }
+
+    op.check_path(path, options, |prefix| {
+        let value =
+            prefix
+                .iter()
+                .try_fold(&document, |value, segment| match usize::from_str(segment) {
+                    Ok(int) if value.is_sequence() => value.get(int),
+                    _ => value.get(*segment),
+                });
+        Ok(value.is_some())
+    })?;
+
+    match op {
+        Op::Set(value) => *yaml_at(&mut document, path)? = value,
+        Op::Delete => delete_yaml(&mut document, path)?,
+        Op::Append(value) => insert_yaml(yaml_at(&mut document, path)?, path, None, value)?,
+        Op::Insert(index, value) => {
+            insert_yaml(yaml_at(&mut document, path)?, path, Some(index), value)?
+        }
+        Op::Merge(value) => merge_yaml(yaml_at(&mut document, path)?, path, value)?,
+    }
+
+    Ok(serde_yaml::to_string(&document)?)
+}
+
+/// Whether `doc` has a `#` comment outside of quoted strings. Errs on the
+/// side of yes, e.g. for a `#` in a block scalar.
+fn has_yaml_comments(doc: &str) -> bool {
+    doc.lines().any(|line| {
+        let mut quote = None;
+        let mut prev = ' ';
+        for c in line.chars() {
+            match (quote, c) {
+                (None, '#') if prev.is_whitespace() => return true,
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                _ => {}
+            }
+            prev = c;
+        }
+        false
+    })
+}
+
+fn yaml_at<'a>(
+    value: &'a mut serde_yaml::Value,
+    path: &[&str],
+) -> Result<&'a mut serde_yaml::Value, EditError> {
+    let (segment, rest) = match path.split_first() {
+        Some(it) => it,
+        None => return Ok(value),
+    };
+
+    if value.is_null() {
+        *value = serde_yaml::Value::Mapping(Default::default());
+    }
+
+    let child = match value {
+        serde_yaml::Value::Sequence(sequence) => usize::from_str(segment)
+            .ok()
+            .and_then(|i| sequence.get_mut(i))
+            .ok_or_else(|| EditError::NotFound(segment.to_string()))?,
+        serde_yaml::Value::Mapping(mapping) => mapping
+            .entry(serde_yaml::Value::String(segment.to_string()))
+            .or_insert(serde_yaml::Value::Null),
+        _ => return Err(EditError::NotAContainer(segment.to_string())),
+    };
+
+    yaml_at(child, rest)
+}
+
+fn delete_yaml(value: &mut serde_yaml::Value, path: &[&str]) -> Result<(), EditError> {
+    let (last, parent_path) = path.split_last().ok_or(EditError::DeleteRoot)?;
+
+    let mut parent = Some(value);
+    for segment in parent_path {
+        parent = parent.and_then(|it| match usize::from_str(segment) {
+            Ok(int) if it.is_sequence() => it.get_mut(int),
+            _ => it.get_mut(*segment),
+        });
+    }
+
+    match parent {
+        Some(serde_yaml::Value::Sequence(sequence)) => {
+            if let Some(int) = usize::from_str(last).ok().filter(|it| *it < sequence.len()) {
+                sequence.remove(int);
+            }
+        }
+        Some(serde_yaml::Value::Mapping(mapping)) => {
+            mapping.remove(*last);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn insert_yaml(
+    array: &mut serde_yaml::Value,
+    path: &[&str],
+    index: Option<usize>,
+    new_value: serde_yaml::Value,
+) -> Result<(), EditError> {
+    if array.is_null() {
+        *array = serde_yaml::Value::Sequence(Default::default());
+    }
+
+    let array = array
+        .as_sequence_mut()
+        .ok_or_else(|| EditError::NotAnArray(path.join("/")))?;
+
+    let index = index.unwrap_or(array.len());
+    if index > array.len() {
+        return Err(EditError::IndexOutOfBounds(index));
+    }
+    array.insert(index, new_value);
+
+    Ok(())
+}
+
+fn merge_yaml(
+    target: &mut serde_yaml::Value,
+    path: &[&str],
+    value: serde_yaml::Value,
+) -> Result<(), EditError> {
+    let entries = match value {
+        serde_yaml::Value::Mapping(entries) => entries,
+        _ => return Err(EditError::ExpectedTable(path.join("/"))),
+    };
+
+    let mapping = match target {
+        serde_yaml::Value::Mapping(mapping) => mapping,
+        _ => {
+            *target = serde_yaml::Value::Mapping(entries);
+            return Ok(());
+        }
+    };
+
+    for (key, value) in entries {
+        match mapping.get_mut(&key) {
+            Some(existing) if existing.is_mapping() && value.is_mapping() => {
+                merge_yaml(existing, path, value)?
+            }
+            _ => {
+                mapping.insert(key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMENTED: &str = "# pinned by ops\nimage: app:1\n";
+
+    #[test]
+    fn refuses_to_drop_yaml_comments() {
+        let value = serde_yaml::Value::from("app:2");
+        let edited = edit_yaml(COMMENTED, &["image"], Op::Set(value), Options::default());
+
+        assert!(matches!(edited, Err(EditError::DropsComments)));
+    }
+
+    #[test]
+    fn drops_yaml_comments_when_allowed() {
+        let value = serde_yaml::Value::from("app:2");
+        let options = Options {
+            drop_comments: true,
+            ..Options::default()
+        };
+
+        let edited = edit_yaml(COMMENTED, &["image"], Op::Set(value), options).unwrap();
+        assert_eq!(edited, "image: app:2\n");
+    }
+
+    #[test]
+    fn edits_yaml_without_comments() {
+        let value = serde_yaml::Value::from("app:2");
+        let doc = "image: 'app:1 # not a comment'\n";
+
+        let edited = edit_yaml(doc, &["image"], Op::Set(value), Options::default()).unwrap();
+        assert_eq!(edited, "image: app:2\n");
+    }
+}
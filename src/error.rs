@@ -1,11 +1,92 @@
-use std::io;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use crate::patches::Format;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DownloadError {
+    #[cfg(feature = "network")]
     #[error("reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
     #[error("io error: {0}")]
     Io(#[from] io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// 401 or 403.
+    #[error("access denied ({status}): {url}")]
+    Denied { url: String, status: u16 },
+    /// any other unsuccessful status.
+    #[error("server responded {status}: {url}")]
+    Status { url: String, status: u16 },
+    /// the request couldn't be sent or the response couldn't be read.
+    #[cfg(feature = "network")]
+    #[error("cannot reach {url}: {source}")]
+    Network { url: String, source: reqwest::Error },
+    #[error("cannot fetch {0} while offline")]
+    Offline(String),
+    /// fetching with `git` failed; `message` is what it said.
+    #[error("git cannot fetch {url}: {message}")]
+    Git { url: String, message: String },
+    #[error("cancelled")]
+    Cancelled,
+}
+
+impl DownloadError {
+    /// For `map_err`, attaching `url` to a failed request.
+    #[cfg(feature = "network")]
+    pub(crate) fn network(url: &str) -> impl FnOnce(reqwest::Error) -> DownloadError + '_ {
+        move |source| DownloadError::Network {
+            url: url.to_owned(),
+            source,
+        }
+    }
+
+    /// The url that couldn't be fetched, if known.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            DownloadError::NotFound(url)
+            | DownloadError::Offline(url)
+            | DownloadError::Denied { url, .. }
+            | DownloadError::Status { url, .. }
+            | DownloadError::Git { url, .. } => Some(url),
+            #[cfg(feature = "network")]
+            DownloadError::Network { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+
+    /// What the user can likely do about the error, if anything.
+    pub fn hint(&self) -> Option<&'static str> {
+        let hint = match self {
+            DownloadError::NotFound(_) => {
+                "check the url, or that the repository exists and its git_ref names a branch, \
+                 tag or commit in it"
+            }
+            DownloadError::Denied { .. } => {
+                "the repository may be private; thorc sends no credentials, so use a url with \
+                 an access token in it"
+            }
+            DownloadError::Git { .. } => {
+                "check that git is installed and can reach the repository (over SSH, with a key \
+                 the agent or ~/.ssh has), and that its git_ref names a branch, tag or commit in it"
+            }
+            DownloadError::Status { status, .. } if *status >= 500 => {
+                "the server is having trouble; try again later"
+            }
+            #[cfg(feature = "network")]
+            DownloadError::Network { .. } => {
+                "check the network connection, and the proxy if one is configured"
+            }
+            _ => return None,
+        };
+
+        Some(hint)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -16,15 +97,505 @@ pub enum GetIndexError {
     Io(#[from] io::Error),
     #[error("deserialization error: {0}")]
     DeserializeError(#[from] toml::de::Error),
+    #[error("{0}")]
+    Schema(#[from] SchemaError),
+    #[error("index includes itself: {0}")]
+    IncludeCycle(String),
+    #[error("{0} is a local template, which indexes for remote use can't list")]
+    LocalTemplate(String),
+    #[error(
+        "{0} is included too deeply, at most {} levels are followed",
+        crate::remote_index::MAX_INCLUDE_DEPTH
+    )]
+    IncludeTooDeep(String),
+    #[error("index path {} leads outside of its repository", .0.display())]
+    PathEscapes(PathBuf),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("deserialization error: {0}")]
+    DeserializeError(#[from] TomlError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("schema_version must be a non-negative integer")]
+    InvalidVersion,
+    #[error("schema version {found} is newer than the supported {supported}; update thorc")]
+    TooNew { found: u32, supported: u32 },
+}
+
+/// A TOML error, followed by the line it is on with a caret under the spot
+/// when toml knows where that is.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}{snippet}")]
+pub struct TomlError {
+    pub source: toml::de::Error,
+    message: String,
+    line_col: Option<(usize, usize)>,
+    snippet: String,
+}
+
+impl TomlError {
+    /// `source`, from parsing `contents`.
+    pub(crate) fn new(source: toml::de::Error, contents: &str) -> Self {
+        let mut message = source.to_string();
+        let line_col = source.line_col().map(|(line, col)| {
+            // toml's own position goes at the end, replaced by the refined one
+            if let Some(at) = message.rfind(" at line ") {
+                message.truncate(at);
+            }
+
+            key_position(&message, contents, line).unwrap_or((line, col))
+        });
+        if let Some((line, col)) = line_col {
+            message = format!("{} at line {} column {}", message, line + 1, col + 1);
+        }
+
+        let line = line_col.and_then(|(line, col)| Some((line, col, contents.lines().nth(line)?)));
+        let snippet = match line {
+            Some((line, col, text)) => {
+                let number = (line + 1).to_string();
+                let margin = " ".repeat(number.len());
+                // toml counts bytes, the terminal chars
+                let col = text.get(..col).map_or(col, |it| it.chars().count());
+
+                format!(
+                    "\n{margin} |\n{number} | {text}\n{margin} | {caret:>width$}",
+                    margin = margin,
+                    number = number,
+                    text = text,
+                    caret = "^",
+                    width = col + 1,
+                )
+            }
+            None => String::new(),
+        };
+
+        Self {
+            source,
+            message,
+            line_col,
+            snippet,
+        }
+    }
+
+    /// Zero-based line and column of the error, if known.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        self.line_col
+    }
+}
+
+/// toml puts errors about a key at the start of the table it is in; this
+/// finds the key itself, from `line` on: the second definition of a
+/// duplicate key, or the one with the value an error is about.
+fn key_position(message: &str, contents: &str, line: usize) -> Option<(usize, usize)> {
+    let duplicate = message
+        .strip_prefix("duplicate key: `")
+        .or_else(|| message.strip_prefix("duplicate field `"));
+    let (key, nth) = match duplicate {
+        Some(rest) => (rest.split('`').next()?, 1),
+        // toml knows where values are, just not always
+        None if !contents.lines().nth(line)?.trim_start().starts_with('[') => return None,
+        None => {
+            let (_, rest) = message.split_once("for key `")?;
+            (rest.split('`').next()?.rsplit('.').next()?, 0)
+        }
+    };
+
+    contents
+        .lines()
+        .enumerate()
+        .skip(line)
+        // up to the next table
+        .take_while(|(i, text)| *i == line || !text.trim_start().starts_with('['))
+        .filter_map(|(i, text)| {
+            let trimmed = text.trim_start();
+            let rest = trimmed.strip_prefix(key)?;
+            rest.trim_start()
+                .starts_with('=')
+                .then(|| (i, text.len() - trimmed.len()))
+        })
+        .nth(nth)
 }
 
 #[derive(thiserror::Error, Debug)]
 #[error("no such git provider")]
 pub struct NoSuchGitProviderError;
 
+#[derive(thiserror::Error, Debug)]
+#[error("git ref {0:?} starts with '-', which git would take for an option")]
+pub struct InvalidGitRefError(pub String);
+
+#[derive(thiserror::Error, Debug)]
+#[error("no such merge strategy")]
+pub struct NoSuchMergeStrategyError;
+
+#[derive(thiserror::Error, Debug)]
+#[error("no such dirs strategy")]
+pub struct NoSuchDirsStrategyError;
+
+/// A failure copying a directory, see [`crate::utils::copy`].
+#[derive(thiserror::Error, Debug)]
+pub enum CopyError {
+    #[error("cannot copy {} to {}: {source}", src.display(), dest.display())]
+    Io {
+        src: PathBuf,
+        dest: PathBuf,
+        source: io::Error,
+    },
+    /// `path` has `..`, root or prefix components, and would end up outside
+    /// of `root`, see [`crate::utils::join_within`]; or it is a symlink that
+    /// leads outside of `root`, or nowhere.
+    #[error("{} leads outside of {}", path.display(), root.display())]
+    Escapes { path: PathBuf, root: PathBuf },
+}
+
+impl CopyError {
+    /// For `map_err`, failing to copy `src` to `dest`.
+    pub(crate) fn io(src: PathBuf, dest: PathBuf) -> impl Fn(io::Error) -> Self {
+        move |source| Self::Io {
+            src: src.clone(),
+            dest: dest.clone(),
+            source,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("no such profile: {0}")]
+pub struct NoSuchProfileError(pub String);
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid refresh {0:?}: expected \"always\", \"never\" or a duration like \"1h\"")]
+pub struct InvalidRefreshError(pub String);
+
+#[derive(Debug, thiserror::Error)]
+#[error("{template} is in both indexes, but differs")]
+pub struct MergeConflictError {
+    pub template: String,
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum CheckTemplateNameError {
-    #[error("invalid character {c:?} at {index}")]
-    InvalidCharacter { c: char, index: usize },
+    #[error("invalid character {c:?} at {index} in {name:?}")]
+    InvalidCharacter { name: String, c: char, index: usize },
+    #[error("{name:?} is longer than {max} characters")]
+    TooLong { name: String, max: usize },
+    #[error("{0:?} starts with `-`, which would be taken for an option")]
+    LeadingDash(String),
+    #[error("{0:?} is reserved")]
+    Reserved(String),
+    #[error("invalid version: {0}")]
+    InvalidVersion(String),
+}
+
+/// A downloaded template that can't make a working project, see
+/// [`crate::template::Template::check_contents`].
+#[derive(Debug, thiserror::Error)]
+pub enum BrokenTemplateError {
+    #[error("{template} has no files in it")]
+    Empty { template: String },
+    #[error("{template} has no {manifest}, which its {setup} setup needs")]
+    MissingManifest {
+        template: String,
+        setup: &'static str,
+        manifest: &'static str,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{template} needs thorc {required} or newer, this is {current}")]
+pub struct ThorcVersionError {
+    pub template: String,
+    pub required: semver::Version,
+    pub current: semver::Version,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockFileError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("deserialization error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+    #[error("serialization error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigFileError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("serialization error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    #[error("{0}")]
+    Schema(#[from] SchemaError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IndexFileError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("deserialization error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+    #[error("serialization error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("{0}")]
+    Schema(#[from] SchemaError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ComposeFileError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("deserialization error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EditError {
+    #[error("toml error: {0}")]
+    Toml(#[from] toml_edit::TomlError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("{0}")]
+    Jsonc(#[from] JsoncError),
+    #[error("no such key or index: {0}")]
+    NotFound(String),
+    #[error("cannot look up {0} in a value that is not an object or array")]
+    NotAContainer(String),
+    #[error("{0} is not an array")]
+    NotAnArray(String),
+    #[error("expected a table / object for {0}")]
+    ExpectedTable(String),
+    #[error("index {0} out of bounds")]
+    IndexOutOfBounds(usize),
+    #[error("missing value")]
+    MissingValue,
+    #[error("cannot delete the whole document")]
+    DeleteRoot,
+    #[error("expected an array index as the last path segment")]
+    ExpectedIndex,
+    #[error("invalid escape in JSON Pointer: {0}")]
+    InvalidPointer(String),
+    #[error("cannot tell the format of {0}, set `format`")]
+    UnknownFormat(String),
+    #[error("only setting values is supported for {0:?} files")]
+    Unsupported(Format),
+    #[error(
+        "editing the YAML would drop its comments; allow that with --drop-comments or \
+         `drop_comments = true`"
+    )]
+    DropsComments,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PatchSpecError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("deserialization error: {0}")]
+    DeserializeError(#[from] toml::de::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApplyPatchesError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("cannot patch {}: it is outside of the target directory", .0.display())]
+    Escapes(PathBuf),
+    #[error("cannot apply patch to {}: {source}", file.display())]
+    Patch { file: PathBuf, source: EditError },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenameError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{} already exists", .0.display())]
+    Collision(std::path::PathBuf),
+    #[error("invalid file name: {0}")]
+    InvalidName(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenderError {
+    #[error("undefined variable: {0}")]
+    UndefinedVariable(String),
+}
+
+/// Everything [`Thorc`](crate::Thorc) and [`generate`](crate::generate) can
+/// fail with, naming the template, index or file involved.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("cannot find the thorc binary: {0}")]
+    NoThorcBin(#[source] io::Error),
+    #[error("cannot load config file {}: {source}", path.display())]
+    ConfigFile {
+        path: PathBuf,
+        source: ConfigFileError,
+    },
+    #[error("{0}")]
+    Profile(#[from] NoSuchProfileError),
+    #[error("cannot decrypt config: {0}")]
+    Secret(#[from] SecretError),
+    #[error("{0}")]
+    Copy(#[from] CopyError),
+    #[error("cannot load index file {}: {source}", path.display())]
+    IndexFile {
+        path: PathBuf,
+        source: IndexFileError,
+    },
+    #[error("cannot write lock file {}: {source}", path.display())]
+    LockFile {
+        path: PathBuf,
+        source: LockFileError,
+    },
+    #[error("cannot get index {index}: {source}")]
+    GetIndex {
+        index: String,
+        source: GetIndexError,
+    },
+    #[error("cannot download {template}: {source}")]
+    Download {
+        template: String,
+        source: DownloadError,
+    },
+    #[error("{hook} hook of {template} failed: {source}")]
+    Hook {
+        template: String,
+        hook: String,
+        source: RunHookError,
+    },
+    #[error("invalid index: {0}")]
+    InvalidIndex(String),
+    #[error("{0} already names an index; don't pass one as well")]
+    IndexAlreadyNamed(String),
+    #[error("unknown template: {0}")]
+    UnknownTemplate(String),
+    #[error("{template} is in several indexes ({}); name one", .indexes.join(", "))]
+    AmbiguousTemplate {
+        template: String,
+        indexes: Vec<String>,
+    },
+    #[error("checksum mismatch for {name}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("{0}")]
+    ThorcVersion(#[from] ThorcVersionError),
+    #[error("{0}")]
+    BrokenTemplate(#[from] BrokenTemplateError),
+    #[error("invalid name: {0}")]
+    InvalidName(#[from] CheckTemplateNameError),
+    #[error("{} already exists and is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    #[error("{} already exists and is not empty", .0.display())]
+    NotEmpty(PathBuf),
+    #[error(
+        "cannot tell the project name from {}, which has no UTF-8 name; pass one",
+        .0.display()
+    )]
+    NoProjectName(PathBuf),
+    #[error(
+        "{template} is deprecated{}",
+        .superseded_by.as_ref().map(|it| format!("; use {} instead", it)).unwrap_or_default()
+    )]
+    Deprecated {
+        template: String,
+        superseded_by: Option<String>,
+    },
+    #[error("cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    /// For `map_err`, attaching `path` to an IO error.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("the config has encrypted values, but no secrets.identity to decrypt them with")]
+    NoIdentity,
+    #[error("cannot run {program}: {source}")]
+    Spawn { program: String, source: io::Error },
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("decryption failed: {0}")]
+    Failed(String),
+    #[error("decrypted value is not UTF-8")]
+    NotUtf8,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScanError {
+    #[error("{}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("invalid manifest {}: {source}", .path.display())]
+    Manifest {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid template name for {}: {source}", .path.display())]
+    InvalidName {
+        path: PathBuf,
+        source: CheckTemplateNameError,
+    },
+    #[error("several templates are called {0}")]
+    DuplicateName(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunHookError {
+    #[error("IO error: {0}")]
+    IO(#[from] io::Error),
+    #[error("status not success: {0}")]
+    StatusNotSuccess(ExitStatus),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServeError {
+    #[error("cannot bind: {0}")]
+    Bind(String),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("download error: {0}")]
+    Download(#[from] DownloadError),
+    #[error("serialization error: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JsoncError {
+    #[error("syntax error at byte {offset}: {message}")]
+    Syntax {
+        offset: usize,
+        message: &'static str,
+    },
+    #[error("no such key or index: {0}")]
+    NotFound(String),
+    #[error("cannot look up {0} in a value that is not an object or array")]
+    NotAContainer(String),
+    #[error("{0} is not an array")]
+    NotAnArray(String),
+    #[error("cannot delete the whole document")]
+    DeleteRoot,
 }
@@ -0,0 +1,321 @@
+//! A minimal C ABI, for editor plugins and other hosts that would rather
+//! embed thorc than run the binary and parse its output.
+//!
+//! Build it as a shared library with
+//! `cargo rustc --lib --features ffi --crate-type cdylib`. Strings going
+//! in and out are NUL-terminated UTF-8; strings thorc returns are freed with
+//! [`thorc_string_free`]. Functions that can fail return `0` on success and
+//! `-1` on failure (or a null pointer), with the reason available from
+//! [`thorc_last_error`].
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::{CStr, CString},
+    io,
+    os::raw::{c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::{
+    generate::{self, GenerateOptions},
+    observer::{Observer, Phase},
+    utils::Conflict,
+    Thorc,
+};
+
+/// Reports what a [`Thorc`] is doing, see [`Observer`]; every callback may
+/// be null, and gets `user_data` back as its first argument.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ThorcCallbacks {
+    pub user_data: *mut c_void,
+    /// a new phase, and what it is about: `resolving` (a template name),
+    /// `fetching-index` (an index name), `downloading` and `extracting` (a
+    /// template name), `copying` (the project directory) or `running-hook`
+    /// (a hook name).
+    pub phase: Option<extern "C" fn(*mut c_void, *const c_char, *const c_char)>,
+    pub progress: Option<extern "C" fn(*mut c_void, usize, usize)>,
+    pub done: Option<extern "C" fn(*mut c_void)>,
+}
+
+/// Returned from a conflict callback to keep the existing file.
+pub const THORC_CONFLICT_KEEP: c_int = 0;
+/// Returned from a conflict callback to overwrite the existing file.
+pub const THORC_CONFLICT_OVERWRITE: c_int = 1;
+/// Returned from a conflict callback to merge with conflict markers.
+pub const THORC_CONFLICT_MERGE: c_int = 2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    // an interior NUL can only come from a path or name; cut it off there
+    let message = message.to_string();
+    let message = message.split('\0').next().unwrap_or_default();
+    let message = CString::new(message).unwrap_or_default();
+
+    LAST_ERROR.with(|it| *it.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning errors and panics into `None` and the last error.
+fn ffi_try<T>(f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(it)) => Some(it),
+        Ok(Err(err)) => {
+            set_last_error(err);
+            None
+        }
+        Err(_) => {
+            set_last_error("thorc panicked");
+            None
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} must not be null", what));
+    }
+
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+unsafe fn opt_str_arg<'a>(s: *const c_char, what: &str) -> Result<Option<&'a str>, String> {
+    if s.is_null() {
+        Ok(None)
+    } else {
+        str_arg(s, what).map(Some)
+    }
+}
+
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+struct CallbackObserver(ThorcCallbacks);
+
+impl Observer for CallbackObserver {
+    fn phase(&self, phase: Phase<'_>) {
+        let callback = match self.0.phase {
+            Some(callback) => callback,
+            None => return,
+        };
+
+        let (name, subject) = match phase {
+            Phase::Resolving { template } => ("resolving", template.to_string()),
+            Phase::FetchingIndex { name } => ("fetching-index", name.to_string()),
+            Phase::Downloading { template } => ("downloading", template.to_string()),
+            Phase::Extracting { template } => ("extracting", template.to_string()),
+            Phase::Copying { directory } => ("copying", directory.display().to_string()),
+            Phase::RunningHook { name } => ("running-hook", name.to_string()),
+        };
+        let (name, subject) = (to_c_string(name), to_c_string(&subject));
+
+        callback(self.0.user_data, name.as_ptr(), subject.as_ptr());
+    }
+
+    fn progress(&self, current: usize, total: usize) {
+        if let Some(callback) = self.0.progress {
+            callback(self.0.user_data, current, total);
+        }
+    }
+
+    fn done(&self) {
+        if let Some(callback) = self.0.done {
+            callback(self.0.user_data);
+        }
+    }
+}
+
+/// The message of the last error on this thread, or null if there was none.
+/// Valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn thorc_last_error() -> *const c_char {
+    LAST_ERROR.with(|it| it.borrow().as_ref().map_or(ptr::null(), |it| it.as_ptr()))
+}
+
+/// Loads the config and the local index, like [`Thorc::builder`]; null
+/// paths mean the defaults. Returns null on failure.
+///
+/// # Safety
+///
+/// The paths must be null or valid C strings; `callbacks` must be null or
+/// point to a valid [`ThorcCallbacks`], whose `user_data` stays valid for as
+/// long as the returned handle.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_new(
+    config_file: *const c_char,
+    local_index_file: *const c_char,
+    callbacks: *const ThorcCallbacks,
+) -> *mut Thorc {
+    ffi_try(|| {
+        let mut builder = Thorc::builder();
+
+        if let Some(config_file) = opt_str_arg(config_file, "config_file")? {
+            builder = builder.config_file(config_file);
+        }
+        if let Some(local_index_file) = opt_str_arg(local_index_file, "local_index_file")? {
+            builder = builder.local_index_file(local_index_file);
+        }
+        if let Some(callbacks) = callbacks.as_ref() {
+            builder = builder.observer(CallbackObserver(*callbacks));
+        }
+
+        let thorc = builder.build().map_err(|err| err.to_string())?;
+
+        Ok(Box::into_raw(Box::new(thorc)))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees a handle from [`thorc_new`]; null is ignored.
+///
+/// # Safety
+///
+/// `thorc` must be null or a handle from [`thorc_new`] not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_free(thorc: *mut Thorc) {
+    if !thorc.is_null() {
+        drop(Box::from_raw(thorc));
+    }
+}
+
+/// Frees a string returned by thorc; null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by thorc not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Searches every index for `term`, like `thorc find`, calling `on_match`
+/// with the index name, template name and description (null if there is
+/// none) of every match, best first. The strings are only valid during the
+/// call.
+///
+/// # Safety
+///
+/// `thorc` must be a live handle and `term` a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_search(
+    thorc: *const Thorc,
+    term: *const c_char,
+    on_match: extern "C" fn(*mut c_void, *const c_char, *const c_char, *const c_char),
+    user_data: *mut c_void,
+) -> c_int {
+    ffi_try(|| {
+        let thorc = thorc.as_ref().ok_or("thorc must not be null")?;
+        let term = str_arg(term, "term")?;
+
+        let result = thorc.find(term, &[]).map_err(|err| err.to_string())?;
+
+        for m in result.iter() {
+            let index = to_c_string(m.index);
+            let name = to_c_string(&m.template.versioned_name());
+            let description = m.template.description().map(|it| to_c_string(it));
+
+            on_match(
+                user_data,
+                index.as_ptr(),
+                name.as_ptr(),
+                description.as_ref().map_or(ptr::null(), |it| it.as_ptr()),
+            );
+        }
+
+        Ok(0)
+    })
+    .unwrap_or(-1)
+}
+
+/// Looks a template up by its (possibly `index:`-qualified) name, like
+/// [`Thorc::resolve`], returning it as JSON; null on failure.
+///
+/// # Safety
+///
+/// `thorc` must be a live handle and `name` a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_resolve(thorc: *const Thorc, name: *const c_char) -> *mut c_char {
+    ffi_try(|| {
+        let thorc = thorc.as_ref().ok_or("thorc must not be null")?;
+        let name = str_arg(name, "name")?;
+
+        let template = thorc.resolve(None, name).map_err(|err| err.to_string())?;
+        let json = serde_json::to_string(&template).map_err(|err| err.to_string())?;
+
+        Ok(to_c_string(&json).into_raw())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Generates a project from `template` into `directory`, like `thorc new`
+/// but also into a directory that already has files. `project_name`
+/// defaults to the directory's name; `variables_json` is null or a JSON
+/// object of strings. `on_conflict` is asked about existing files with
+/// different contents, returning one of the `THORC_CONFLICT_*` constants,
+/// or anything else to abort; when null, they are overwritten.
+///
+/// # Safety
+///
+/// `thorc` must be a live handle, `template` and `directory` valid C
+/// strings, and `project_name` and `variables_json` null or valid C strings.
+#[no_mangle]
+pub unsafe extern "C" fn thorc_generate(
+    thorc: *const Thorc,
+    template: *const c_char,
+    directory: *const c_char,
+    project_name: *const c_char,
+    variables_json: *const c_char,
+    on_conflict: Option<extern "C" fn(*mut c_void, *const c_char, *const c_char) -> c_int>,
+    user_data: *mut c_void,
+) -> c_int {
+    ffi_try(|| {
+        let thorc = thorc.as_ref().ok_or("thorc must not be null")?;
+        let template = str_arg(template, "template")?;
+        let directory = str_arg(directory, "directory")?;
+        let project_name = opt_str_arg(project_name, "project_name")?;
+        let variables = match opt_str_arg(variables_json, "variables_json")? {
+            Some(json) => serde_json::from_str::<BTreeMap<String, String>>(json)
+                .map_err(|err| format!("invalid variables: {}", err))?,
+            None => BTreeMap::new(),
+        };
+
+        let mut options = GenerateOptions::new(template, PathBuf::from(directory));
+        options.project_name = project_name.map(str::to_owned);
+        options.variables = variables;
+        options.allow_dirty = true;
+
+        let mut resolver = |src: &Path, dest: &Path| match on_conflict {
+            Some(on_conflict) => {
+                let (src, dest) = (path_c_string(src), path_c_string(dest));
+
+                match on_conflict(user_data, src.as_ptr(), dest.as_ptr()) {
+                    THORC_CONFLICT_KEEP => Ok(Conflict::Keep),
+                    THORC_CONFLICT_OVERWRITE => Ok(Conflict::Overwrite),
+                    THORC_CONFLICT_MERGE => Ok(Conflict::Merge),
+                    _ => Err(io::Error::other("aborted")),
+                }
+            }
+            None => Ok(Conflict::Overwrite),
+        };
+
+        generate::generate_project_with(thorc, options, &mut resolver)
+            .map_err(|err| err.to_string())?;
+
+        Ok(0)
+    })
+    .unwrap_or(-1)
+}
+
+fn path_c_string(path: &Path) -> CString {
+    to_c_string(&path.display().to_string())
+}
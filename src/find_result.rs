@@ -1,46 +1,252 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
 use crate::template::Template;
 
-#[derive(Debug, Clone)]
+/// How search terms are matched against template names and descriptions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// the term appears in the text, ignoring case.
+    #[default]
+    IgnoreCase,
+    /// the term appears in the text exactly.
+    CaseSensitive,
+    /// the term's characters appear in the text in order, not necessarily
+    /// next to each other, ignoring case.
+    Fuzzy,
+}
+
+impl MatchMode {
+    pub fn matches(self, text: &str, term: &str) -> bool {
+        match self {
+            MatchMode::IgnoreCase => text.to_lowercase().contains(&term.to_lowercase()),
+            MatchMode::CaseSensitive => text.contains(term),
+            MatchMode::Fuzzy => fuzzy_score(text, term).is_some(),
+        }
+    }
+
+    /// How closely a template name matches `term`; higher is more relevant.
+    pub fn score(self, name: &str, term: &str) -> i64 {
+        let (tier, rank) = self.rank(name, term);
+        -((tier as i64) << 32) - rank
+    }
+
+    /// Sort key for a template name matching `term`; lower is more relevant.
+    fn rank(self, name: &str, term: &str) -> (usize, i64) {
+        match self {
+            MatchMode::IgnoreCase => name_rank(&name.to_lowercase(), &term.to_lowercase()),
+            MatchMode::CaseSensitive => name_rank(name, term),
+            MatchMode::Fuzzy => match fuzzy_score(name, term) {
+                _ if name.eq_ignore_ascii_case(term) => (0, 0),
+                Some(score) => (1, -score),
+                None => (3, 0),
+            },
+        }
+    }
+}
+
+fn fuzzy_score(text: &str, term: &str) -> Option<i64> {
+    SkimMatcherV2::default()
+        .ignore_case()
+        .fuzzy_match(text, term)
+}
+
+/// Which parts of a template matched; the variants are in order of
+/// relevance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchKind {
+    NameAndDescription,
+    Name,
+    Description,
+    /// one of the tags, but neither name nor description.
+    Tags,
+}
+
+/// A template found in an index.
+#[derive(Debug, Clone, Copy)]
+pub struct Match<'a> {
+    /// name of the index the template was found in.
+    pub index: &'a str,
+    pub template: &'a Template,
+    pub kind: MatchKind,
+    /// how closely the name matched the term; higher is better. Scores are
+    /// only comparable within one search.
+    pub score: i64,
+}
+
+/// The templates of one index that matched a search.
+#[derive(Debug, Clone, Default)]
 pub struct FindResult<'a> {
-    pub name_and_description: Vec<&'a Template>,
-    pub name_only: Vec<&'a Template>,
-    pub description_only: Vec<&'a Template>,
+    matches: Vec<(&'a Template, MatchKind, i64)>,
 }
 
 impl<'a> FindResult<'a> {
+    pub(crate) fn push(&mut self, template: &'a Template, kind: MatchKind, score: i64) {
+        self.matches.push((template, kind, score));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a Template, MatchKind, i64)> + '_ {
+        self.matches.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    fn of_kind(&self, kind: MatchKind) -> Vec<&'a Template> {
+        self.iter()
+            .filter(|&(_, k, _)| k == kind)
+            .map(|(t, _, _)| t)
+            .collect()
+    }
+
+    pub fn name_and_description(&self) -> Vec<&'a Template> {
+        self.of_kind(MatchKind::NameAndDescription)
+    }
+
+    pub fn name_only(&self) -> Vec<&'a Template> {
+        self.of_kind(MatchKind::Name)
+    }
+
+    pub fn description_only(&self) -> Vec<&'a Template> {
+        self.of_kind(MatchKind::Description)
+    }
+
+    pub fn tags_only(&self) -> Vec<&'a Template> {
+        self.of_kind(MatchKind::Tags)
+    }
+
     pub fn compose(&self, name: &'a str) -> FindResultComposite<'a> {
         FindResultComposite {
-            name_and_description: self
-                .name_and_description
+            matches: self
                 .iter()
-                .map(|&it| (name, it))
+                .map(|(template, kind, score)| Match {
+                    index: name,
+                    template,
+                    kind,
+                    score,
+                })
                 .collect(),
-            name_only: self.name_only.iter().map(|&it| (name, it)).collect(),
-            description_only: self.description_only.iter().map(|&it| (name, it)).collect(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// The templates that matched a search over several indexes.
+#[derive(Debug, Clone, Default)]
 pub struct FindResultComposite<'a> {
-    pub name_and_description: Vec<(&'a str, &'a Template)>,
-    pub name_only: Vec<(&'a str, &'a Template)>,
-    pub description_only: Vec<(&'a str, &'a Template)>,
+    matches: Vec<Match<'a>>,
 }
 
 impl<'a> FindResultComposite<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = Match<'a>> + '_ {
+        self.matches.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    fn of_kind(&self, kind: MatchKind) -> Vec<(&'a str, &'a Template)> {
+        self.iter()
+            .filter(|it| it.kind == kind)
+            .map(|it| (it.index, it.template))
+            .collect()
+    }
+
+    pub fn name_and_description(&self) -> Vec<(&'a str, &'a Template)> {
+        self.of_kind(MatchKind::NameAndDescription)
+    }
+
+    pub fn name_only(&self) -> Vec<(&'a str, &'a Template)> {
+        self.of_kind(MatchKind::Name)
+    }
+
+    pub fn description_only(&self) -> Vec<(&'a str, &'a Template)> {
+        self.of_kind(MatchKind::Description)
+    }
+
+    pub fn tags_only(&self) -> Vec<(&'a str, &'a Template)> {
+        self.of_kind(MatchKind::Tags)
+    }
+
     pub fn merge_ref<'b>(&mut self, other: FindResultComposite<'b>)
     where
         'a: 'b,
         'b: 'a,
     {
-        self.name_and_description.extend(other.name_and_description);
-        self.name_only.extend(other.name_only);
-        self.description_only.extend(other.description_only);
+        self.matches.extend(other.matches);
     }
 
     pub fn merge(mut self, other: Self) -> Self {
         self.merge_ref(other);
         self
     }
+
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&Match<'a>) -> bool,
+    {
+        self.matches.retain(f);
+    }
+
+    /// Keeps only the templates that have every one of `tags`.
+    pub fn retain_tagged(&mut self, tags: &[String]) {
+        self.retain(|it| it.template.has_tags(tags));
+    }
+
+    /// Best scores first, then by name.
+    pub fn sort_by_score(&mut self) {
+        self.matches.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.template.name().cmp(b.template.name()))
+        });
+    }
+
+    /// By [`MatchKind`], then like [`FindResultComposite::sort_by_score`].
+    pub fn sort_by_kind(&mut self) {
+        self.sort_by_score();
+        self.matches.sort_by_key(|it| it.kind);
+    }
+
+    /// Orders each kind of match by how closely template names match `term`:
+    /// exact matches first, then prefixes, then earlier matches, then by
+    /// name.
+    pub fn rank(&mut self, term: &str) {
+        self.rank_with(term, MatchMode::default());
+    }
+
+    /// Like [`FindResultComposite::rank`]; with [`MatchMode::Fuzzy`], names
+    /// that match better come first.
+    pub fn rank_with(&mut self, term: &str, mode: MatchMode) {
+        for it in self.matches.iter_mut() {
+            it.score = mode.score(it.template.name(), term);
+        }
+        self.sort_by_kind();
+    }
+}
+
+impl<'a> IntoIterator for FindResultComposite<'a> {
+    type Item = Match<'a>;
+    type IntoIter = std::vec::IntoIter<Match<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.matches.into_iter()
+    }
+}
+
+fn name_rank(name: &str, term: &str) -> (usize, i64) {
+    match name.find(term) {
+        Some(_) if name == term => (0, 0),
+        Some(0) => (1, name.len() as i64),
+        Some(pos) => (2, pos as i64),
+        None => (3, 0),
+    }
 }
@@ -0,0 +1,226 @@
+//! The whole flow of `thorc new`: checking the name and the target
+//! directory, resolving the template, downloading it, copying it and
+//! running its setup.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    context::IndexName,
+    error::Error,
+    lock::LockFile,
+    template::{check_qualified_template_name, Template},
+    utils::Conflict,
+    Thorc,
+};
+
+/// Decides what [`generate_project_with`] does when it can't tell by itself:
+/// about files that already exist, and, if it asks to, about templates by
+/// the same name in several indexes. Closures asking about files are
+/// resolvers too.
+pub trait ConflictResolver {
+    /// `dest` already exists, with contents other than `src`'s; see
+    /// [`crate::utils::copy_with`]. Overwrites by default.
+    fn file_conflict(&mut self, src: &Path, dest: &Path) -> io::Result<Conflict> {
+        let _ = (src, dest);
+        Ok(Conflict::Overwrite)
+    }
+
+    /// Whether to ask [`ConflictResolver::file_conflict`] at all; files
+    /// that already exist are otherwise overwritten without being compared.
+    /// Does by default.
+    fn asks_about_files(&self) -> bool {
+        true
+    }
+
+    /// Whether to look for the template in every index, which fetches all
+    /// the remote ones, and ask [`ConflictResolver::duplicate_template`]
+    /// if several have it. Otherwise the first found is used, as
+    /// [`Thorc::resolve`] finds it. Doesn't by default.
+    fn checks_duplicates(&self) -> bool {
+        false
+    }
+
+    /// Several indexes have a template called `name`; `candidates` are the
+    /// index names and templates, in the order [`Thorc::resolve`] looks in
+    /// them. Returns the position of the one to use, or `None` to
+    /// give up. Takes the first by default.
+    fn duplicate_template(
+        &mut self,
+        name: &str,
+        candidates: &[(&str, &Template)],
+    ) -> Option<usize> {
+        let _ = (name, candidates);
+        Some(0)
+    }
+}
+
+/// Overwrites existing files and takes the first template found, as
+/// [`generate_project`] does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultResolver;
+
+impl ConflictResolver for DefaultResolver {
+    fn asks_about_files(&self) -> bool {
+        false
+    }
+}
+
+impl<F> ConflictResolver for F
+where
+    F: FnMut(&Path, &Path) -> io::Result<Conflict>,
+{
+    fn file_conflict(&mut self, src: &Path, dest: &Path) -> io::Result<Conflict> {
+        self(src, dest)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// template name, possibly qualified with its index as `index:name`.
+    pub template: String,
+    pub index: Option<IndexName>,
+    pub directory: PathBuf,
+    /// defaults to the name of `directory`.
+    pub project_name: Option<String>,
+    pub variables: BTreeMap<String, String>,
+    /// generate into a directory that already has files in it.
+    pub allow_dirty: bool,
+    /// generate from a deprecated template instead of failing with
+    /// [`Error::Deprecated`].
+    pub allow_deprecated: bool,
+}
+
+impl GenerateOptions {
+    pub fn new(template: impl Into<String>, directory: impl Into<PathBuf>) -> Self {
+        Self {
+            template: template.into(),
+            index: None,
+            directory: directory.into(),
+            project_name: None,
+            variables: BTreeMap::new(),
+            allow_dirty: false,
+            allow_deprecated: false,
+        }
+    }
+}
+
+/// What [`generate_project`] did.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub directory: PathBuf,
+    /// the lock file written into `directory`, naming the template and
+    /// everything else it was generated with.
+    pub lock: LockFile,
+}
+
+/// Fails if `directory` exists and isn't a directory, or isn't empty (unless
+/// `allow_dirty`).
+pub fn check_target_directory(directory: &Path, allow_dirty: bool) -> Result<(), Error> {
+    if directory.exists() {
+        if !directory.is_dir() {
+            return Err(Error::NotADirectory(directory.to_owned()));
+        } else if !allow_dirty
+            && directory
+                .read_dir()
+                .map_err(Error::io(directory))?
+                .next()
+                .is_some()
+        {
+            return Err(Error::NotEmpty(directory.to_owned()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The project name a project generated into `directory` gets unless given
+/// one: the directory's name, if it has one and it is UTF-8.
+pub fn default_project_name(directory: &Path) -> Result<String, Error> {
+    directory
+        .file_name()
+        .and_then(|it| it.to_str())
+        .map(|it| it.to_string())
+        .ok_or_else(|| Error::NoProjectName(directory.to_owned()))
+}
+
+/// Generates a project as described by `options`, with the
+/// [`DefaultResolver`].
+pub fn generate_project(thorc: &Thorc, options: GenerateOptions) -> Result<Report, Error> {
+    generate_project_with(thorc, options, &mut DefaultResolver)
+}
+
+/// Like [`generate_project`], but asks `resolver` about existing files, and
+/// which template to use if several indexes have one by the requested name.
+pub fn generate_project_with(
+    thorc: &Thorc,
+    options: GenerateOptions,
+    resolver: &mut dyn ConflictResolver,
+) -> Result<Report, Error> {
+    let GenerateOptions {
+        template,
+        index,
+        directory,
+        project_name,
+        variables,
+        allow_dirty,
+        allow_deprecated,
+    } = options;
+
+    check_qualified_template_name(&template)?;
+    check_target_directory(&directory, allow_dirty)?;
+
+    let project_name = match project_name {
+        Some(project_name) => project_name,
+        None => default_project_name(&directory)?,
+    };
+
+    let mut found = if resolver.checks_duplicates() {
+        thorc.resolve_all(index, &template)?
+    } else {
+        vec![thorc.resolve_with_index(index, &template)?]
+    };
+    let chosen = if found.len() > 1 {
+        let candidates = found
+            .iter()
+            .map(|(index, t)| (index.as_str(), t))
+            .collect::<Vec<_>>();
+        resolver
+            .duplicate_template(&template, &candidates)
+            .filter(|&it| it < found.len())
+            .ok_or_else(|| Error::AmbiguousTemplate {
+                template: template.clone(),
+                indexes: found.iter().map(|(index, _)| index.clone()).collect(),
+            })?
+    } else {
+        0
+    };
+    let (index, template) = found.swap_remove(chosen);
+    if let Some(notice) = template.deprecation_notice() {
+        if !allow_deprecated {
+            return Err(Error::Deprecated {
+                template: template.versioned_name(),
+                superseded_by: template.superseded_by().map(|it| it.to_string()),
+            });
+        }
+
+        tracing::warn!("{} is {}", template.versioned_name(), notice);
+    }
+
+    let asks_about_files = resolver.asks_about_files();
+    let mut file_conflict = |src: &Path, dest: &Path| resolver.file_conflict(src, dest);
+    let lock = thorc.generate_from(
+        Some(&index),
+        &template,
+        &directory,
+        &project_name,
+        &variables,
+        None,
+        asks_about_files.then_some(&mut file_conflict as _),
+    )?;
+
+    Ok(Report { directory, lock })
+}
@@ -0,0 +1,269 @@
+//! Running the hooks a template ships in `thor/` (or another directory, see
+//! [`DEFAULT_HOOKS_DIR`]), and the built-in setup for templates without a
+//! `setup` hook.
+//!
+//! Hooks are described as [`Hook`]s and handed to a [`HookExecutor`]; the
+//! default, [`ProcessExecutor`], runs them as child processes.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::RunHookError,
+    observer::{Observer, Phase},
+    template::{SetupKind, Template},
+};
+
+pub const SETUP_HOOK_NAME: &str = "setup";
+
+/// Where a template keeps its hooks, relative to it, unless its metadata or
+/// the config name another directory.
+pub const DEFAULT_HOOKS_DIR: &str = "thor";
+
+/// Whether generating a project runs its setup hook (or the built-in setup).
+/// Ordered from the most to the least permissive.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookPolicy {
+    #[default]
+    Always,
+    /// only if the [`HookExecutor`] confirms it.
+    Prompt,
+    Never,
+}
+
+/// What a hook runs.
+#[derive(Debug, Clone)]
+pub enum HookProgram {
+    /// an executable shipped with the template.
+    File(PathBuf),
+    /// a built-in script, piped to `bash`.
+    Script(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub name: String,
+    pub program: HookProgram,
+    pub args: Vec<OsString>,
+    /// `THORC`, pointing back at the thorc binary, and a `THORC_VAR_<NAME>`
+    /// for every variable.
+    pub env: BTreeMap<String, OsString>,
+}
+
+/// Runs hooks; swap it to sandbox them, log them, or not run them at all.
+pub trait HookExecutor {
+    /// Runs `hook` to completion; a hook that doesn't succeed is an error.
+    fn execute(&self, hook: &Hook) -> Result<(), RunHookError>;
+
+    /// Whether to run `hook` under [`HookPolicy::Prompt`]. Declines by
+    /// default, as there is no one to ask.
+    fn confirm(&self, hook: &Hook) -> bool {
+        let _ = hook;
+        false
+    }
+}
+
+/// Runs hooks with another executor, as far as a [`HookPolicy`] allows;
+/// hooks it doesn't allow are skipped, not failed.
+pub struct PolicyExecutor<'a> {
+    executor: &'a dyn HookExecutor,
+    policy: HookPolicy,
+}
+
+impl<'a> PolicyExecutor<'a> {
+    pub fn new(executor: &'a dyn HookExecutor, policy: HookPolicy) -> Self {
+        Self { executor, policy }
+    }
+}
+
+impl HookExecutor for PolicyExecutor<'_> {
+    fn execute(&self, hook: &Hook) -> Result<(), RunHookError> {
+        match self.policy {
+            HookPolicy::Always => self.executor.execute(hook),
+            HookPolicy::Prompt if self.executor.confirm(hook) => self.executor.execute(hook),
+            HookPolicy::Prompt => {
+                tracing::info!("Not running the {} hook, as it wasn't confirmed", hook.name);
+                Ok(())
+            }
+            HookPolicy::Never => {
+                tracing::info!("Hooks are disabled, not running the {} hook", hook.name);
+                Ok(())
+            }
+        }
+    }
+
+    fn confirm(&self, hook: &Hook) -> bool {
+        self.executor.confirm(hook)
+    }
+}
+
+/// The default [`HookExecutor`], running hooks as child processes that
+/// inherit stdout and stderr.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessExecutor;
+
+impl HookExecutor for ProcessExecutor {
+    #[tracing::instrument(skip_all, fields(hook = %hook.name))]
+    fn execute(&self, hook: &Hook) -> Result<(), RunHookError> {
+        let mut cmd = match &hook.program {
+            HookProgram::File(path) => Command::new(path),
+            HookProgram::Script(_) => {
+                let mut cmd = Command::new("/usr/bin/env");
+                cmd.stdin(Stdio::piped()).arg("bash").arg("-s").arg("-");
+                cmd
+            }
+        };
+        cmd.args(&hook.args).envs(&hook.env);
+
+        tracing::debug!("Running: {:?}", cmd);
+
+        let start = Instant::now();
+        let mut child = cmd.spawn()?;
+
+        if let HookProgram::Script(sh) = &hook.program {
+            write!(&mut child.stdin.take().unwrap(), "{}", sh)?;
+        }
+
+        let exit = child.wait()?;
+        tracing::debug!(
+            status = %exit,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "Hook finished"
+        );
+
+        if !exit.success() {
+            return Err(RunHookError::StatusNotSuccess(exit));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn hook_path(dir: &Path, hooks_dir: &str, name: &str) -> PathBuf {
+    let mut pb = dir.join(hooks_dir);
+    pb.push(name);
+    pb
+}
+
+pub fn hook_exists(dir: &Path, hooks_dir: &str, name: &str) -> bool {
+    hook_path(dir, hooks_dir, name).exists()
+}
+
+fn hook_env(self_bin: &Path, variables: &BTreeMap<String, String>) -> BTreeMap<String, OsString> {
+    let mut env = BTreeMap::new();
+    env.insert("THORC".to_string(), self_bin.as_os_str().to_owned());
+    for (name, value) in variables {
+        env.insert(format!("THORC_VAR_{}", name.to_uppercase()), value.into());
+    }
+    env
+}
+
+/// Runs the hook `hook_name` of the project in `directory`, if it has one
+/// in `hooks_dir`.
+pub fn run_hook(
+    executor: &dyn HookExecutor,
+    self_bin: &Path,
+    directory: &Path,
+    hooks_dir: &str,
+    hook_name: &str,
+    variables: &BTreeMap<String, String>,
+    args: &[&OsStr],
+) -> Result<(), RunHookError> {
+    let hook = hook_path(directory, hooks_dir, hook_name);
+
+    if hook.exists() {
+        if hook.is_file() {
+            executor.execute(&Hook {
+                name: hook_name.to_string(),
+                program: HookProgram::File(hook),
+                args: args.iter().map(|it| it.to_os_string()).collect(),
+                env: hook_env(self_bin, variables),
+            })?;
+        } else {
+            tracing::warn!("Looks like {} is not a file", hook.display());
+        }
+    } else {
+        tracing::info!("Looks like {} doesn't exist, not running", hook.display());
+    }
+
+    Ok(())
+}
+
+/// Runs the `setup` hook in `hooks_dir` of a freshly copied project, or the
+/// built-in setup for its [`SetupKind`] if it doesn't have one.
+#[allow(clippy::too_many_arguments)]
+pub fn finish_setup(
+    executor: &dyn HookExecutor,
+    self_bin: &Path,
+    observer: &dyn Observer,
+    template: &Template,
+    directory: &Path,
+    hooks_dir: &str,
+    project_name: &str,
+    variables: &BTreeMap<String, String>,
+) -> Result<(), RunHookError> {
+    let args = [directory.as_os_str(), project_name.as_ref()];
+
+    if hook_exists(directory, hooks_dir, SETUP_HOOK_NAME) {
+        observer.phase(Phase::RunningHook {
+            name: SETUP_HOOK_NAME,
+        });
+        run_hook(
+            executor,
+            self_bin,
+            directory,
+            hooks_dir,
+            SETUP_HOOK_NAME,
+            variables,
+            &args,
+        )
+    } else {
+        if let Some(setup_kind) = template.setup() {
+            observer.phase(Phase::RunningHook {
+                name: SETUP_HOOK_NAME,
+            });
+            let sh = match setup_kind {
+                SetupKind::Rust => {
+                    r#"#!/usr/bin/env bash
+                        dir="$1"
+                        name="$2"
+
+                        echo "Setting up for rust" >&2
+                        echo "value = \"$name\"" | $THORC edit-toml "$dir/Cargo.toml" "package/name" || exit $?
+                        "#
+                }
+                SetupKind::Npm => {
+                    r#"#!/usr/bin/env bash
+                    dir="$1"
+                    name="$2"
+
+                    echo "Setting up for npm" >&2
+                    echo "\"$name\"" | $THORC edit-json "$dir/package.json" "name" || exit $?
+                    "#
+                }
+            };
+
+            executor.execute(&Hook {
+                name: SETUP_HOOK_NAME.to_string(),
+                program: HookProgram::Script(sh.to_string()),
+                args: args.iter().map(|it| it.to_os_string()).collect(),
+                env: hook_env(self_bin, variables),
+            })
+        } else {
+            tracing::warn!(
+                "No setup hook found for {}; you may need to change some things manually",
+                template.name()
+            );
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! How thorc fetches indexes, templates and registry listings: settings
+//! for every HTTP request, and the [`Downloader`] abstracting the transport.
+//!
+//! Everything but the [`Downloader`] trait itself needs the `network`
+//! feature.
+
+use std::io::{self, Write};
+#[cfg(feature = "network")]
+use std::{io::Read, sync::RwLock, time::Duration};
+
+#[cfg(feature = "network")]
+use reqwest::{header, StatusCode};
+
+use crate::{cancel::CancelToken, error::DownloadError, utils::StreamHasher};
+
+#[cfg(feature = "network")]
+#[derive(Debug, Clone, Default)]
+pub struct HttpSettings {
+    /// timeout for each whole request; none by default.
+    pub timeout: Option<Duration>,
+    /// proxy for all requests, in addition to the `HTTP(S)_PROXY` variables.
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Sent unless the settings name another; some APIs, like GitHub's, reject
+/// requests without one.
+#[cfg(feature = "network")]
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[cfg(feature = "network")]
+static SETTINGS: RwLock<Option<HttpSettings>> = RwLock::new(None);
+
+/// Replaces the settings used by all later requests, process-wide.
+#[cfg(feature = "network")]
+pub fn configure(settings: HttpSettings) {
+    *SETTINGS.write().unwrap() = Some(settings);
+}
+
+#[cfg(feature = "network")]
+pub(crate) fn client() -> reqwest::Result<reqwest::blocking::Client> {
+    let settings = SETTINGS.read().unwrap();
+    let mut builder = reqwest::blocking::Client::builder().user_agent(DEFAULT_USER_AGENT);
+
+    if let Some(settings) = &*settings {
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(user_agent) = &settings.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+    }
+
+    builder.build()
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn async_client() -> reqwest::Result<reqwest::Client> {
+    let settings = SETTINGS.read().unwrap();
+    let mut builder = reqwest::Client::builder().user_agent(DEFAULT_USER_AGENT);
+
+    if let Some(settings) = &*settings {
+        if let Some(timeout) = settings.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(user_agent) = &settings.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+    }
+
+    builder.build()
+}
+
+/// Fails with the [`DownloadError`] for an unsuccessful `status` of a
+/// response from `url`.
+#[cfg(feature = "network")]
+pub(crate) fn check_status(url: &str, status: StatusCode) -> Result<(), DownloadError> {
+    let url = url.to_string();
+    match status {
+        _ if status.is_success() || status.is_redirection() => Ok(()),
+        StatusCode::NOT_FOUND => Err(DownloadError::NotFound(url)),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(DownloadError::Denied {
+            url,
+            status: status.as_u16(),
+        }),
+        _ => Err(DownloadError::Status {
+            url,
+            status: status.as_u16(),
+        }),
+    }
+}
+
+/// A response body, and the etag to send along next time.
+#[derive(Debug, Clone, Default)]
+pub struct Fetched {
+    pub bytes: Vec<u8>,
+    pub etag: Option<String>,
+    /// where the response came from, if redirects led away from the url
+    /// asked for.
+    pub url: Option<String>,
+}
+
+/// What is left of a response whose body [`Downloader::fetch_into`] wrote
+/// out as it arrived.
+#[derive(Debug, Clone, Default)]
+pub struct Received {
+    pub etag: Option<String>,
+    /// the sha512 of the body.
+    pub digest: String,
+    /// how many bytes the body had.
+    pub len: u64,
+    /// where the response came from, if redirects led away from the url
+    /// asked for.
+    pub url: Option<String>,
+}
+
+/// How thorc fetches indexes and template archives; swap it for tests,
+/// offline stubs or transports other than plain HTTP.
+pub trait Downloader {
+    /// Fetches `url`. With the `etag` of an earlier fetch, returns `None` if
+    /// nothing changed since. Missing resources are
+    /// [`DownloadError::NotFound`].
+    fn fetch(&self, url: &str, etag: Option<&str>) -> Result<Option<Fetched>, DownloadError>;
+
+    /// Like [`Downloader::fetch`], but gives up once `cancel` is cancelled.
+    /// By default only checks before and after fetching; override it for
+    /// transports that can stop midway.
+    fn fetch_cancellable(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        cancel: &CancelToken,
+    ) -> Result<Option<Fetched>, DownloadError> {
+        cancel.check()?;
+        let fetched = self.fetch(url, etag)?;
+        cancel.check()?;
+
+        Ok(fetched)
+    }
+
+    /// Like [`Downloader::fetch_cancellable`], but writes the body to `out`
+    /// instead of returning it, hashing it on the way; archives are
+    /// downloaded like this, so they never have to fit in memory. By default
+    /// fetches the whole body first; override it for transports that can
+    /// hand it over in pieces.
+    fn fetch_into(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+    ) -> Result<Option<Received>, DownloadError> {
+        let fetched = match self.fetch_cancellable(url, etag, cancel)? {
+            Some(fetched) => fetched,
+            None => return Ok(None),
+        };
+        out.write_all(&fetched.bytes)?;
+
+        let mut hasher = StreamHasher::new();
+        hasher.update(&fetched.bytes);
+
+        Ok(Some(Received {
+            etag: fetched.etag,
+            digest: hasher.finish(),
+            len: fetched.bytes.len() as u64,
+            url: fetched.url,
+        }))
+    }
+
+    fn fetch_text(&self, url: &str) -> Result<String, DownloadError> {
+        let bytes = self.fetch(url, None)?.unwrap_or_default().bytes;
+        let text = String::from_utf8(bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(text)
+    }
+}
+
+/// The default [`Downloader`], using the [`configure`]d settings.
+#[cfg(feature = "network")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReqwestDownloader;
+
+#[cfg(feature = "network")]
+impl Downloader for ReqwestDownloader {
+    fn fetch(&self, url: &str, etag: Option<&str>) -> Result<Option<Fetched>, DownloadError> {
+        self.fetch_cancellable(url, etag, &CancelToken::new())
+    }
+
+    fn fetch_cancellable(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        cancel: &CancelToken,
+    ) -> Result<Option<Fetched>, DownloadError> {
+        let mut bytes = Vec::new();
+        let received = self.fetch_into(url, etag, &mut bytes, cancel)?;
+
+        Ok(received.map(|received| Fetched {
+            bytes,
+            etag: received.etag,
+            url: received.url,
+        }))
+    }
+
+    /// Checks `cancel` between chunks of the response body.
+    fn fetch_into(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        out: &mut dyn Write,
+        cancel: &CancelToken,
+    ) -> Result<Option<Received>, DownloadError> {
+        cancel.check()?;
+
+        let req = client()?.get(url);
+        let req = etag
+            .iter()
+            .fold(req, |req, etag| req.header(header::IF_NONE_MATCH, *etag));
+        let mut resp = req.send().map_err(DownloadError::network(url))?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        check_status(url, resp.status())?;
+
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .and_then(|it| it.to_str().ok())
+            .map(str::to_owned);
+        let redirected = Some(resp.url().as_str())
+            .filter(|it| *it != url)
+            .map(str::to_owned);
+
+        let mut hasher = StreamHasher::new();
+        let mut len = 0;
+        let mut chunk = [0; 64 * 1024];
+        loop {
+            cancel.check()?;
+            match resp.read(&mut chunk)? {
+                0 => break,
+                n => {
+                    hasher.update(&chunk[..n]);
+                    out.write_all(&chunk[..n])?;
+                    len += n as u64;
+                }
+            }
+        }
+
+        Ok(Some(Received {
+            etag,
+            digest: hasher.finish(),
+            len,
+            url: redirected,
+        }))
+    }
+}
+
+/// A [`Downloader`] that fails every fetch with [`DownloadError::Offline`];
+/// the default without the `network` feature.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfflineDownloader;
+
+impl Downloader for OfflineDownloader {
+    fn fetch(&self, url: &str, _etag: Option<&str>) -> Result<Option<Fetched>, DownloadError> {
+        Err(DownloadError::Offline(url.to_string()))
+    }
+}
@@ -1,49 +1,443 @@
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{find_result::FindResult, template::Template};
+use crate::{
+    error::{IndexFileError, MergeConflictError, NoSuchMergeStrategyError, SchemaError, TomlError},
+    find_result::{FindResult, MatchKind, MatchMode},
+    patches::Format,
+    remote_index::IncludedIndex,
+    schema::{self, Migration},
+    template::{SetupKind, Template, VersionSpec},
+    utils,
+};
+
+/// Version of the index format this thorc reads and writes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` upgrades an index from version `i` to `i + 1`. Version 0
+/// is the format from before indexes had a `schema_version`.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [
+    // 0 -> 1: only adds `schema_version` itself
+    |_| {},
+];
+
+/// A template listed, with the same name and version, in both indexes of an
+/// [`IndexDiff`], but differently.
+#[derive(Debug, Clone)]
+pub struct TemplateChange<'a> {
+    pub old: &'a Template,
+    pub new: &'a Template,
+    /// the keys whose values differ, as in the index file.
+    pub fields: Vec<String>,
+}
+
+/// What changed between two indexes, see [`TemplateIndex::diff`]. Templates
+/// are told apart by name and version.
+#[derive(Debug, Clone, Default)]
+pub struct IndexDiff<'a> {
+    pub added: Vec<&'a Template>,
+    pub removed: Vec<&'a Template>,
+    pub changed: Vec<TemplateChange<'a>>,
+}
+
+impl<'a> IndexDiff<'a> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Counts describing an index, see [`TemplateIndex::stats`].
+#[derive(Debug, Clone, Default)]
+pub struct IndexStats<'a> {
+    pub total: usize,
+    /// by git provider for repo templates, otherwise `local` or `archive`.
+    pub by_source: BTreeMap<&'static str, usize>,
+    /// by setup kind; `none` for templates without one.
+    pub by_setup: BTreeMap<&'static str, usize>,
+    pub by_tag: BTreeMap<&'a str, usize>,
+    pub missing_description: Vec<&'a Template>,
+}
+
+/// What [`TemplateIndex::merge`] does about a template that's in both
+/// indexes, with the same name and version, but differs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// fail with a [`MergeConflictError`].
+    #[default]
+    Error,
+    /// keep the template already in the index.
+    PreferSelf,
+    /// replace it with the one being merged in.
+    PreferOther,
+    /// keep both, renaming the one being merged in to `name-2`, `name-3`, ...
+    Rename,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = NoSuchMergeStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let strategy = match s {
+            "error" => MergeStrategy::Error,
+            "prefer-self" => MergeStrategy::PreferSelf,
+            "prefer-other" => MergeStrategy::PreferOther,
+            "rename" => MergeStrategy::Rename,
+            _ => return Err(NoSuchMergeStrategyError),
+        };
+
+        Ok(strategy)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TemplateIndex {
+    /// 0 if the index was deserialized directly from an unversioned file;
+    /// [`TemplateIndex::from_toml`] upgrades it to [`SCHEMA_VERSION`].
+    #[serde(default)]
+    pub schema_version: u32,
     #[serde(default)]
     pub for_remote: bool,
+    /// template names may have `.` and `/` in them, see
+    /// [`check_template_name_with`](crate::template::check_template_name_with).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub namespaced: bool,
+    /// indexes whose templates this one lists too, see
+    /// [`RemoteIndex::get_index_with`](crate::remote_index::RemoteIndex::get_index_with).
+    #[serde(default, rename = "include", skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<IncludedIndex>,
     #[serde(default, rename = "template")]
     pub templates: BTreeSet<Template>,
 }
 
+impl Default for TemplateIndex {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            for_remote: false,
+            namespaced: false,
+            includes: Vec::new(),
+            templates: BTreeSet::new(),
+        }
+    }
+}
+
 impl TemplateIndex {
-    pub fn find<'a>(&'a self, term: &str) -> FindResult<'a> {
-        let (name_and_description, (name_only, description_only)): (Vec<_>, (Vec<_>, Vec<_>)) =
+    /// Parses an index, migrating it from older schema versions; indexes
+    /// newer than this thorc understands are rejected.
+    pub fn from_toml(contents: &str) -> Result<Self, SchemaError> {
+        Self::from_str_as(contents, Some(Format::Toml))
+    }
+
+    /// Like [`TemplateIndex::from_toml`], but also reads JSON and YAML, with
+    /// the same structure as in TOML. Other formats are taken to be TOML.
+    pub fn from_str_as(contents: &str, format: Option<Format>) -> Result<Self, SchemaError> {
+        Ok(Self::from_str_versioned(contents, format)?.0)
+    }
+
+    /// The templates an index file lists more than once, as `name` or
+    /// `name@version`; parsing it keeps only the first of each.
+    pub(crate) fn duplicates_in(contents: &str, format: Option<Format>) -> Vec<String> {
+        let table = match format {
+            Some(Format::Json) => serde_json::from_str::<toml::value::Table>(contents).ok(),
+            Some(Format::Yaml) => serde_yaml::from_str::<toml::value::Table>(contents).ok(),
+            _ => toml::from_str::<toml::value::Table>(contents).ok(),
+        };
+        let templates = table
+            .as_ref()
+            .and_then(|it| it.get("template"))
+            .and_then(|it| it.as_array());
+
+        let mut seen = BTreeSet::new();
+        let mut duplicates = Vec::new();
+        for template in templates.into_iter().flatten() {
+            let name = template
+                .get("name")
+                .and_then(|it| it.as_str())
+                .unwrap_or_default();
+            let key = match template.get("version").and_then(|it| it.as_str()) {
+                Some(version) => format!("{}@{}", name, version),
+                None => name.to_string(),
+            };
+
+            if !seen.insert(key.clone()) {
+                duplicates.push(key);
+            }
+        }
+
+        duplicates
+    }
+
+    /// Like [`TemplateIndex::from_str_as`]; also returns the version the
+    /// index was at.
+    fn from_str_versioned(
+        contents: &str,
+        format: Option<Format>,
+    ) -> Result<(Self, u32), SchemaError> {
+        let mut table = match format {
+            Some(Format::Json) => serde_json::from_str::<toml::value::Table>(contents)?,
+            Some(Format::Yaml) => serde_yaml::from_str::<toml::value::Table>(contents)?,
+            _ => return schema::from_toml(contents, &MIGRATIONS),
+        };
+        let version = schema::migrate(&mut table, &MIGRATIONS)?;
+
+        // there are no toml lines to point at
+        let index = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err| TomlError::new(err, ""))?;
+
+        Ok((index, version))
+    }
+
+    /// Loads an index in the format its extension says, see
+    /// [`TemplateIndex::from_str_as`].
+    pub fn load(path: &Path) -> Result<Self, IndexFileError> {
+        let contents = fs::read_to_string(path)?;
+        let index = Self::from_str_as(&contents, Format::from_path(path))?;
+
+        Ok(index)
+    }
+
+    /// Rewrites the index at `path` in the current [`SCHEMA_VERSION`], keeping
+    /// the original next to it as `<file>.v<version>.bak`. Returns the version
+    /// it was at and the backup's path, or `None` if it was already current.
+    pub fn migrate(path: &Path) -> Result<Option<(u32, PathBuf)>, IndexFileError> {
+        let contents = fs::read_to_string(path)?;
+        let (index, version) = Self::from_str_versioned(&contents, Format::from_path(path))?;
+
+        if version == SCHEMA_VERSION {
+            return Ok(None);
+        }
+
+        let backup = schema::backup(path, version, &contents)?;
+        index.save(path)?;
+
+        Ok(Some((version, backup)))
+    }
+
+    /// Loads the index at `path`, lets `f` change it and saves it, holding a
+    /// lock throughout (see [`utils::with_lock`]) so that concurrent edits
+    /// aren't lost.
+    pub fn edit<T, F>(path: &Path, f: F) -> Result<T, IndexFileError>
+    where
+        F: FnOnce(&mut TemplateIndex) -> T,
+    {
+        utils::with_lock(path, || {
+            let mut index = Self::load(path)?;
+            let result = f(&mut index);
+            index.save(path)?;
+
+            Ok(result)
+        })
+    }
+
+    /// Writes the index atomically, see [`utils::write_atomic`], in the
+    /// format its extension says.
+    pub fn save(&self, path: &Path) -> Result<(), IndexFileError> {
+        let contents = match Format::from_path(path) {
+            Some(Format::Json) => serde_json::to_string_pretty(self)?,
+            Some(Format::Yaml) => serde_yaml::to_string(self)?,
+            _ => toml::to_string_pretty(self)?,
+        };
+        utils::write_atomic(path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// What it takes to get from this index to `other`.
+    pub fn diff<'a>(&'a self, other: &'a TemplateIndex) -> IndexDiff<'a> {
+        let mut diff = IndexDiff::default();
+
+        for old in self.templates.iter() {
+            match other.templates.get(old) {
+                Some(new) => {
+                    let fields = changed_fields(old, new);
+                    if !fields.is_empty() {
+                        diff.changed.push(TemplateChange { old, new, fields });
+                    }
+                }
+                None => diff.removed.push(old),
+            }
+        }
+
+        diff.added = other
+            .templates
+            .iter()
+            .filter(|it| !self.templates.contains(*it))
+            .collect();
+
+        diff
+    }
+
+    /// Adds the templates and includes of `other` to this index, resolving
+    /// conflicts according to `strategy`. Templates that are in both but
+    /// identical aren't conflicts. On error, the index is left unchanged.
+    pub fn merge(
+        &mut self,
+        other: TemplateIndex,
+        strategy: MergeStrategy,
+    ) -> Result<(), MergeConflictError> {
+        let conflicts = |t: &Template| {
             self.templates
-                .iter()
-                .map(|t| {
-                    let n = t.name().contains(term);
-                    let desc = t.description().map_or(false, |d| d.contains(term));
-                    if n && desc {
-                        (Some(t), (None, None))
-                    } else if n {
-                        (None, (Some(t), None))
-                    } else if desc {
-                        (None, (None, Some(t)))
-                    } else {
-                        (None, (None, None))
+                .get(t)
+                .is_some_and(|it| !changed_fields(it, t).is_empty())
+        };
+
+        if strategy == MergeStrategy::Error {
+            if let Some(t) = other.templates.iter().find(|t| conflicts(t)) {
+                return Err(MergeConflictError {
+                    template: t.versioned_name(),
+                });
+            }
+        }
+
+        for include in other.includes {
+            if !self.includes.iter().any(|it| it.id() == include.id()) {
+                self.includes.push(include);
+            }
+        }
+
+        for mut t in other.templates {
+            if !self.templates.contains(&t) {
+                self.templates.insert(t);
+                continue;
+            }
+
+            match strategy {
+                MergeStrategy::Error | MergeStrategy::PreferSelf => {}
+                MergeStrategy::PreferOther => {
+                    self.templates.replace(t);
+                }
+                MergeStrategy::Rename => {
+                    if changed_fields(self.templates.get(&t).unwrap(), &t).is_empty() {
+                        continue;
                     }
-                })
-                .unzip();
 
-        fn idnt<T>(v: T) -> T {
-            v
+                    let name = t.name().to_string();
+                    for n in 2.. {
+                        t.set_name(format!("{}-{}", name, n));
+                        if !self.templates.contains(&t) {
+                            break;
+                        }
+                    }
+                    self.templates.insert(t);
+                }
+            }
         }
 
-        FindResult {
-            name_and_description: name_and_description.into_iter().filter_map(idnt).collect(),
-            name_only: name_only.into_iter().filter_map(idnt).collect(),
-            description_only: description_only.into_iter().filter_map(idnt).collect(),
+        Ok(())
+    }
+
+    pub fn stats(&self) -> IndexStats<'_> {
+        let mut stats = IndexStats {
+            total: self.templates.len(),
+            ..IndexStats::default()
+        };
+
+        for t in self.templates.iter() {
+            let source = match t {
+                Template::Repo { repo, .. } => repo.git_provider.simple_name(),
+                Template::Local { .. } => "local",
+                Template::Archive { .. } => "archive",
+            };
+            *stats.by_source.entry(source).or_default() += 1;
+
+            let setup = t.setup().map_or("none", SetupKind::name);
+            *stats.by_setup.entry(setup).or_default() += 1;
+
+            for tag in t.tags() {
+                *stats.by_tag.entry(tag).or_default() += 1;
+            }
+
+            if t.description().is_none() {
+                stats.missing_description.push(t);
+            }
+        }
+
+        stats
+    }
+
+    /// Finds templates whose name, description or tags contain `term`,
+    /// ignoring case.
+    pub fn find<'a>(&'a self, term: &str) -> FindResult<'a> {
+        self.find_with(term, MatchMode::default())
+    }
+
+    /// Like [`TemplateIndex::find`]; matches are ordered by [`MatchKind`],
+    /// then as listed in the index.
+    pub fn find_with<'a>(&'a self, term: &str, mode: MatchMode) -> FindResult<'a> {
+        let mut matches = self
+            .templates
+            .iter()
+            .filter_map(|t| {
+                let n = mode.matches(t.name(), term);
+                let desc = t.description().is_some_and(|d| mode.matches(d, term));
+                let kind = if n && desc {
+                    MatchKind::NameAndDescription
+                } else if n {
+                    MatchKind::Name
+                } else if desc {
+                    MatchKind::Description
+                } else if t.tags().iter().any(|tag| mode.matches(tag, term)) {
+                    MatchKind::Tags
+                } else {
+                    return None;
+                };
+
+                Some((t, kind))
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|&(_, kind)| kind);
+
+        let mut result = FindResult::default();
+        for (t, kind) in matches {
+            result.push(t, kind, mode.score(t.name(), term));
         }
+
+        result
     }
 
+    /// Finds a template by `name` or `name@version`, see
+    /// [`TemplateIndex::find_version`].
     pub fn find_exact<'a>(&'a self, name: &str) -> Option<&'a Template> {
-        self.templates.iter().find(|it| it.name() == name)
+        match name.split_once('@') {
+            Some((name, version)) => self.find_version(name, Some(version)),
+            None => self.find_version(name, None),
+        }
     }
+
+    /// Finds the newest version of the template `name` matching `version`;
+    /// no version, like `latest`, picks the newest one. Unversioned entries
+    /// are older than any version, and an invalid version matches nothing.
+    pub fn find_version<'a>(&'a self, name: &str, version: Option<&str>) -> Option<&'a Template> {
+        let spec = match version {
+            Some(version) => VersionSpec::parse(version).ok()?,
+            None => VersionSpec::Latest,
+        };
+
+        // templates are ordered by name, then version
+        self.templates
+            .iter()
+            .rev()
+            .find(|it| it.name() == name && spec.matches(it.version()))
+    }
+}
+
+fn changed_fields(old: &Template, new: &Template) -> Vec<String> {
+    let table = |t: &Template| match toml::Value::try_from(t) {
+        Ok(toml::Value::Table(table)) => table,
+        _ => toml::value::Table::new(),
+    };
+    let (old, new) = (table(old), table(new));
+
+    old.keys()
+        .chain(new.keys().filter(|it| !old.contains_key(*it)))
+        .filter(|it| old.get(*it) != new.get(*it))
+        .cloned()
+        .collect()
 }
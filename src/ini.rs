@@ -0,0 +1,96 @@
+//! Line-based editing of INI-style files (setup.cfg, .editorconfig,
+//! .gitconfig), leaving every line but the edited one untouched.
+
+fn section_header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    line.strip_prefix('[')?.strip_suffix(']').map(str::trim)
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with(';') || line.starts_with('#')
+}
+
+/// Splits `key = value` into the key and everything up to the value.
+fn key_of(line: &str) -> Option<(&str, &str)> {
+    if is_comment(line) || section_header(line).is_some() {
+        return None;
+    }
+
+    let eq = line.find('=')?;
+    let after_eq = &line[eq + 1..];
+    let value_start = eq + 1 + (after_eq.len() - after_eq.trim_start().len());
+
+    Some((line[..eq].trim(), &line[..value_start]))
+}
+
+/// Sets `key` to `value` in `section` (`None` for keys before the first
+/// section), adding the key or section if they don't exist.
+pub fn set(doc: &str, section: Option<&str>, key: &str, value: &str) -> String {
+    let mut lines = doc.lines().map(str::to_string).collect::<Vec<_>>();
+
+    let mut current = None;
+    // index of the last non-blank line of the wanted section
+    let mut section_end = None;
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        if let Some(header) = section_header(line) {
+            current = Some(header.to_string());
+            if current.as_deref() == section {
+                section_end = Some(i);
+            }
+            continue;
+        }
+
+        if current.as_deref() != section {
+            continue;
+        }
+
+        if let Some((line_key, prefix)) = key_of(line) {
+            if line_key == key {
+                *line = format!("{}{}", prefix, value);
+                return join(lines, doc);
+            }
+        }
+
+        if !line.trim().is_empty() {
+            section_end = Some(i);
+        }
+    }
+
+    let entry = format!("{} = {}", key, value);
+
+    match (section, section_end) {
+        (_, Some(end)) => lines.insert(end + 1, entry),
+        (None, None) => lines.insert(0, entry),
+        (Some(section), None) => {
+            if lines.last().is_some_and(|it| !it.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", section));
+            lines.push(entry);
+        }
+    }
+
+    join(lines, doc)
+}
+
+/// Like [`set`], with the path given as `key` or `section/key`; section
+/// names may contain `/` themselves (editorconfig globs), so only the last
+/// segment is the key.
+pub fn set_path(doc: &str, path: &[&str], value: &str) -> String {
+    let path = path.join("/");
+
+    match path.rsplit_once('/') {
+        Some((section, key)) => set(doc, Some(section), key, value),
+        None => set(doc, None, &path, value),
+    }
+}
+
+fn join(lines: Vec<String>, original: &str) -> String {
+    let mut out = lines.join("\n");
+    if original.is_empty() || original.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,807 @@
+//! Editing JSON-with-comments files (tsconfig.json, .vscode/settings.json)
+//! in place: values are spliced into the original text, so comments,
+//! trailing commas and formatting outside the edited value survive.
+
+use crate::error::JsoncError;
+
+enum Node {
+    Object {
+        start: usize,
+        end: usize,
+        members: Vec<Member>,
+        /// position of a comma after the last member, if there is one.
+        trailing_comma: Option<usize>,
+    },
+    Array {
+        start: usize,
+        end: usize,
+        items: Vec<Node>,
+        trailing_comma: Option<usize>,
+    },
+    Scalar {
+        start: usize,
+        end: usize,
+    },
+}
+
+/// `start..end` of a member (key included) or item.
+type Span = (usize, usize);
+
+struct Member {
+    key: String,
+    key_start: usize,
+    value: Node,
+}
+
+impl Node {
+    fn start(&self) -> usize {
+        match self {
+            Node::Object { start, .. } | Node::Array { start, .. } | Node::Scalar { start, .. } => {
+                *start
+            }
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            Node::Object { end, .. } | Node::Array { end, .. } | Node::Scalar { end, .. } => *end,
+        }
+    }
+
+    /// Spans of the members / items of a container, and the position of its
+    /// trailing comma.
+    fn entries(&self) -> Option<(Vec<Span>, Option<usize>)> {
+        match self {
+            Node::Object {
+                members,
+                trailing_comma,
+                ..
+            } => Some((
+                members
+                    .iter()
+                    .map(|it| (it.key_start, it.value.end()))
+                    .collect(),
+                *trailing_comma,
+            )),
+            Node::Array {
+                items,
+                trailing_comma,
+                ..
+            } => Some((
+                items.iter().map(|it| (it.start(), it.end())).collect(),
+                *trailing_comma,
+            )),
+            Node::Scalar { .. } => None,
+        }
+    }
+
+    fn get(&self, segment: &str) -> Result<Option<(usize, &Node)>, JsoncError> {
+        match self {
+            Node::Object { members, .. } => Ok(members
+                .iter()
+                .enumerate()
+                .find(|(_, it)| it.key == segment)
+                .map(|(i, it)| (i, &it.value))),
+            Node::Array { items, .. } => Ok(segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| items.get(i).map(|it| (i, it)))),
+            Node::Scalar { .. } => Err(JsoncError::NotAContainer(segment.to_string())),
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    fn error(&self, message: &'static str) -> JsoncError {
+        JsoncError::Syntax {
+            offset: self.pos,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), JsoncError> {
+        loop {
+            let rest = &self.src[self.pos..];
+
+            if rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                match comment.find("*/") {
+                    Some(end) => self.pos += end + 4,
+                    None => return Err(self.error("unterminated comment")),
+                }
+            } else if let Some(c) = rest.chars().next().filter(|c| c.is_whitespace()) {
+                self.pos += c.len_utf8();
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Node, JsoncError> {
+        let node = self.parse_value()?;
+        self.skip_trivia()?;
+
+        if self.pos != self.src.len() {
+            return Err(self.error("trailing characters"));
+        }
+
+        Ok(node)
+    }
+
+    fn parse_value(&mut self) -> Result<Node, JsoncError> {
+        self.skip_trivia()?;
+
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => {
+                let (start, end) = self.parse_string()?;
+                Ok(Node::Scalar { start, end })
+            }
+            Some(_) => {
+                let start = self.pos;
+                let len = self.src[start..]
+                    .find(|c: char| c.is_whitespace() || ",:]}/".contains(c))
+                    .unwrap_or(self.src.len() - start);
+                let literal = &self.src[start..start + len];
+
+                if serde_json::from_str::<serde_json::Value>(literal).is_err() {
+                    return Err(self.error("invalid literal"));
+                }
+
+                self.pos += len;
+                Ok(Node::Scalar {
+                    start,
+                    end: self.pos,
+                })
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<(usize, usize), JsoncError> {
+        let start = self.pos;
+        let mut escaped = false;
+
+        for (i, c) in self.src[start + 1..].char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    self.pos = start + 1 + i + 1;
+                    return Ok((start, self.pos));
+                }
+                _ => {}
+            }
+        }
+
+        Err(self.error("unterminated string"))
+    }
+
+    fn parse_object(&mut self) -> Result<Node, JsoncError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let mut members = Vec::new();
+        let mut trailing_comma = None;
+
+        loop {
+            self.skip_trivia()?;
+
+            if self.peek() == Some(b'}') {
+                self.pos += 1;
+                break;
+            }
+
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key"));
+            }
+
+            let (key_start, key_end) = self.parse_string()?;
+            let key = serde_json::from_str::<String>(&self.src[key_start..key_end])
+                .map_err(|_| self.error("invalid key"))?;
+
+            self.skip_trivia()?;
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.pos += 1;
+
+            let value = self.parse_value()?;
+            members.push(Member {
+                key,
+                key_start,
+                value,
+            });
+
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(b',') => {
+                    trailing_comma = Some(self.pos);
+                    self.pos += 1;
+                }
+                Some(b'}') => trailing_comma = None,
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+
+        Ok(Node::Object {
+            start,
+            end: self.pos,
+            members,
+            trailing_comma,
+        })
+    }
+
+    fn parse_array(&mut self) -> Result<Node, JsoncError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let mut items = Vec::new();
+        let mut trailing_comma = None;
+
+        loop {
+            self.skip_trivia()?;
+
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                break;
+            }
+
+            items.push(self.parse_value()?);
+
+            self.skip_trivia()?;
+            match self.peek() {
+                Some(b',') => {
+                    trailing_comma = Some(self.pos);
+                    self.pos += 1;
+                }
+                Some(b']') => trailing_comma = None,
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+
+        Ok(Node::Array {
+            start,
+            end: self.pos,
+            items,
+            trailing_comma,
+        })
+    }
+}
+
+/// A replacement of `start..end` in the document.
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+impl Edit {
+    fn apply(&self, doc: &str) -> String {
+        format!("{}{}{}", &doc[..self.start], self.text, &doc[self.end..])
+    }
+}
+
+/// Sets the value at `path` to `value` (JSON text), creating any missing
+/// object keys along the way.
+pub fn set(doc: &str, path: &[&str], value: &str) -> Result<String, JsoncError> {
+    let value = value.trim();
+    Parser::new(value).parse_document()?;
+
+    let root = Parser::new(doc).parse_document()?;
+    let edit = plan_set(doc, &root, path, value)?;
+
+    Ok(edit.apply(doc))
+}
+
+/// The source text of the value at `path`, if there is one.
+pub fn get<'a>(doc: &'a str, path: &[&str]) -> Result<Option<&'a str>, JsoncError> {
+    let root = Parser::new(doc).parse_document()?;
+
+    Ok(find(&root, path)?.map(|node| &doc[node.start()..node.end()]))
+}
+
+/// Removes the member or item at `path`, together with its comma. Deleting
+/// something that doesn't exist leaves the document as it is.
+pub fn delete(doc: &str, path: &[&str]) -> Result<String, JsoncError> {
+    let (last, parent_path) = path.split_last().ok_or(JsoncError::DeleteRoot)?;
+
+    let root = Parser::new(doc).parse_document()?;
+    let parent = match find(&root, parent_path)? {
+        Some(parent) => parent,
+        None => return Ok(doc.to_string()),
+    };
+    let index = match parent.get(last)? {
+        Some((index, _)) => index,
+        None => return Ok(doc.to_string()),
+    };
+
+    let (entries, trailing_comma) = parent.entries().unwrap();
+    let (start, end) = entries[index];
+
+    let edit = if entries.len() == 1 {
+        // keep just the brackets
+        Edit {
+            start: parent.start() + 1,
+            end: parent.end() - 1,
+            text: String::new(),
+        }
+    } else if index + 1 < entries.len() {
+        remove_lines(doc, start, comma_after(doc, end)? + 1)
+    } else if let Some(comma) = trailing_comma {
+        remove_lines(doc, start, comma + 1)
+    } else {
+        // the last one goes with the comma before it
+        Edit {
+            start: comma_after(doc, entries[index - 1].1)?,
+            end,
+            text: String::new(),
+        }
+    };
+
+    Ok(edit.apply(doc))
+}
+
+/// Inserts `value` (JSON text) into the array at `path`, before the item at
+/// `index`, or at the end.
+pub fn insert(
+    doc: &str,
+    path: &[&str],
+    index: Option<usize>,
+    value: &str,
+) -> Result<String, JsoncError> {
+    let value = value.trim();
+    Parser::new(value).parse_document()?;
+
+    let root = Parser::new(doc).parse_document()?;
+    let array = match find(&root, path)? {
+        Some(array @ Node::Array { .. }) => array,
+        Some(_) => return Err(JsoncError::NotAnArray(path.join("/"))),
+        None => return Err(JsoncError::NotFound(path.join("/"))),
+    };
+
+    let (start, end) = (array.start(), array.end());
+    let (entries, trailing_comma) = array.entries().unwrap();
+    let index = index.unwrap_or(entries.len());
+
+    let edit = match entries.get(index) {
+        Some(&(item_start, _)) => {
+            let indent = line_indent(doc, item_start);
+            let text = if starts_line(doc, item_start) {
+                format!("{},\n{}", value, indent)
+            } else {
+                format!("{}, ", value)
+            };
+
+            Edit {
+                start: item_start,
+                end: item_start,
+                text,
+            }
+        }
+        None if index > entries.len() => return Err(JsoncError::NotFound(index.to_string())),
+        None => match entries.last() {
+            Some(&(last_start, last_end)) => append_after(
+                doc,
+                (last_start, last_end),
+                trailing_comma,
+                !doc[start..end].contains('\n'),
+                value,
+            ),
+            None if doc[start + 1..end - 1].trim().is_empty() => Edit {
+                start,
+                end,
+                text: format!("[{}]", value),
+            },
+            None => Edit {
+                start: start + 1,
+                end: start + 1,
+                text: format!("{}, ", value),
+            },
+        },
+    };
+
+    Ok(edit.apply(doc))
+}
+
+fn find<'a>(node: &'a Node, path: &[&str]) -> Result<Option<&'a Node>, JsoncError> {
+    match path.split_first() {
+        Some((segment, rest)) => match node.get(segment)? {
+            Some((_, child)) => find(child, rest),
+            None => Ok(None),
+        },
+        None => Ok(Some(node)),
+    }
+}
+
+/// Position of the comma following the value ending at `pos`.
+fn comma_after(doc: &str, pos: usize) -> Result<usize, JsoncError> {
+    let mut parser = Parser { src: doc, pos };
+    parser.skip_trivia()?;
+    Ok(parser.pos)
+}
+
+fn starts_line(doc: &str, pos: usize) -> bool {
+    let line_start = doc[..pos].rfind('\n').map_or(0, |it| it + 1);
+    doc[line_start..pos].trim().is_empty()
+}
+
+/// Removes `start..end`; when that is all there is on its line, the whole
+/// line goes.
+fn remove_lines(doc: &str, start: usize, end: usize) -> Edit {
+    let line_start = doc[..start].rfind('\n').map_or(0, |it| it + 1);
+
+    if let Some(newline) = doc[end..].find('\n') {
+        if starts_line(doc, start) && doc[end..end + newline].trim().is_empty() {
+            return Edit {
+                start: line_start,
+                end: end + newline + 1,
+                text: String::new(),
+            };
+        }
+    }
+
+    let spaces = doc[end..].len() - doc[end..].trim_start_matches(' ').len();
+    Edit {
+        start,
+        end: end + spaces,
+        text: String::new(),
+    }
+}
+
+fn plan_set(doc: &str, node: &Node, path: &[&str], value: &str) -> Result<Edit, JsoncError> {
+    let (segment, rest) = match path.split_first() {
+        Some(it) => it,
+        None => {
+            return Ok(Edit {
+                start: node.start(),
+                end: node.end(),
+                text: value.to_string(),
+            })
+        }
+    };
+
+    match node {
+        Node::Object { members, .. } => match members.iter().find(|it| it.key == *segment) {
+            Some(member) => plan_set(doc, &member.value, rest, value),
+            None => Ok(insert_member(doc, node, segment, &nested(rest, value))),
+        },
+        Node::Array { items, .. } => match segment.parse::<usize>().ok().and_then(|i| items.get(i))
+        {
+            Some(item) => plan_set(doc, item, rest, value),
+            None => Err(JsoncError::NotFound(segment.to_string())),
+        },
+        Node::Scalar { .. } => Err(JsoncError::NotAContainer(segment.to_string())),
+    }
+}
+
+/// Wraps `value` in objects for each of the keys in `path`.
+fn nested(path: &[&str], value: &str) -> String {
+    path.iter().rev().fold(value.to_string(), |value, key| {
+        format!("{{ {}: {} }}", serde_json::to_string(key).unwrap(), value)
+    })
+}
+
+fn line_indent(doc: &str, pos: usize) -> &str {
+    let line_start = doc[..pos].rfind('\n').map_or(0, |it| it + 1);
+    let line = &doc[line_start..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn insert_member(doc: &str, object: &Node, key: &str, value: &str) -> Edit {
+    let (start, end, members, trailing_comma) = match object {
+        Node::Object {
+            start,
+            end,
+            members,
+            trailing_comma,
+        } => (*start, *end, members, *trailing_comma),
+        _ => unreachable!(),
+    };
+
+    let member = format!("{}: {}", serde_json::to_string(key).unwrap(), value);
+    let single_line = !doc[start..end].contains('\n');
+
+    let last = match members.last() {
+        Some(last) => last,
+        None if doc[start + 1..end - 1].trim().is_empty() => {
+            let indent = line_indent(doc, start);
+            return Edit {
+                start,
+                end,
+                text: format!("{{\n{}  {}\n{}}}", indent, member, indent),
+            };
+        }
+        None => {
+            // only comments inside; keep them after the new member
+            let indent = line_indent(doc, start);
+            return Edit {
+                start: start + 1,
+                end: start + 1,
+                text: format!("\n{}  {},", indent, member),
+            };
+        }
+    };
+
+    append_after(
+        doc,
+        (last.key_start, last.value.end()),
+        trailing_comma,
+        single_line,
+        &member,
+    )
+}
+
+/// Adds `entry` after the last entry of a container.
+fn append_after(
+    doc: &str,
+    (last_start, last_end): Span,
+    trailing_comma: Option<usize>,
+    single_line: bool,
+    entry: &str,
+) -> Edit {
+    let indent = line_indent(doc, last_start);
+
+    match (trailing_comma, single_line) {
+        (Some(comma), true) => Edit {
+            start: comma + 1,
+            end: comma + 1,
+            text: format!(" {},", entry),
+        },
+        (Some(comma), false) => Edit {
+            start: comma + 1,
+            end: comma + 1,
+            text: format!("\n{}{},", indent, entry),
+        },
+        (None, true) => Edit {
+            start: last_end,
+            end: last_end,
+            text: format!(", {}", entry),
+        },
+        (None, false) => Edit {
+            start: last_end,
+            end: last_end,
+            text: format!(",\n{}{}", indent, entry),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = r#"{
+  // the formatter
+  "editor.formatOnSave": true, // on every save
+  /* which one */
+  "editor.defaultFormatter": "rust-lang.rust-analyzer",
+  "files.exclude": {
+    "target": true,
+  },
+}
+"#;
+
+    #[test]
+    fn reads_past_comments_and_trailing_commas() {
+        assert_eq!(
+            get(SETTINGS, &["editor.formatOnSave"]).unwrap(),
+            Some("true")
+        );
+        assert_eq!(
+            get(SETTINGS, &["files.exclude", "target"]).unwrap(),
+            Some("true")
+        );
+        assert_eq!(get("[1, /* two */ 2,]", &["1"]).unwrap(), Some("2"));
+        assert_eq!(get(SETTINGS, &["missing"]).unwrap(), None);
+        assert!(matches!(
+            get(SETTINGS, &["editor.formatOnSave", "x"]),
+            Err(JsoncError::NotAContainer(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_documents() {
+        for doc in ["{", "{\"a\" 1}", "[1 2]", "/* unterminated", "{} {}"] {
+            assert!(
+                matches!(get(doc, &[]), Err(JsoncError::Syntax { .. })),
+                "{}",
+                doc
+            );
+        }
+    }
+
+    #[test]
+    fn set_keeps_comments_next_to_the_value() {
+        let edited = set(SETTINGS, &["editor.formatOnSave"], "false").unwrap();
+        assert_eq!(edited, SETTINGS.replace("Save\": true,", "Save\": false,"));
+
+        let edited = set(
+            SETTINGS,
+            &["editor.defaultFormatter"],
+            "\"esbenp.prettier-vscode\"",
+        );
+        assert_eq!(
+            edited.unwrap(),
+            SETTINGS.replace("\"rust-lang.rust-analyzer\"", "\"esbenp.prettier-vscode\"")
+        );
+    }
+
+    #[test]
+    fn set_adds_missing_members() {
+        let edited = set(SETTINGS, &["files.exclude", "node_modules"], "true").unwrap();
+        assert_eq!(
+            edited,
+            SETTINGS.replace(
+                "\"target\": true,\n",
+                "\"target\": true,\n    \"node_modules\": true,\n"
+            )
+        );
+
+        assert_eq!(
+            set(r#"{"a": 1}"#, &["b"], "2").unwrap(),
+            r#"{"a": 1, "b": 2}"#
+        );
+        assert_eq!(
+            set("{\n  \"a\": 1\n}", &["b"], "2").unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": 2\n}"
+        );
+    }
+
+    #[test]
+    fn set_creates_nested_keys() {
+        assert_eq!(
+            set("{}", &["a", "b", "c"], "1").unwrap(),
+            "{\n  \"a\": { \"b\": { \"c\": 1 } }\n}"
+        );
+        assert_eq!(
+            set("{\n  \"a\": {}\n}", &["a", "b", "c"], "1").unwrap(),
+            "{\n  \"a\": {\n    \"b\": { \"c\": 1 }\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn set_into_empty_objects() {
+        assert_eq!(set("{}", &["a"], "1").unwrap(), "{\n  \"a\": 1\n}");
+        assert_eq!(
+            set("{ /* nothing yet */ }", &["a"], "1").unwrap(),
+            "{\n  \"a\": 1, /* nothing yet */ }"
+        );
+    }
+
+    #[test]
+    fn set_checks_the_value() {
+        assert!(matches!(
+            set("{}", &["a"], "{"),
+            Err(JsoncError::Syntax { .. })
+        ));
+        assert!(matches!(
+            set("[1]", &["3"], "1"),
+            Err(JsoncError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn delete_first_middle_and_last_members() {
+        let doc = "{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": 3\n}";
+        assert_eq!(
+            delete(doc, &["a"]).unwrap(),
+            "{\n  \"b\": 2,\n  \"c\": 3\n}"
+        );
+        assert_eq!(
+            delete(doc, &["b"]).unwrap(),
+            "{\n  \"a\": 1,\n  \"c\": 3\n}"
+        );
+        assert_eq!(
+            delete(doc, &["c"]).unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": 2\n}"
+        );
+
+        let doc = r#"{"a": 1, "b": 2, "c": 3}"#;
+        assert_eq!(delete(doc, &["a"]).unwrap(), r#"{"b": 2, "c": 3}"#);
+        assert_eq!(delete(doc, &["b"]).unwrap(), r#"{"a": 1, "c": 3}"#);
+        assert_eq!(delete(doc, &["c"]).unwrap(), r#"{"a": 1, "b": 2}"#);
+    }
+
+    #[test]
+    fn delete_with_trailing_comma() {
+        assert_eq!(delete("[1, 2, 3,]", &["2"]).unwrap(), "[1, 2, ]");
+        assert_eq!(
+            delete(SETTINGS, &["files.exclude"]).unwrap(),
+            SETTINGS.replace("  \"files.exclude\": {\n    \"target\": true,\n  },\n", "")
+        );
+    }
+
+    #[test]
+    fn delete_keeps_comments_of_the_others() {
+        let edited = delete(SETTINGS, &["editor.defaultFormatter"]).unwrap();
+        assert_eq!(
+            edited,
+            SETTINGS.replace(
+                "  \"editor.defaultFormatter\": \"rust-lang.rust-analyzer\",\n",
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn delete_the_only_member() {
+        assert_eq!(
+            delete(r#"{"a": [1]}"#, &["a", "0"]).unwrap(),
+            r#"{"a": []}"#
+        );
+        assert_eq!(delete(r#"{"a": 1}"#, &["a"]).unwrap(), "{}");
+    }
+
+    #[test]
+    fn delete_something_missing() {
+        assert_eq!(delete(SETTINGS, &["missing"]).unwrap(), SETTINGS);
+        assert_eq!(delete(SETTINGS, &["missing", "deeper"]).unwrap(), SETTINGS);
+        assert!(matches!(delete(SETTINGS, &[]), Err(JsoncError::DeleteRoot)));
+    }
+
+    #[test]
+    fn insert_into_empty_arrays() {
+        assert_eq!(insert("[]", &[], None, "1").unwrap(), "[1]");
+        assert_eq!(insert("[ ]", &[], Some(0), "1").unwrap(), "[1]");
+        assert_eq!(
+            insert("[ /* none */ ]", &[], None, "1").unwrap(),
+            "[1,  /* none */ ]"
+        );
+    }
+
+    #[test]
+    fn insert_into_single_line_arrays() {
+        assert_eq!(insert("[1, 3]", &[], Some(1), "2").unwrap(), "[1, 2, 3]");
+        assert_eq!(insert("[1, 2]", &[], Some(0), "0").unwrap(), "[0, 1, 2]");
+        assert_eq!(insert("[1, 2]", &[], None, "3").unwrap(), "[1, 2, 3]");
+        assert_eq!(insert("[1, 2,]", &[], None, "3").unwrap(), "[1, 2, 3,]");
+    }
+
+    #[test]
+    fn insert_into_multi_line_arrays() {
+        let doc = "{\n  \"a\": [\n    1, // one\n    3\n  ]\n}";
+        assert_eq!(
+            insert(doc, &["a"], Some(1), "2").unwrap(),
+            "{\n  \"a\": [\n    1, // one\n    2,\n    3\n  ]\n}"
+        );
+        assert_eq!(
+            insert(doc, &["a"], None, "4").unwrap(),
+            "{\n  \"a\": [\n    1, // one\n    3,\n    4\n  ]\n}"
+        );
+        assert_eq!(
+            insert("[\n  1,\n]", &[], None, "2").unwrap(),
+            "[\n  1,\n  2,\n]"
+        );
+    }
+
+    #[test]
+    fn insert_checks_the_target() {
+        assert!(matches!(
+            insert("[1]", &[], Some(2), "1"),
+            Err(JsoncError::NotFound(_))
+        ));
+        assert!(matches!(
+            insert("{}", &[], None, "1"),
+            Err(JsoncError::NotAnArray(_))
+        ));
+        assert!(matches!(
+            insert("{}", &["a"], None, "1"),
+            Err(JsoncError::NotFound(_))
+        ));
+    }
+}
@@ -1,10 +1,41 @@
+pub mod cancel;
+pub mod compose;
 pub mod config;
+pub mod context;
+pub mod dotenv;
+pub mod edit;
 pub mod find_result;
+pub mod generate;
+pub mod hooks;
+pub mod http;
 pub mod index;
+pub mod ini;
+pub mod jsonc;
+pub mod lock;
+pub mod marker;
+pub mod observer;
+pub mod patches;
+#[cfg(feature = "python")]
+mod python;
+pub mod registry;
 pub mod remote_index;
+pub mod rename;
+pub mod render;
 pub mod repo_def;
 pub mod ro;
+pub mod scan;
+mod schema;
+pub mod secrets;
+#[cfg(feature = "network")]
+pub mod serve;
 pub mod template;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod utils;
+
+pub use context::{Thorc, ThorcBuilder};
+pub use error::Error;
@@ -0,0 +1,52 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::LockFileError, template::Template};
+
+pub const LOCK_FILE_NAME: &str = "thorc.lock";
+
+/// Everything needed to recreate a generated project exactly as it was.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockFile {
+    pub project_name: String,
+
+    /// commit the template archive was built from, if the archive recorded one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+
+    /// hash of the template tree the project was generated from.
+    pub checksum: String,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+
+    pub template: Template,
+}
+
+impl LockFile {
+    pub fn load(path: &Path) -> Result<Self, LockFileError> {
+        let contents = fs::read_to_string(path)?;
+        let lock = toml::from_str(&contents)?;
+
+        Ok(lock)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LockFileError> {
+        let contents = toml::to_string_pretty(self)?;
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    /// Returns the template pinned to the recorded commit, when there is one.
+    pub fn pinned_template(&self) -> Template {
+        let mut template = self.template.clone();
+
+        if let (Template::Repo { repo, .. }, Some(commit)) = (&mut template, &self.commit) {
+            repo.commit = Some(commit.clone());
+        }
+
+        template
+    }
+}
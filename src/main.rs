@@ -1,32 +1,79 @@
+mod tui;
+
 use std::{
-    fmt::Write,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
     fs,
-    io::{self, BufRead, Write as IoWrite},
-    path::{Components, Path, PathBuf},
-    process::{Command, ExitStatus, Stdio},
+    io::{self, BufRead, IsTerminal, Write as IoWrite},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
 use clap::Parser;
-use directories::ProjectDirs;
+use indicatif::ProgressBar;
+use signal_hook::consts::SIGINT;
 use thorc::{
-    config::Config,
-    index::TemplateIndex,
-    remote_index::RemoteIndex,
-    repo_def::{GitProvider, RepoDef},
-    ro::RO,
-    template::check_template_name,
-    template::{SetupKind, Template},
+    cancel::CancelToken,
+    compose::{ComposeFile, COMPOSE_FILE_NAME},
+    config::{find_project_config, Config},
+    context::IndexName,
+    context::{default_cache_dir, default_config_file, default_local_index_file, ThorcBuilder},
+    edit::{self, parse_path, split_index, Op},
+    error::{DownloadError, EditError, RunHookError},
+    find_result::{Match, MatchKind, MatchMode},
+    generate::{self, generate_project_with, ConflictResolver, GenerateOptions},
+    hooks::{Hook, HookExecutor, HookProgram, ProcessExecutor, DEFAULT_HOOKS_DIR},
+    index::{MergeStrategy, TemplateIndex},
+    lock::LockFile,
+    marker::{self, Position},
+    observer::{Observer, Phase},
+    patches::PatchSpec,
+    remote_index::{IndexSource, Refresh, RemoteIndex},
+    repo_def::{self, Fetch, GitProvider, RepoDef},
+    scan,
+    template::{check_qualified_template_name, check_template_name_with},
+    template::{Metadata, Template},
+    utils::Conflict,
+    Thorc,
 };
 
 #[derive(Parser)]
 struct Opts {
+    /// config file; `$THORC_CONFIG` if not given.
     #[clap(short, long, parse(from_os_str))]
     config: Option<PathBuf>,
 
+    /// local template index; `$THORC_LOCAL_INDEX` if not given. Templates
+    /// are cached in `$THORC_CACHE_DIR` if it is set.
     #[clap(short = 'i', long = "index", parse(from_os_str))]
     local_templates_index: Option<PathBuf>,
 
+    /// `[profile.<name>]` of the config to use; `$THORC_PROFILE` if not
+    /// given.
+    #[clap(short, long)]
+    profile: Option<String>,
+
+    /// keep the config, the local index and the cache next to the thorc
+    /// binary; the same as `THORC_DIRS=portable`.
+    #[clap(long)]
+    portable: bool,
+
+    /// don't show progress while fetching indexes and templates.
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// log more: `-v` for debug output, with timings and cache hits, `-vv`
+    /// for everything.
+    #[clap(short, long, parse(from_occurrences))]
+    verbose: u64,
+
     #[clap(subcommand)]
     subcmd: Subcommand,
 }
@@ -36,31 +83,89 @@ enum Subcommand {
     AddToIndex(AddToIndexCommand),
     AddLocalToIndex(AddLocalToIndexCommand),
     RemoveFromIndex(RemoveFromIndexCommand),
-    List,
+    List(ListCommand),
+    Show(ShowCommand),
     Find(FindCommand),
+    IndexDiff(IndexDiffCommand),
+    MergeIndex(MergeIndexCommand),
+    IndexStats(IndexStatsCommand),
+    Scan(ScanCommand),
     New(NewCommand),
+    Regenerate(RegenerateCommand),
+    Compose(ComposeCommand),
+    Tui,
+    Serve(ServeCommand),
+    Cache(CacheCommand),
     AddRemoteIndex(AddRemoteIndexCommand),
     RemoveRemoteIndex(RemoveRemoteIndexCommand),
+    SetVariable(SetVariableCommand),
+    UnsetVariable(UnsetVariableCommand),
+    Config(ConfigCommand),
 
     // utils
     EditToml(EditTomlCommand),
     EditJson(EditJsonCommand),
+    GetToml(GetTomlCommand),
+    GetJson(GetJsonCommand),
+    EditYaml(EditYamlCommand),
+    EditJsonc(EditJsoncCommand),
+    EditIni(EditIniCommand),
+    EditEnv(EditEnvCommand),
+    ApplyPatches(ApplyPatchesCommand),
+    ReplaceInFile(ReplaceInFileCommand),
+    RenamePaths(RenamePathsCommand),
+    InsertAfter(InsertTextCommand),
+    InsertBefore(InsertTextCommand),
+    Render(RenderCommand),
+    Cargo(CargoCommand),
 }
 
 #[derive(Parser)]
 pub struct AddToIndexCommand {
-    #[clap(long, parse(try_from_str), default_value = "github")]
-    git_provider: GitProvider,
+    /// `defaults.git_provider` from the config, or github, if not given.
+    #[clap(long, parse(try_from_str))]
+    git_provider: Option<GitProvider>,
+    /// a self-hosted instance of the git provider, like
+    /// `gitlab.example.com`.
+    #[clap(long)]
+    host: Option<String>,
+    /// url of the repo's archive on a forge or server thorc doesn't know,
+    /// with `{user}`, `{repo}` and `{ref}` in it to be replaced.
+    #[clap(
+        long,
+        requires = "tree-url-template",
+        conflicts_with_all = &["git-provider", "host"]
+    )]
+    archive_url_template: Option<String>,
+    /// url to show for the repo, along with `--archive-url-template`.
+    #[clap(long, requires = "archive-url-template")]
+    tree_url_template: Option<String>,
+    /// `defaults.user` from the config if not given; on GitLab, a group
+    /// may have subgroups, like `group/subgroup`.
     #[clap(short, long)]
-    user: String,
+    user: Option<String>,
     #[clap(long)]
     repo: String,
+    /// `defaults.git_ref` from the config, or the repo's default branch, if
+    /// not given.
     #[clap(long)]
-    git_ref: String,
+    git_ref: Option<String>,
+    /// fetch the repo with a shallow `git` clone instead of downloading an
+    /// archive, for refs the provider has no archive of.
+    #[clap(long, conflicts_with = "archive-url-template")]
+    git_clone: bool,
+    /// like `--git-clone`, but over SSH with your keys, for private repos.
+    #[clap(long, conflicts_with_all = &["archive-url-template", "git-clone"])]
+    ssh: bool,
     #[clap(long)]
     issue: Option<usize>,
     #[clap(long)]
     description: Option<String>,
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// lets several versions of the template share its name.
+    #[clap(long = "template-version")]
+    version: Option<semver::Version>,
 
     name: String,
 }
@@ -71,31 +176,106 @@ pub struct AddLocalToIndexCommand {
     path: PathBuf,
     #[clap(long)]
     description: Option<String>,
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// lets several versions of the template share its name.
+    #[clap(long = "template-version")]
+    version: Option<semver::Version>,
     name: String,
 }
 
 #[derive(Parser)]
 pub struct RemoveFromIndexCommand {
+    /// `name` removes every version of the template, `name@version` only
+    /// that one.
     name: String,
 }
 
 #[derive(Parser)]
-pub struct FindCommand {
-    term: String,
+pub struct ListCommand {
+    /// show everything known about each template, not just a summary.
+    #[clap(short, long)]
+    long: bool,
 }
 
-pub enum IndexName {
-    Local,
-    Remote(String),
+/// Shows everything known about a template.
+#[derive(Parser)]
+pub struct ShowCommand {
+    #[clap(short, long, parse(from_str))]
+    index: Option<IndexName>,
+    template_name: String,
 }
 
-impl<'a> From<&'a str> for IndexName {
-    fn from(s: &'a str) -> Self {
-        match s {
-            "local" => IndexName::Local,
-            s => IndexName::Remote(s.to_string()),
-        }
-    }
+/// Lists the templates added, removed and changed between two index files.
+#[derive(Parser)]
+pub struct IndexDiffCommand {
+    #[clap(parse(from_os_str))]
+    old: PathBuf,
+    #[clap(parse(from_os_str))]
+    new: PathBuf,
+}
+
+/// Combines several index files into one, e.g. to publish.
+#[derive(Parser)]
+pub struct MergeIndexCommand {
+    /// what to do about templates in several of the indexes: error,
+    /// prefer-self (the earlier index), prefer-other (the later index) or
+    /// rename.
+    #[clap(long, parse(try_from_str), default_value = "error")]
+    strategy: MergeStrategy,
+    #[clap(short, long, parse(from_os_str))]
+    output: PathBuf,
+    #[clap(parse(from_os_str), required = true)]
+    indexes: Vec<PathBuf>,
+}
+
+/// Counts the templates of an index by source, setup and tag, and lists the
+/// ones without a description.
+#[derive(Parser)]
+pub struct IndexStatsCommand {
+    /// defaults to the local index.
+    #[clap(parse(from_os_str))]
+    index: Option<PathBuf>,
+}
+
+/// Adds every template under a directory to an index, replacing the ones
+/// previously found there; a template is a directory with a
+/// `thor/template.toml` manifest.
+#[derive(Parser)]
+pub struct ScanCommand {
+    #[clap(parse(from_os_str))]
+    dir: PathBuf,
+    /// index to update, created if missing; defaults to the local index.
+    #[clap(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// look for manifests in this directory of templates instead of
+    /// `thor`; defaults to the config's `hooks_dir`.
+    #[clap(long)]
+    hooks_dir: Option<String>,
+}
+
+#[derive(Parser)]
+pub struct FindCommand {
+    /// everything matches if left out, e.g. to list templates by tag.
+    term: Option<String>,
+    /// show at most this many templates.
+    #[clap(long)]
+    limit: Option<usize>,
+    /// only search these indexes; `local` is the local index.
+    #[clap(short, long = "index")]
+    indexes: Vec<String>,
+    /// only show templates with this tag; may be repeated.
+    #[clap(long = "tag")]
+    tags: Vec<String>,
+    /// match the term's case exactly.
+    #[clap(long, conflicts_with = "fuzzy")]
+    case_sensitive: bool,
+    /// match templates containing the term's characters in order, best
+    /// matches first.
+    #[clap(long)]
+    fuzzy: bool,
+    #[clap(long)]
+    no_pager: bool,
 }
 
 #[derive(Parser)]
@@ -105,25 +285,147 @@ pub struct NewCommand {
     template_name: String,
     #[clap(long)]
     project_name: Option<String>,
+    #[clap(parse(from_os_str))]
     directory: PathBuf,
     #[clap(long)]
     allow_dirty: bool,
+    /// when generating into a non-empty directory, ask what to do about each
+    /// conflicting file.
+    #[clap(long)]
+    interactive: bool,
+    /// generate into a non-empty directory, only adding the files it doesn't
+    /// have yet; the existing ones that differ are listed at the end.
+    #[clap(long, conflicts_with = "interactive")]
+    merge: bool,
+    /// generate from the template even if it's deprecated.
+    #[clap(long)]
+    allow_deprecated: bool,
+    /// if the template's repository was renamed or transferred, point its
+    /// entry in the local index at the new one without asking.
+    #[clap(long)]
+    fix: bool,
 }
 
 #[derive(Parser)]
-pub struct AddRemoteIndexCommand {
-    #[clap(long, parse(try_from_str), default_value = "github")]
-    git_provider: GitProvider,
-    #[clap(short, long)]
-    user: String,
+pub struct RegenerateCommand {
+    #[clap(parse(from_os_str))]
+    lock_file: PathBuf,
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
     #[clap(long)]
-    repo: String,
+    allow_dirty: bool,
+}
+
+#[derive(Parser)]
+pub struct ComposeCommand {
+    #[clap(short, long, parse(from_os_str), default_value = COMPOSE_FILE_NAME)]
+    file: PathBuf,
+    #[clap(parse(from_os_str), default_value = ".")]
+    directory: PathBuf,
     #[clap(long)]
-    git_ref: String,
-    #[clap(long, parse(from_os_str))]
+    allow_dirty: bool,
+}
+
+#[derive(Parser)]
+pub struct ServeCommand {
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    /// url clients reach the server at; defaults to the request's Host header.
+    #[clap(long)]
+    public_url: Option<String>,
+    /// indexes to serve, as `name=path/to/index.toml`; defaults to the local index.
+    indexes: Vec<ServedIndex>,
+}
+
+pub struct ServedIndex {
+    name: String,
     path: PathBuf,
+}
+
+impl FromStr for ServedIndex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, path)) => Ok(Self {
+                name: name.to_string(),
+                path: PathBuf::from(path),
+            }),
+            None => Err(format!("expected name=path, got {}", s)),
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct CacheCommand {
+    #[clap(subcommand)]
+    subcmd: CacheSubcommand,
+}
+
+#[derive(Parser)]
+enum CacheSubcommand {
+    Purge(PurgeCacheCommand),
+}
+
+#[derive(Parser)]
+pub struct PurgeCacheCommand {
+    #[clap(short, long, parse(from_str))]
+    index: Option<IndexName>,
+    /// remote index or template whose cache entries to remove.
+    name: String,
+}
+
+#[derive(Parser)]
+pub struct AddRemoteIndexCommand {
+    /// `defaults.git_provider` from the config, or github, if not given.
+    #[clap(long, parse(try_from_str))]
+    git_provider: Option<GitProvider>,
+    /// a self-hosted instance of the git provider, like
+    /// `gitlab.example.com`.
+    #[clap(long)]
+    host: Option<String>,
+    /// url of the repo's archive on a forge or server thorc doesn't know,
+    /// with `{user}`, `{repo}` and `{ref}` in it to be replaced.
+    #[clap(
+        long,
+        requires = "tree-url-template",
+        conflicts_with_all = &["git-provider", "host"]
+    )]
+    archive_url_template: Option<String>,
+    /// url to show for the repo, along with `--archive-url-template`.
+    #[clap(long, requires = "archive-url-template")]
+    tree_url_template: Option<String>,
+    /// `defaults.user` from the config if not given; on GitLab, a group
+    /// may have subgroups, like `group/subgroup`.
+    #[clap(short, long)]
+    user: Option<String>,
+    #[clap(long, required_unless_present_any = &["url", "registry"])]
+    repo: Option<String>,
+    /// `defaults.git_ref` from the config, or the repo's default branch, if
+    /// not given.
+    #[clap(long)]
+    git_ref: Option<String>,
+    /// fetch the repo with a shallow `git` clone instead of downloading an
+    /// archive, for refs the provider has no archive of.
+    #[clap(long, conflicts_with = "archive-url-template")]
+    git_clone: bool,
+    /// like `--git-clone`, but over SSH with your keys, for private repos.
+    #[clap(long, conflicts_with_all = &["archive-url-template", "git-clone"])]
+    ssh: bool,
+    #[clap(long, parse(from_os_str), required_unless_present_any = &["url", "registry"])]
+    path: Option<PathBuf>,
+    /// url of an index file served over http, instead of a repo.
+    #[clap(long, conflicts_with_all = &["host", "archive-url-template", "user", "repo", "git-ref", "git-clone", "ssh", "path", "registry"])]
+    url: Option<String>,
+    /// base url of a registry service, instead of a repo.
+    #[clap(long, conflicts_with_all = &["host", "archive-url-template", "user", "repo", "git-ref", "git-clone", "ssh", "path"])]
+    registry: Option<String>,
     #[clap(long)]
     description: Option<String>,
+    /// how eagerly to download the index again: always, never, or after a
+    /// duration like 30m, 1h or 7d (the default is 1m).
+    #[clap(long, parse(try_from_str))]
+    refresh: Option<Refresh>,
 
     name: String,
 }
@@ -133,53 +435,289 @@ pub struct RemoveRemoteIndexCommand {
     name: String,
 }
 
+/// Sets a variable every generated project gets unless given another, like
+/// `author` or `license`, in the config's `[variables]`.
+#[derive(Parser)]
+pub struct SetVariableCommand {
+    name: String,
+    value: String,
+}
+
+#[derive(Parser)]
+pub struct UnsetVariableCommand {
+    name: String,
+}
+
+#[derive(Parser)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    subcmd: ConfigSubcommand,
+}
+
+#[derive(Parser)]
+enum ConfigSubcommand {
+    Migrate(MigrateConfigCommand),
+}
+
+/// Upgrades the config and the local index to the current schema version,
+/// keeping the originals next to them as `<file>.v<version>.bak`.
+#[derive(Parser)]
+pub struct MigrateConfigCommand {
+    /// Also migrate the project config (`.thorc.toml`) found from the
+    /// current directory, which is otherwise only migrated in memory.
+    #[clap(long)]
+    project: bool,
+}
+
 #[derive(Parser)]
 pub struct EditTomlCommand {
+    #[clap(parse(from_os_str))]
     toml_file: PathBuf,
     objcet_path: ObjectPath,
+    #[clap(flatten)]
+    value: ValueSource,
+    #[clap(flatten)]
+    mode: EditMode,
 }
 
 #[derive(Parser)]
 pub struct EditJsonCommand {
+    #[clap(parse(from_os_str))]
     json_file: PathBuf,
     objcet_path: ObjectPath,
+    #[clap(flatten)]
+    value: ValueSource,
+    #[clap(flatten)]
+    mode: EditMode,
 }
 
-pub struct ObjectPath {
-    pb: PathBuf,
+/// Prints the value at the object path.
+#[derive(Parser)]
+pub struct GetTomlCommand {
+    #[clap(parse(from_os_str))]
+    toml_file: PathBuf,
+    objcet_path: ObjectPath,
+    /// Print strings without quotes.
+    #[clap(long)]
+    raw: bool,
 }
 
-impl FromStr for ObjectPath {
-    type Err = <PathBuf as FromStr>::Err;
+/// Prints the value at the object path.
+#[derive(Parser)]
+pub struct GetJsonCommand {
+    #[clap(parse(from_os_str))]
+    json_file: PathBuf,
+    objcet_path: ObjectPath,
+    /// Print strings without quotes.
+    #[clap(long)]
+    raw: bool,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse().map(|pb| Self { pb })
-    }
+/// Like edit-json, but keeps comments, trailing commas and formatting.
+#[derive(Parser)]
+pub struct EditJsoncCommand {
+    #[clap(parse(from_os_str))]
+    jsonc_file: PathBuf,
+    objcet_path: ObjectPath,
+    #[clap(flatten)]
+    value: ValueSource,
+}
+
+/// Sets `key` or `section/key` in an INI-style file.
+#[derive(Parser)]
+pub struct EditIniCommand {
+    #[clap(parse(from_os_str))]
+    ini_file: PathBuf,
+    objcet_path: ObjectPath,
+    #[clap(flatten)]
+    value: ValueSource,
+}
+
+/// Sets `key` in a dotenv file.
+#[derive(Parser)]
+pub struct EditEnvCommand {
+    #[clap(parse(from_os_str))]
+    env_file: PathBuf,
+    key: String,
+    #[clap(flatten)]
+    value: ValueSource,
+}
+
+#[derive(Parser)]
+pub struct EditYamlCommand {
+    #[clap(parse(from_os_str))]
+    yaml_file: PathBuf,
+    objcet_path: ObjectPath,
+    #[clap(flatten)]
+    value: ValueSource,
+    #[clap(flatten)]
+    mode: EditMode,
+    /// Edit the file even if it has comments; rewriting it drops them.
+    #[clap(long)]
+    drop_comments: bool,
+}
+
+/// Applies every patch in a spec file; files are only written once all of
+/// the patches applied cleanly.
+#[derive(Parser)]
+pub struct ApplyPatchesCommand {
+    #[clap(parse(from_os_str))]
+    spec: PathBuf,
+    #[clap(parse(from_os_str))]
+    target_dir: PathBuf,
+}
+
+/// Finds and replaces text in files; `files` may be glob patterns.
+#[derive(Parser)]
+pub struct ReplaceInFileCommand {
+    pattern: String,
+    replacement: String,
+    #[clap(required = true)]
+    files: Vec<String>,
+    /// Treat the pattern and replacement as plain text instead of a regex
+    /// and a replacement template (`$1`, `${name}`).
+    #[clap(long)]
+    literal: bool,
+    /// Print a diff of the changes instead of writing them.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Inserts lines next to the first line containing a marker, unless they are
+/// already in the file.
+#[derive(Parser)]
+pub struct InsertTextCommand {
+    #[clap(parse(from_os_str))]
+    file: PathBuf,
+    marker: String,
+    text: String,
+}
+
+/// Replaces `{{ variable }}` placeholders in files, in place. Variables come
+/// from the `THORC_VAR_*` environment hooks run with, and from the lock file
+/// if one is given.
+#[derive(Parser)]
+pub struct RenderCommand {
+    #[clap(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+    #[clap(long, parse(from_os_str))]
+    lock_file: Option<PathBuf>,
+}
+
+/// Renames the files and directories under `directory` whose names match
+/// `pattern`.
+#[derive(Parser)]
+pub struct RenamePathsCommand {
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+    pattern: String,
+    replacement: String,
+    /// Treat the pattern and replacement as plain text instead of a regex
+    /// and a replacement template (`$1`, `${name}`).
+    #[clap(long)]
+    literal: bool,
+    /// Print the renames instead of doing them.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+pub struct CargoCommand {
+    #[clap(subcommand)]
+    subcmd: CargoSubcommand,
+}
+
+#[derive(Parser)]
+enum CargoSubcommand {
+    AddWorkspaceMember(AddWorkspaceMemberCommand),
+}
+
+/// Adds a path to `workspace.members`, creating the array if needed.
+#[derive(Parser)]
+pub struct AddWorkspaceMemberCommand {
+    #[clap(long, parse(from_os_str), default_value = "Cargo.toml")]
+    manifest_path: PathBuf,
+    path: String,
+}
+
+/// What edit-toml / edit-json / edit-yaml do with the value; sets it if no
+/// option is given.
+#[derive(Parser)]
+pub struct EditMode {
+    /// Remove the key / array element at the path instead of setting it.
+    #[clap(long, conflicts_with_all = &["append", "insert", "merge"])]
+    delete: bool,
+    /// Push the value onto the end of the array at the path.
+    #[clap(long, conflicts_with_all = &["insert", "merge"])]
+    append: bool,
+    /// Insert the value before the element at the path (`.../<index>`).
+    #[clap(long, conflicts_with = "merge")]
+    insert: bool,
+    /// Merge the value (a table / object) into the one at the path.
+    #[clap(long)]
+    merge: bool,
+    /// Create missing tables / objects on the way to the path (and the array,
+    /// for --append and --insert) instead of failing.
+    #[clap(long)]
+    create_missing: bool,
+}
+
+/// Where the edit commands take the new value from; stdin if neither
+/// option is given.
+#[derive(Parser)]
+pub struct ValueSource {
+    /// The new value.
+    #[clap(long, conflicts_with = "value-file")]
+    value: Option<String>,
+    /// Read the new value from a file.
+    #[clap(long, parse(from_os_str))]
+    value_file: Option<PathBuf>,
 }
 
-const NAME: &'static str = env!("CARGO_PKG_NAME");
-const CONFIG_FILE_NAME: &'static str = concat!(env!("CARGO_PKG_NAME"), ".conf");
+impl ValueSource {
+    fn read(&self) -> String {
+        if let Some(value) = &self.value {
+            return value.clone();
+        }
+
+        if let Some(value_file) = &self.value_file {
+            return fs::read_to_string(value_file).expect("Cannot read value file");
+        }
+
+        let stdin = io::stdin();
+        let mut input_str = String::new();
+
+        for line in stdin.lock().lines() {
+            writeln!(&mut input_str, "{}", line.unwrap()).unwrap();
+        }
 
-fn proj_dirs() -> ProjectDirs {
-    ProjectDirs::from("", "", NAME).unwrap()
+        input_str
+    }
 }
 
-fn config_dir() -> PathBuf {
-    let proj_dirs = proj_dirs();
-    proj_dirs.config_dir().to_owned()
+/// A path into a document, see [`parse_path`].
+pub struct ObjectPath {
+    segments: Vec<String>,
 }
 
-fn cache_dir() -> PathBuf {
-    let proj_dirs = proj_dirs();
-    proj_dirs.cache_dir().to_owned()
+impl FromStr for ObjectPath {
+    type Err = EditError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_path(s).map(|segments| Self { segments })
+    }
 }
 
-fn config_file() -> PathBuf {
-    config_dir().join(CONFIG_FILE_NAME)
+impl ObjectPath {
+    fn strs(&self) -> Vec<&str> {
+        self.segments.iter().map(String::as_str).collect()
+    }
 }
 
-fn local_index_file() -> PathBuf {
-    config_dir().join("local_templates.toml")
+impl fmt::Display for ObjectPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("/"))
+    }
 }
 
 macro_rules! err {
@@ -190,193 +728,918 @@ macro_rules! err {
     };
 }
 
-fn load_config(config: &Option<PathBuf>) -> (PathBuf, Config) {
-    let config_file = config.clone().unwrap_or_else(config_file);
-    let config = fs::read_to_string(&config_file).expect("Cannot read config file");
-    let config = toml::from_str::<Config>(&config).expect("Cannot parse config file");
+impl EditMode {
+    /// The operation to apply, and the path it applies to.
+    fn op<'a, V>(
+        &self,
+        objcet_path: &'a ObjectPath,
+        value: impl FnOnce() -> V,
+    ) -> (Vec<&'a str>, Op<V>) {
+        let path = objcet_path.strs();
+
+        if self.delete {
+            return (path, Op::Delete);
+        }
+        if self.insert {
+            let (parent, index) = split_index(&path).unwrap_or_else(|e| err!("{}", e));
+            return (parent.to_vec(), Op::Insert(index, value()));
+        }
+
+        let op = if self.append {
+            Op::Append(value())
+        } else if self.merge {
+            Op::Merge(value())
+        } else {
+            Op::Set(value())
+        };
+
+        (path, op)
+    }
+
+    fn options(&self) -> edit::Options {
+        edit::Options {
+            create_missing: self.create_missing,
+            ..edit::Options::default()
+        }
+    }
+}
+
+/// The provider `--archive-url-template` and `--tree-url-template` make, if
+/// given.
+fn custom_provider(
+    archive_url_template: Option<String>,
+    tree_url_template: Option<String>,
+) -> Option<GitProvider> {
+    Some(GitProvider::Custom {
+        archive_url_template: archive_url_template?,
+        tree_url_template: tree_url_template?,
+    })
+}
+
+fn fetch_strategy(git_clone: bool, ssh: bool) -> Fetch {
+    match (git_clone, ssh) {
+        (_, true) => Fetch::Ssh,
+        (true, false) => Fetch::Git,
+        (false, false) => Fetch::Archive,
+    }
+}
+
+/// Fills in repo flags that weren't given from the `config`'s defaults, then
+/// built-in ones.
+fn repo_def_with_defaults(
+    config: &Config,
+    git_provider: Option<GitProvider>,
+    host: Option<String>,
+    user: Option<String>,
+    repo: String,
+    git_ref: Option<String>,
+    fetch: Fetch,
+) -> RepoDef {
+    let defaults = config.defaults.clone();
+    let git_ref = git_ref.or(defaults.git_ref).unwrap_or_default();
+    if let Err(err) = repo_def::check_git_ref(&git_ref) {
+        err!("Invalid --git-ref: {}", err);
+    }
 
-    (config_file, config)
+    RepoDef {
+        git_provider: git_provider
+            .or(defaults.git_provider)
+            .unwrap_or(GitProvider::GitHub),
+        host,
+        user: user
+            .or(defaults.user)
+            .unwrap_or_else(|| err!("Pass --user, or set defaults.user in the config")),
+        repo,
+        git_ref,
+        fetch,
+        commit: None,
+    }
 }
 
 fn edit_config<F>(config: &Option<PathBuf>, f: F)
 where
-    F: FnOnce(Config) -> Config,
+    F: FnOnce(&mut Config),
 {
-    let (config_file, config) = load_config(config);
-    let config = f(config);
+    let config_file = config.clone().unwrap_or_else(default_config_file);
 
-    let config_str = toml::to_string_pretty(&config).expect("Couldn't serialize local index");
-    fs::write(&config_file, &config_str).expect("Couldn't write local index");
+    Config::edit(&config_file, f)
+        .unwrap_or_else(|e| err!("Cannot edit {}: {}", config_file.display(), e));
 }
 
 fn load_local_index(local_templates_index: &Option<PathBuf>) -> (PathBuf, TemplateIndex) {
     let local_index_file = local_templates_index
         .clone()
-        .unwrap_or_else(local_index_file);
-    let local_index = fs::read_to_string(&local_index_file).expect("Cannot read local index file");
-    let local_index =
-        toml::from_str::<TemplateIndex>(&local_index).expect("Cannot parse local index file");
+        .unwrap_or_else(default_local_index_file);
+    let local_index = TemplateIndex::load(&local_index_file)
+        .unwrap_or_else(|e| err!("Cannot load {}: {}", local_index_file.display(), e));
 
     (local_index_file, local_index)
 }
 
 fn edit_index<F>(local_templates_index: &Option<PathBuf>, f: F)
 where
-    F: FnOnce(TemplateIndex) -> TemplateIndex,
+    F: FnOnce(&mut TemplateIndex),
 {
-    let (local_index_file, local_index) = load_local_index(local_templates_index);
-    let local_index = f(local_index);
+    let local_index_file = local_templates_index
+        .clone()
+        .unwrap_or_else(default_local_index_file);
 
-    let index_str = toml::to_string_pretty(&local_index).expect("Couldn't serialize local index");
-    fs::write(&local_index_file, &index_str).expect("Couldn't write local index");
+    TemplateIndex::edit(&local_index_file, f)
+        .unwrap_or_else(|e| err!("Cannot edit {}: {}", local_index_file.display(), e));
 }
 
-fn self_bin_path() -> PathBuf {
-    std::env::current_exe().expect("Cannot get self binary")
-}
+/// `err`, followed by the url and what to do about it if it comes down to a
+/// failed download.
+fn explain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = err.to_string();
 
-fn main() {
-    let Opts {
-        ref config,
-        ref local_templates_index,
-        subcmd,
-    } = Opts::parse();
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(download) = err.downcast_ref::<DownloadError>() {
+            if let Some(url) = download.url() {
+                write!(message, "\n  url: {}", url).unwrap();
+            }
+            if let Some(hint) = download.hint() {
+                write!(message, "\n  hint: {}", hint).unwrap();
+            }
+            break;
+        }
+        source = err.source();
+    }
 
-    tracing_subscriber::fmt::SubscriberBuilder::default()
-        .pretty()
-        .init();
+    message
+}
 
-    let cache = cache_dir();
+fn check_target_directory(directory: &Path, allow_dirty: bool) {
+    if let Err(err) = generate::check_target_directory(directory, allow_dirty) {
+        err!("{}", err);
+    }
+}
 
-    match subcmd {
-        Subcommand::AddToIndex(AddToIndexCommand {
-            git_provider,
-            user,
-            repo,
-            git_ref,
-            issue,
-            name,
-            description,
-        }) => edit_index(local_templates_index, |mut local_index| {
-            if let Some(t) = local_index.templates.iter().find(|it| it.name() == name) {
-                err!("Template already exists in index, pointing to {:?}", t);
-            }
+fn default_project_name(directory: &Path) -> String {
+    generate::default_project_name(directory).unwrap_or_else(|e| err!("{}", e))
+}
 
-            if let Err(err) = check_template_name(&name) {
-                err!("Invalid name: {}", err);
-            }
+static QUIET: AtomicBool = AtomicBool::new(false);
 
-            let t = Template::Repo {
+fn spinner(message: String) -> ProgressBar {
+    if QUIET.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+
+    let progress = ProgressBar::new_spinner();
+    progress.set_message(message);
+    progress.enable_steady_tick(Duration::from_millis(100));
+    progress
+}
+
+/// Shows what `Thorc` is busy with as a spinner; copying (which may prompt)
+/// and hooks (which print) get the terminal to themselves.
+#[derive(Default)]
+struct SpinnerObserver {
+    bar: RefCell<Option<ProgressBar>>,
+    message: RefCell<String>,
+}
+
+impl Observer for SpinnerObserver {
+    fn phase(&self, phase: Phase<'_>) {
+        let message = match phase {
+            Phase::Resolving { template } => format!("resolving {}…", template),
+            Phase::FetchingIndex { name } => format!("fetching index {}…", name),
+            Phase::Downloading { template } => format!("downloading {}…", template),
+            Phase::Extracting { template } => format!("extracting {}…", template),
+            _ => return self.done(),
+        };
+
+        self.bar
+            .borrow_mut()
+            .get_or_insert_with(|| spinner(String::new()))
+            .set_message(message.clone());
+        *self.message.borrow_mut() = message;
+    }
+
+    fn progress(&self, current: usize, total: usize) {
+        if let Some(bar) = &*self.bar.borrow() {
+            bar.set_message(format!("{} {}/{}", self.message.borrow(), current, total));
+        }
+    }
+
+    fn done(&self) {
+        if let Some(bar) = self.bar.borrow_mut().take() {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn moved(&self, template: &str, from: &RepoDef, to: &RepoDef) {
+        MOVED
+            .lock()
+            .unwrap()
+            .push((template.to_string(), from.clone(), to.clone()));
+    }
+}
+
+/// Templates whose repositories turned out to have moved while downloading
+/// them: their names, and where they moved from and to.
+static MOVED: Mutex<Vec<(String, RepoDef, RepoDef)>> = Mutex::new(Vec::new());
+
+/// Points the local index's entries for templates whose repositories moved
+/// at the new ones, if `fix` or the user agrees.
+fn update_moved(local_templates_index: &Option<PathBuf>, fix: bool) {
+    let moved = std::mem::take(&mut *MOVED.lock().unwrap());
+
+    for (template, from, to) in moved {
+        let is_entry = |it: &Template| match it {
+            Template::Repo { name, repo, .. } => {
+                *name == template && repo.user == from.user && repo.repo == from.repo
+            }
+            _ => false,
+        };
+
+        let (_, local_index) = load_local_index(local_templates_index);
+        if !local_index.templates.iter().any(is_entry) {
+            println!(
+                "{} isn't from the local index; the index it is from should name {}/{}",
+                template, to.user, to.repo
+            );
+            continue;
+        }
+
+        let question = format!(
+            "Update {} in the local index to {}/{}? [y/n] ",
+            template, to.user, to.repo
+        );
+        let agreed = fix || (io::stdin().is_terminal() && ask(&question));
+        if !agreed {
+            println!("Pass --fix to update {} in the local index", template);
+            continue;
+        }
+
+        edit_index(local_templates_index, |index| {
+            index.templates = std::mem::take(&mut index.templates)
+                .into_iter()
+                .map(|mut it| {
+                    if is_entry(&it) {
+                        // keeping the entry's ref, even if left out
+                        if let Template::Repo { repo, .. } = &mut it {
+                            repo.user = to.user.clone();
+                            repo.repo = to.repo.clone();
+                        }
+                    }
+                    it
+                })
+                .collect();
+        });
+        println!("Updated {} to {}", template, to.link());
+    }
+}
+
+/// Asks `question` until the answer is yes or no; no if stdin closes.
+fn ask(question: &str) -> bool {
+    let stdin = io::stdin();
+
+    loop {
+        print!("{}", question);
+        if io::stdout().flush().is_err() {
+            return false;
+        }
+
+        let mut answer = String::new();
+        match stdin.lock().read_line(&mut answer) {
+            Ok(0) | Err(_) => return false,
+            Ok(_) => {}
+        }
+
+        match answer.trim() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => {}
+        }
+    }
+}
+
+/// Cancelled by the first Ctrl-C, letting downloads and copying clean up;
+/// a second one exits right away.
+fn ctrl_c_token() -> CancelToken {
+    let cancel = CancelToken::new();
+
+    // registered first, so it sees the flag before the first Ctrl-C sets it
+    signal_hook::flag::register_conditional_shutdown(SIGINT, 130, cancel.flag())
+        .and_then(|_| signal_hook::flag::register(SIGINT, cancel.flag()))
+        .expect("Cannot install the Ctrl-C handler");
+
+    cancel
+}
+
+/// A builder for the config file and profile the flags name, and the
+/// project config of the current directory.
+fn thorc_builder(config: &Option<PathBuf>, profile: &Option<String>) -> ThorcBuilder {
+    let mut builder = Thorc::builder();
+
+    if let Ok(cwd) = std::env::current_dir() {
+        builder = builder.project_dir(cwd);
+    }
+
+    if let Some(config) = config {
+        builder = builder.config_file(config);
+    }
+    if let Some(profile) = profile {
+        builder = builder.profile(profile);
+    }
+
+    builder
+}
+
+/// The config as [`load_thorc`] layers it, for its defaults; nothing else is
+/// loaded or decrypted.
+fn load_config(config: &Option<PathBuf>, profile: &Option<String>) -> Config {
+    thorc_builder(config, profile)
+        .load_config()
+        .unwrap_or_else(|e| err!("Cannot load config: {}", e))
+}
+
+fn load_thorc(
+    config: &Option<PathBuf>,
+    local_templates_index: &Option<PathBuf>,
+    profile: &Option<String>,
+) -> Thorc {
+    let mut builder = thorc_builder(config, profile)
+        .observer(SpinnerObserver::default())
+        .hook_executor(PromptingExecutor)
+        .cancel_token(ctrl_c_token());
+
+    if let Some(local_templates_index) = local_templates_index {
+        builder = builder.local_index_file(local_templates_index);
+    }
+
+    builder
+        .build()
+        .unwrap_or_else(|e| err!("Cannot load config: {}", e))
+}
+
+fn generate(
+    thorc: &Thorc,
+    template: &Template,
+    directory: &Path,
+    project_name: &str,
+    variables: &BTreeMap<String, String>,
+    expected_checksum: Option<&str>,
+    interactive: bool,
+) {
+    if interactive {
+        thorc.generate_with(
+            template,
+            directory,
+            project_name,
+            variables,
+            expected_checksum,
+            prompt_conflict,
+        )
+    } else {
+        thorc.generate(
+            template,
+            directory,
+            project_name,
+            variables,
+            expected_checksum,
+        )
+    }
+    .unwrap_or_else(|e| err!("Cannot generate {}: {}", template.name(), explain(&e)));
+}
+
+fn insert_text(file: &Path, marker: &str, text: &str, position: Position) {
+    let contents = fs::read_to_string(file).expect("Cannot read file");
+    let inserted = marker::insert(&contents, marker, text, position)
+        .unwrap_or_else(|| err!("No line in {} contains {:?}", file.display(), marker));
+
+    if inserted != contents {
+        fs::write(file, inserted).expect("Cannot write file");
+    }
+}
+
+/// Prints `output` through `$PAGER` (or `less`) when stdout is a terminal.
+fn page(output: &str, no_pager: bool) {
+    if no_pager || !io::stdout().is_terminal() {
+        print!("{}", output);
+        return;
+    }
+
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -FRX".to_string());
+    let mut pager = pager.split_whitespace();
+
+    let child = pager.next().map(|program| {
+        Command::new(program)
+            .args(pager)
+            .stdin(Stdio::piped())
+            .spawn()
+    });
+
+    match child {
+        Some(Ok(mut child)) => {
+            // the user quitting the pager early closes the pipe; that's fine
+            let _ = child.stdin.take().unwrap().write_all(output.as_bytes());
+            let _ = child.wait();
+        }
+        _ => print!("{}", output),
+    }
+}
+
+fn prompt_conflict(src: &Path, dest: &Path) -> io::Result<Conflict> {
+    let existing = fs::read(dest)?;
+    let incoming = fs::read(src)?;
+
+    let is_text = match (
+        std::str::from_utf8(&existing),
+        std::str::from_utf8(&incoming),
+    ) {
+        (Ok(existing), Ok(incoming)) => {
+            let dest_name = dest.display().to_string();
+            print!(
+                "{}",
+                similar::TextDiff::from_lines(existing, incoming)
+                    .unified_diff()
+                    .header(&dest_name, "template")
+            );
+            true
+        }
+        _ => {
+            println!("Binary file {} differs", dest.display());
+            false
+        }
+    };
+
+    let stdin = io::stdin();
+    loop {
+        if is_text {
+            print!("{}: [k]eep, [o]verwrite, [m]erge? ", dest.display());
+        } else {
+            print!("{}: [k]eep, [o]verwrite? ", dest.display());
+        }
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Ok(Conflict::Keep);
+        }
+
+        match answer.trim() {
+            "k" | "keep" => return Ok(Conflict::Keep),
+            "o" | "overwrite" => return Ok(Conflict::Overwrite),
+            "m" | "merge" if is_text => return Ok(Conflict::Merge),
+            _ => {}
+        }
+    }
+}
+
+/// With `--interactive`, asks about every conflict, and which template to use
+/// if several indexes have it; otherwise overwrites files and takes the first
+/// template found without looking further.
+struct CliResolver {
+    interactive: bool,
+    /// keep every existing file, remembering it in `kept`.
+    merge: bool,
+    kept: Vec<PathBuf>,
+}
+
+impl ConflictResolver for CliResolver {
+    fn file_conflict(&mut self, src: &Path, dest: &Path) -> io::Result<Conflict> {
+        if self.merge {
+            self.kept.push(dest.to_owned());
+            Ok(Conflict::Keep)
+        } else if self.interactive {
+            prompt_conflict(src, dest)
+        } else {
+            Ok(Conflict::Overwrite)
+        }
+    }
+
+    fn asks_about_files(&self) -> bool {
+        self.merge || self.interactive
+    }
+
+    fn checks_duplicates(&self) -> bool {
+        self.interactive
+    }
+
+    fn duplicate_template(
+        &mut self,
+        name: &str,
+        candidates: &[(&str, &Template)],
+    ) -> Option<usize> {
+        for (i, (index, template)) in candidates.iter().enumerate() {
+            println!("{}) [{}] {}", i + 1, index, template.one_line_summary());
+        }
+
+        let stdin = io::stdin();
+        loop {
+            print!("Which {}? [1-{}] ", name, candidates.len());
+            io::stdout().flush().ok()?;
+
+            let mut answer = String::new();
+            if stdin.lock().read_line(&mut answer).ok()? == 0 {
+                return None;
+            }
+
+            match answer.trim().parse::<usize>() {
+                Ok(n) if (1..=candidates.len()).contains(&n) => return Some(n - 1),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Runs hooks as child processes, asking on the terminal about those the
+/// config's hook policy says to prompt for.
+struct PromptingExecutor;
+
+impl HookExecutor for PromptingExecutor {
+    fn execute(&self, hook: &Hook) -> Result<(), RunHookError> {
+        ProcessExecutor.execute(hook)
+    }
+
+    fn confirm(&self, hook: &Hook) -> bool {
+        if !io::stdin().is_terminal() {
+            tracing::warn!(
+                "Not running the {} hook, as there is no one to ask",
+                hook.name
+            );
+            return false;
+        }
+
+        let program = match &hook.program {
+            HookProgram::File(path) => path.display().to_string(),
+            HookProgram::Script(_) => "the built-in setup".to_string(),
+        };
+
+        ask(&format!("Run the {} hook ({})? [y/n] ", hook.name, program))
+    }
+}
+
+fn main() {
+    let Opts {
+        ref config,
+        ref local_templates_index,
+        ref profile,
+        portable,
+        quiet,
+        verbose,
+        subcmd,
+    } = Opts::parse();
+
+    if portable {
+        // rather than passing it around, so that hooks calling back into
+        // thorc are portable too
+        std::env::set_var("THORC_DIRS", "portable");
+    }
+
+    QUIET.store(quiet, Ordering::Relaxed);
+
+    tracing_subscriber::fmt::SubscriberBuilder::default()
+        .pretty()
+        .with_max_level(match verbose {
+            0 => tracing::Level::INFO,
+            1 => tracing::Level::DEBUG,
+            _ => tracing::Level::TRACE,
+        })
+        .init();
+
+    let cache = default_cache_dir();
+
+    match subcmd {
+        Subcommand::AddToIndex(AddToIndexCommand {
+            git_provider,
+            host,
+            archive_url_template,
+            tree_url_template,
+            user,
+            repo,
+            git_ref,
+            git_clone,
+            ssh,
+            issue,
+            name,
+            description,
+            tags,
+            version,
+        }) => edit_index(local_templates_index, |local_index| {
+            if let Some(t) = local_index
+                .templates
+                .iter()
+                .find(|it| it.name() == name && it.version() == version.as_ref())
+            {
+                err!("Template already exists in index, pointing to {:?}", t);
+            }
+
+            if let Err(err) = check_template_name_with(&name, local_index.namespaced) {
+                err!("Invalid name: {}", err);
+            }
+
+            let t = Template::Repo {
                 name,
                 description,
-                repo: RepoDef {
-                    git_provider,
+                metadata: Metadata {
+                    version,
+                    tags,
+                    ..Metadata::default()
+                },
+                repo: repo_def_with_defaults(
+                    &load_config(config, profile),
+                    custom_provider(archive_url_template, tree_url_template).or(git_provider),
+                    host,
                     user,
                     repo,
                     git_ref,
-                },
+                    fetch_strategy(git_clone, ssh),
+                ),
                 issue,
                 setup: None,
             };
 
             local_index.templates.insert(t);
-
-            local_index
         }),
         Subcommand::AddLocalToIndex(AddLocalToIndexCommand {
             path,
             description,
+            tags,
+            version,
             name,
-        }) => edit_index(local_templates_index, |mut local_index| {
+        }) => edit_index(local_templates_index, |local_index| {
             if local_index.for_remote {
                 err!("Local templates may not be added to indexes intended to be used remotely");
             }
 
-            if let Err(err) = check_template_name(&name) {
+            if let Err(err) = check_template_name_with(&name, local_index.namespaced) {
                 err!("Invalid name: {}", err);
             }
 
-            if let Some(t) = local_index.templates.iter().find(|it| it.name() == name) {
+            if let Some(t) = local_index
+                .templates
+                .iter()
+                .find(|it| it.name() == name && it.version() == version.as_ref())
+            {
                 err!("Template already exists in index, pointing to {:?}", t);
             }
 
             let t = Template::Local {
                 name,
                 description,
+                metadata: Metadata {
+                    version,
+                    tags,
+                    ..Metadata::default()
+                },
                 path,
             };
 
             local_index.templates.insert(t);
-
-            local_index
         }),
         Subcommand::RemoveFromIndex(RemoveFromIndexCommand { name }) => {
-            edit_index(local_templates_index, |mut local_index| {
-                if let Err(err) = check_template_name(&name) {
+            edit_index(local_templates_index, |local_index| {
+                let (template_name, version) = match name.split_once('@') {
+                    Some((template_name, version)) => match semver::Version::parse(version) {
+                        Ok(version) => (template_name, Some(version)),
+                        Err(err) => err!("Invalid version: {}", err),
+                    },
+                    None => (name.as_str(), None),
+                };
+
+                if let Err(err) = check_template_name_with(template_name, local_index.namespaced) {
                     err!("Invalid name: {}", err);
                 }
 
-                if !local_index.templates.remove(name.as_str()) {
+                let count = local_index.templates.len();
+                local_index.templates.retain(|it| {
+                    it.name() != template_name
+                        || version.as_ref().is_some_and(|v| it.version() != Some(v))
+                });
+
+                if local_index.templates.len() == count {
                     err!("Template {} doesn't exists in index", name);
                 }
-
-                local_index
             })
         }
-        Subcommand::List => {
+        Subcommand::List(ListCommand { long }) => {
             let (_, local_index) = load_local_index(local_templates_index);
 
-            for template in local_index.templates.iter() {
-                println!("{}", template.one_line_summary());
+            for (i, template) in local_index.templates.iter().enumerate() {
+                if long {
+                    if i > 0 {
+                        println!();
+                    }
+                    print!("{}", template.details());
+                } else {
+                    println!("{}", template.one_line_summary());
+                }
             }
         }
-        Subcommand::Find(FindCommand { term }) => {
-            let (_, local_index) = load_local_index(local_templates_index);
-            let (_, config) = load_config(config);
+        Subcommand::Show(ShowCommand {
+            index,
+            template_name,
+        }) => {
+            let thorc = load_thorc(config, local_templates_index, profile);
+            let template = thorc
+                .resolve(index, &template_name)
+                .unwrap_or_else(|e| err!("Cannot resolve template: {}", explain(&e)));
 
-            let first_result = local_index.find(&term);
-            let mut result = first_result.compose("<local>");
+            print!("{}", template.details());
+        }
+        Subcommand::IndexDiff(IndexDiffCommand { old, new }) => {
+            let old = TemplateIndex::load(&old)
+                .unwrap_or_else(|e| err!("Cannot load {}: {}", old.display(), e));
+            let new = TemplateIndex::load(&new)
+                .unwrap_or_else(|e| err!("Cannot load {}: {}", new.display(), e));
+            let diff = old.diff(&new);
+
+            for template in diff.added {
+                println!("+ {}", template.one_line_summary());
+            }
+            for template in diff.removed {
+                println!("- {}", template.one_line_summary());
+            }
+            for change in diff.changed {
+                println!(
+                    "~ {} ({})",
+                    change.new.one_line_summary(),
+                    change.fields.join(", ")
+                );
+            }
+        }
+        Subcommand::MergeIndex(MergeIndexCommand {
+            strategy,
+            output,
+            indexes,
+        }) => {
+            let mut merged = TemplateIndex::default();
+            // only publishable if every part is
+            let mut for_remote = true;
+            let mut namespaced = false;
+
+            for path in indexes {
+                let index = TemplateIndex::load(&path)
+                    .unwrap_or_else(|e| err!("Cannot load {}: {}", path.display(), e));
+                for_remote &= index.for_remote;
+                namespaced |= index.namespaced;
+                merged
+                    .merge(index, strategy)
+                    .unwrap_or_else(|e| err!("Cannot merge {}: {}", path.display(), e));
+            }
 
-            let remote_indexes = config
-                .remote_indexes
-                .iter()
-                .map(|remote_index| {
-                    (
-                        &remote_index.name,
-                        remote_index.get_index(&cache).expect("Cannot get index"),
-                    )
+            merged.for_remote = for_remote;
+            merged.namespaced = namespaced;
+            merged.save(&output).expect("Cannot write merged index");
+        }
+        Subcommand::IndexStats(IndexStatsCommand { index }) => {
+            let (_, index) = load_local_index(&index.or_else(|| local_templates_index.clone()));
+            let stats = index.stats();
+
+            println!("templates: {}", stats.total);
+            for (heading, counts) in [
+                ("by source", &stats.by_source),
+                ("by setup", &stats.by_setup),
+            ] {
+                println!("{}:", heading);
+                for (key, count) in counts {
+                    println!("  {}: {}", key, count);
+                }
+            }
+            println!("by tag:");
+            for (tag, count) in &stats.by_tag {
+                println!("  {}: {}", tag, count);
+            }
+            println!("missing a description: {}", stats.missing_description.len());
+            for template in &stats.missing_description {
+                println!("  {}", template.versioned_name());
+            }
+        }
+        Subcommand::Scan(ScanCommand {
+            dir,
+            output,
+            hooks_dir,
+        }) => {
+            let hooks_dir = hooks_dir
+                .or_else(|| {
+                    let thorc = load_thorc(config, local_templates_index, profile);
+                    thorc.config().hooks_dir.clone()
                 })
-                .collect::<Vec<_>>();
+                .unwrap_or_else(|| DEFAULT_HOOKS_DIR.to_string());
+            let templates = scan::scan_dir_with(&dir, &hooks_dir)
+                .unwrap_or_else(|e| err!("Cannot scan: {}", e));
+            let dir = dir.canonicalize().expect("Cannot resolve directory");
+
+            let index_file = output
+                .or_else(|| local_templates_index.clone())
+                .unwrap_or_else(default_local_index_file);
+            let mut index = if index_file.exists() {
+                TemplateIndex::load(&index_file)
+                    .unwrap_or_else(|e| err!("Cannot load {}: {}", index_file.display(), e))
+            } else {
+                TemplateIndex::default()
+            };
 
-            for (remote_name, index) in remote_indexes.iter() {
-                let find_result = index.find(&term);
-                let composed = find_result.compose(*remote_name);
-                result.merge_ref(composed);
+            if index.for_remote {
+                err!("Local templates may not be added to indexes intended to be used remotely");
             }
 
-            if !result.name_and_description.is_empty() {
-                println!("Templates that matched both name and description:");
-
-                for &(index, template) in result.name_and_description.iter() {
-                    println!("[{}] {}", index, template.one_line_summary());
-                }
+            // templates since removed from `dir` go away too
+            index.templates.retain(|it| match it {
+                Template::Local { path, .. } => !path.starts_with(&dir),
+                _ => true,
+            });
+            for template in templates {
+                println!("{}", template.one_line_summary());
+                index.templates.replace(template);
             }
 
-            if !result.name_only.is_empty() {
-                println!("Templates that matched only name:");
+            index.save(&index_file).expect("Cannot write index file");
+        }
+        Subcommand::Find(FindCommand {
+            term,
+            limit,
+            indexes,
+            tags,
+            case_sensitive,
+            fuzzy,
+            no_pager,
+        }) => {
+            let mode = if fuzzy {
+                MatchMode::Fuzzy
+            } else if case_sensitive {
+                MatchMode::CaseSensitive
+            } else {
+                MatchMode::IgnoreCase
+            };
+
+            let thorc = load_thorc(config, local_templates_index, profile);
+            let term = term.unwrap_or_default();
+            let mut result = thorc
+                .find_with(&term, &indexes, mode)
+                .unwrap_or_else(|e| err!("Cannot search: {}", explain(&e)));
+            result.retain_tagged(&tags);
+
+            let mut output = String::new();
+
+            let mut last_kind = None;
+
+            for Match {
+                index,
+                template,
+                kind,
+                ..
+            } in result.iter().take(limit.unwrap_or(usize::MAX))
+            {
+                if last_kind != Some(kind) {
+                    let heading = match kind {
+                        MatchKind::NameAndDescription => {
+                            "Templates that matched both name and description:"
+                        }
+                        MatchKind::Name => "Templates that matched only name:",
+                        MatchKind::Description => "Templates that matched only description:",
+                        MatchKind::Tags => "Templates that matched only tags:",
+                    };
+                    writeln!(&mut output, "{}", heading).unwrap();
+                    last_kind = Some(kind);
+                }
 
-                for &(index, template) in result.name_only.iter() {
-                    println!("[{}] {}", index, template.one_line_summary());
+                match template.deprecation_notice() {
+                    Some(notice) => writeln!(
+                        &mut output,
+                        "[{}] {} ({})",
+                        index,
+                        template.one_line_summary(),
+                        notice
+                    ),
+                    None => writeln!(&mut output, "[{}] {}", index, template.one_line_summary()),
                 }
+                .unwrap();
             }
 
-            if !result.description_only.is_empty() {
-                println!("Templates that matched only description:");
+            // templates by the same name in several indexes shadow each other
+            let mut indexes_of = BTreeMap::<&str, BTreeSet<&str>>::new();
+            for it in result.iter() {
+                indexes_of
+                    .entry(it.template.name())
+                    .or_default()
+                    .insert(it.index);
+            }
+            for (name, indexes) in indexes_of {
+                if indexes.len() < 2 {
+                    continue;
+                }
 
-                for &(index, template) in result.description_only.iter() {
-                    println!("[{}] {}", index, template.one_line_summary());
+                // in the order `new` looks in them, which may not look in all
+                if let Ok(found) = thorc.resolve_all(None, name) {
+                    if found.len() > 1 {
+                        let indexes = found.iter().map(|(index, _)| index.as_str());
+                        tracing::warn!(
+                            "{} is in several indexes ({}); `new` uses the one from {}",
+                            name,
+                            indexes.collect::<Vec<_>>().join(", "),
+                            found[0].0
+                        );
+                    }
                 }
             }
+
+            page(&output, no_pager);
         }
         Subcommand::New(NewCommand {
             index,
@@ -384,101 +1647,241 @@ fn main() {
             project_name,
             directory,
             allow_dirty,
+            interactive,
+            merge,
+            allow_deprecated,
+            fix,
         }) => {
-            let (_, local_index) = load_local_index(local_templates_index);
-            let (_, config) = load_config(config);
+            let thorc = load_thorc(config, local_templates_index, profile);
+
+            let options = GenerateOptions {
+                index,
+                project_name,
+                allow_dirty: allow_dirty || interactive || merge,
+                allow_deprecated,
+                ..GenerateOptions::new(template_name, &directory)
+            };
 
-            if let Err(err) = check_template_name(&template_name) {
-                err!("Invalid name: {}", err);
+            let mut resolver = CliResolver {
+                interactive,
+                merge,
+                kept: Vec::new(),
+            };
+            generate_project_with(&thorc, options, &mut resolver)
+                .unwrap_or_else(|e| err!("Cannot generate project: {}", explain(&e)));
+            update_moved(local_templates_index, fix);
+
+            if !resolver.kept.is_empty() {
+                println!(
+                    "Kept {} existing files that differ from the template:",
+                    resolver.kept.len()
+                );
+                for path in resolver.kept {
+                    println!(
+                        "  {}",
+                        path.strip_prefix(&directory).unwrap_or(&path).display()
+                    );
+                }
+            }
+        }
+        Subcommand::Regenerate(RegenerateCommand {
+            lock_file,
+            directory,
+            allow_dirty,
+        }) => {
+            let thorc = load_thorc(config, local_templates_index, profile);
+            let lock = LockFile::load(&lock_file).expect("Cannot read lock file");
+
+            check_target_directory(&directory, allow_dirty);
+
+            if lock.commit.is_none() {
+                tracing::warn!(
+                    "No commit recorded for {}; using {} as-is",
+                    lock.template.name(),
+                    lock.template.one_line_summary()
+                );
             }
 
-            if directory.exists() {
-                if !directory.is_dir() {
+            generate(
+                &thorc,
+                &lock.pinned_template(),
+                &directory,
+                &lock.project_name,
+                &lock.variables,
+                Some(&lock.checksum),
+                false,
+            );
+        }
+        Subcommand::Compose(ComposeCommand {
+            file,
+            directory,
+            allow_dirty,
+        }) => {
+            let thorc = load_thorc(config, local_templates_index, profile);
+
+            let compose = ComposeFile::load(&file).expect("Cannot read compose file");
+
+            for entry in compose.templates.iter() {
+                if let Err(err) = check_qualified_template_name(&entry.name) {
+                    err!("Invalid name: {}", err);
+                }
+
+                let target = entry.target(&directory).unwrap_or_else(|| {
                     err!(
-                        "{} already exists and is not a directory",
+                        "Compose target {} must be relative and stay inside {}",
+                        entry.directory.display(),
                         directory.display()
-                    );
-                } else if !allow_dirty && directory.read_dir().unwrap().next().is_some() {
-                    err!("{} already exists and is not empty", directory.display());
-                }
+                    )
+                });
+
+                check_target_directory(&target, allow_dirty);
             }
 
-            let indexes = config
-                .get_all_remote_indexes(&cache)
-                .expect("Cannot get indexes");
+            for entry in compose.templates.iter() {
+                let template = thorc
+                    .resolve(entry.index.as_deref().map(IndexName::from), &entry.name)
+                    .unwrap_or_else(|e| err!("Cannot resolve template: {}", explain(&e)));
+
+                // checked above
+                let target = entry.target(&directory).unwrap();
+
+                let project_name = match &entry.project_name {
+                    Some(project_name) => project_name.clone(),
+                    None => default_project_name(&target),
+                };
+
+                generate(
+                    &thorc,
+                    &template,
+                    &target,
+                    &project_name,
+                    &entry.variables(&compose.variables),
+                    None,
+                    false,
+                );
+            }
+        }
+        Subcommand::Tui => {
+            let thorc = load_thorc(config, local_templates_index, profile);
 
-            let index_v = index.map(|it| match it {
-                IndexName::Local => RO::Ref(&local_index),
-                IndexName::Remote(r) => {
-                    match config.remote_indexes.iter().find(|it| it.name == r) {
-                        Some(index) => {
-                            RO::Owned(index.get_index(&cache).expect("Cannot get index"))
-                        }
-                        None => err!("Invalid index: {}", r),
-                    }
-                }
-            });
+            let mut indexes = vec![("<local>", thorc.local_index())];
+            indexes.extend(thorc.remote_indexes(|_| true).expect("Cannot get index"));
 
-            let template = match &index_v {
-                Some(index) => index.find_exact(&template_name),
-                None => local_index
-                    .find_exact(&template_name)
-                    .or_else(|| find_template(&indexes, &template_name)),
-            };
+            let selection = tui::run(&indexes, thorc.cache_dir()).expect("Terminal error");
 
-            let template = match template {
-                Some(template) => template,
-                None => err!("Unknown template: {}", template_name),
+            if let Some((template, directory)) = selection {
+                check_target_directory(&directory, false);
+
+                generate(
+                    &thorc,
+                    &template,
+                    &directory,
+                    &default_project_name(&directory),
+                    &BTreeMap::new(),
+                    None,
+                    false,
+                );
+            }
+        }
+        Subcommand::Serve(ServeCommand {
+            bind,
+            public_url,
+            indexes,
+        }) => {
+            let indexes = if indexes.is_empty() {
+                let (_, local_index) = load_local_index(local_templates_index);
+                vec![("local".to_string(), local_index)]
+            } else {
+                indexes
+                    .into_iter()
+                    .map(|ServedIndex { name, path }| (name, load_local_index(&Some(path)).1))
+                    .collect()
             };
 
-            let template_path = template.download(&cache).expect("Cannot download template");
+            thorc::serve::serve(&bind, public_url.as_deref(), &indexes, &cache)
+                .expect("Server error");
+        }
+        Subcommand::Cache(CacheCommand {
+            subcmd: CacheSubcommand::Purge(PurgeCacheCommand { index, name }),
+        }) => {
+            let thorc = load_thorc(config, local_templates_index, profile);
 
-            fs::create_dir_all(&directory).expect("Cannot create directory");
+            let remote_index = match index {
+                None => thorc
+                    .config()
+                    .remote_indexes
+                    .iter()
+                    .find(|it| it.name == name),
+                Some(_) => None,
+            };
 
-            thorc::utils::copy(&template_path, &directory).expect("Cannot copy template");
+            let removed = match remote_index {
+                Some(remote_index) => remote_index.purge_cache(&cache),
+                None => thorc
+                    .resolve(index, &name)
+                    .unwrap_or_else(|e| err!("Cannot resolve template: {}", explain(&e)))
+                    .purge_cache(&cache),
+            }
+            .expect("Cannot purge cache");
 
-            finish_setup(
-                &self_bin_path(),
-                &template,
-                &directory,
-                project_name
-                    .as_ref()
-                    .map(|it| it.as_str())
-                    .unwrap_or_else(|| directory.file_name().unwrap().to_str().unwrap()),
-            )
-            .expect("Cannot finish setup");
+            if removed.is_empty() {
+                println!("Nothing cached for {}", name);
+            }
+
+            for path in removed {
+                println!("Removed {}", path.display());
+            }
         }
         Subcommand::AddRemoteIndex(AddRemoteIndexCommand {
             name,
             description,
             git_provider,
+            host,
+            archive_url_template,
+            tree_url_template,
             user,
             repo,
             git_ref,
+            git_clone,
+            ssh,
             path,
-        }) => edit_config(config, |mut config| {
-            if name == "local" {
-                err!("Cannot add a remote index named 'local'");
-            }
-
-            let remote_index = RemoteIndex {
-                name,
-                description,
-                path,
-                repo: RepoDef {
-                    git_provider,
-                    user,
-                    repo,
-                    git_ref,
-                },
-            };
-
-            config.remote_indexes.push(remote_index);
+            url,
+            registry,
+            refresh,
+        }) => {
+            let config_path = config;
+            edit_config(config, |config| {
+                if name == "local" {
+                    err!("Cannot add a remote index named 'local'");
+                }
 
-            config
-        }),
+                let source = match (url, registry) {
+                    (Some(url), _) => IndexSource::Url { url },
+                    (None, Some(registry)) => IndexSource::Registry { registry },
+                    (None, None) => IndexSource::Repo(repo_def_with_defaults(
+                        &load_config(config_path, profile),
+                        custom_provider(archive_url_template, tree_url_template).or(git_provider),
+                        host,
+                        user,
+                        repo.unwrap(),
+                        git_ref,
+                        fetch_strategy(git_clone, ssh),
+                    )),
+                };
+
+                let remote_index = RemoteIndex {
+                    name,
+                    description,
+                    path: path.unwrap_or_else(|| PathBuf::from("index.toml")),
+                    source,
+                    refresh: refresh.unwrap_or_default(),
+                };
+
+                config.remote_indexes.push(remote_index);
+            })
+        }
         Subcommand::RemoveRemoteIndex(RemoveRemoteIndexCommand { name }) => {
-            edit_config(config, |mut config| {
+            edit_config(config, |config| {
                 if name == "local" {
                     err!("Cannot remove index named 'local'");
                 }
@@ -492,255 +1895,384 @@ fn main() {
                     .0;
 
                 config.remote_indexes.remove(remote_index);
-
-                config
             })
         }
+        Subcommand::SetVariable(SetVariableCommand { name, value }) => {
+            edit_config(config, |config| {
+                config.variables.insert(name, value);
+            })
+        }
+        Subcommand::UnsetVariable(UnsetVariableCommand { name }) => edit_config(config, |config| {
+            if config.variables.remove(&name).is_none() {
+                err!("No variable called '{}' set", name);
+            }
+        }),
+        Subcommand::Config(ConfigCommand {
+            subcmd: ConfigSubcommand::Migrate(MigrateConfigCommand { project }),
+        }) => {
+            let config_file = config.clone().unwrap_or_else(default_config_file);
+            let local_index_file = local_templates_index
+                .clone()
+                .unwrap_or_else(default_local_index_file);
+            let project_config = if project {
+                let project_config = std::env::current_dir()
+                    .ok()
+                    .and_then(|it| find_project_config(&it));
+                if project_config.is_none() {
+                    println!("No project config found");
+                }
+                project_config
+            } else {
+                None
+            };
+
+            let migrated = [Some(&config_file), project_config.as_ref()]
+                .into_iter()
+                .flatten()
+                .filter(|it| it.exists())
+                .map(|it| (it, Config::migrate(it).map_err(|e| e.to_string())))
+                .chain(
+                    Some(&local_index_file)
+                        .filter(|it| it.exists())
+                        .map(|it| (it, TemplateIndex::migrate(it).map_err(|e| e.to_string()))),
+                );
+
+            for (path, result) in migrated {
+                match result {
+                    Ok(Some((version, backup))) => println!(
+                        "Migrated {} from schema version {}; the original is in {}",
+                        path.display(),
+                        version,
+                        backup.display()
+                    ),
+                    Ok(None) => println!("{} is up to date", path.display()),
+                    Err(e) => err!("Cannot migrate {}: {}", path.display(), e),
+                }
+            }
+        }
         Subcommand::EditToml(EditTomlCommand {
             toml_file,
             objcet_path,
+            value,
+            mode,
         }) => {
-            let stdin = io::stdin();
-            let mut input_str = String::new();
-
-            for line in stdin.lock().lines() {
-                writeln!(&mut input_str, "{}", line.unwrap()).unwrap();
-            }
-
-            let mut input = input_str
-                .parse::<toml_edit::Document>()
-                .expect("Failed to parse input");
-            let input = std::mem::replace(&mut input["value"], toml_edit::Item::None);
-            let mut toml_file_value = fs::read_to_string(&toml_file)
-                .unwrap()
-                .parse::<toml_edit::Document>()
-                .unwrap();
+            let (path, op) = mode.op(&objcet_path, || {
+                // stdin and --value-file hold a `value = ...` document,
+                // --value just the value itself
+                let input_str = match &value.value {
+                    Some(literal) => format!("value = {}", literal),
+                    None => value.read(),
+                };
+
+                let mut input = input_str
+                    .parse::<toml_edit::Document>()
+                    .expect("Failed to parse input");
+                std::mem::replace(&mut input["value"], toml_edit::Item::None)
+            });
 
-            patch_toml(
-                &mut toml_file_value.root,
-                input,
-                &mut objcet_path.pb.components(),
-            );
+            let toml_file_str = edit::edit_toml(
+                &fs::read_to_string(&toml_file).unwrap(),
+                &path,
+                op,
+                mode.options(),
+            )
+            .expect("Cannot patch file");
 
-            let toml_file_str = toml_file_value.to_string();
             fs::write(&toml_file, toml_file_str).unwrap();
         }
         Subcommand::EditJson(EditJsonCommand {
             json_file,
             objcet_path,
+            value,
+            mode,
         }) => {
-            let stdin = io::stdin();
-            let mut input_str = String::new();
-
-            for line in stdin.lock().lines() {
-                writeln!(&mut input_str, "{}", line.unwrap()).unwrap();
-            }
-
-            let input = serde_json::from_str::<serde_json::Value>(&input_str)
-                .expect("Failed to parse input");
-            let mut json_file_value =
-                serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&json_file).unwrap())
-                    .unwrap();
-
-            patch_json(
-                &mut json_file_value,
-                input,
-                &mut objcet_path.pb.components(),
-            );
+            let (path, op) = mode.op(&objcet_path, || value.read());
+
+            // edited in place, so key order and formatting are kept
+            let json_file_str = edit::edit_json(
+                &fs::read_to_string(&json_file).unwrap(),
+                &path,
+                op.as_ref().map(String::as_str),
+                mode.options(),
+            )
+            .expect("Cannot patch file");
 
-            let json_file_str = serde_json::to_string_pretty(&json_file_value).unwrap();
             fs::write(&json_file, json_file_str).unwrap();
         }
-    }
-}
+        Subcommand::GetToml(GetTomlCommand {
+            toml_file,
+            objcet_path,
+            raw,
+        }) => {
+            let toml_file_value = fs::read_to_string(&toml_file)
+                .unwrap()
+                .parse::<toml_edit::Document>()
+                .unwrap();
 
-fn patch_toml(
-    original_value: &mut toml_edit::Item,
-    new_value: toml_edit::Item,
-    path: &mut Components,
-) {
-    let next = path.next();
+            let mut value = &toml_file_value.root;
+            for c in objcet_path.segments.iter() {
+                let c = c.as_str();
 
-    match next {
-        Some(c) => {
-            let c = c.as_os_str().to_str().unwrap();
+                value = match usize::from_str(c) {
+                    Ok(int) => &value[int],
+                    Err(_) => &value[c],
+                };
+            }
 
-            if let Ok(int) = usize::from_str(c) {
-                patch_toml(&mut original_value[int], new_value, path);
-            } else {
-                patch_toml(&mut original_value[c], new_value, path);
+            match value {
+                toml_edit::Item::None => err!("No value at {}", objcet_path),
+                toml_edit::Item::Value(v) if raw && v.is_str() => {
+                    println!("{}", v.as_str().unwrap())
+                }
+                toml_edit::Item::Value(v) => println!("{}", v.clone().decorated("", "")),
+                toml_edit::Item::Table(t) => print!("{}", t),
+                toml_edit::Item::ArrayOfTables(a) => println!("{}", a.clone().into_array()),
             }
         }
-        None => {
-            *original_value = new_value;
-        }
-    }
-}
+        Subcommand::GetJson(GetJsonCommand {
+            json_file,
+            objcet_path,
+            raw,
+        }) => {
+            let json_file_value =
+                serde_json::from_str::<serde_json::Value>(&fs::read_to_string(&json_file).unwrap())
+                    .unwrap();
 
-fn patch_json(
-    original_value: &mut serde_json::Value,
-    new_value: serde_json::Value,
-    path: &mut Components,
-) {
-    let next = path.next();
+            let mut value = Some(&json_file_value);
+            for c in objcet_path.segments.iter() {
+                let c = c.as_str();
 
-    match next {
-        Some(c) => {
-            let c = c.as_os_str().to_str().unwrap();
+                value = value.and_then(|value| match usize::from_str(c) {
+                    Ok(int) => value.get(int),
+                    Err(_) => value.get(c),
+                });
+            }
 
-            if let Ok(int) = usize::from_str(c) {
-                patch_json(
-                    &mut original_value.as_array_mut().unwrap()[int],
-                    new_value,
-                    path,
-                );
-            } else {
-                patch_json(
-                    &mut original_value.as_object_mut().unwrap()[c],
-                    new_value,
-                    path,
-                );
+            match value {
+                None => err!("No value at {}", objcet_path),
+                Some(serde_json::Value::String(s)) if raw => println!("{}", s),
+                Some(value) => println!("{}", serde_json::to_string_pretty(value).unwrap()),
             }
         }
-        None => {
-            *original_value = new_value;
-        }
-    }
-}
+        Subcommand::EditJsonc(EditJsoncCommand {
+            jsonc_file,
+            objcet_path,
+            value,
+        }) => {
+            let input_str = value.read();
 
-#[derive(Debug, thiserror::Error)]
-pub enum RunHookError {
-    #[error("IO error: {0}")]
-    IO(#[from] io::Error),
-    #[error("status not success: {0}")]
-    StatusNotSuccess(ExitStatus),
-}
+            let path = objcet_path.strs();
 
-fn hook_path(dir: &Path, name: &str) -> PathBuf {
-    let mut pb = dir.join("thor");
-    pb.push(name);
-    pb
-}
+            let jsonc_file_str =
+                thorc::jsonc::set(&fs::read_to_string(&jsonc_file).unwrap(), &path, &input_str)
+                    .expect("Cannot patch file");
 
-fn hook_exists(dir: &Path, name: &str) -> bool {
-    hook_path(dir, name).exists()
-}
+            fs::write(&jsonc_file, jsonc_file_str).unwrap();
+        }
+        Subcommand::EditIni(EditIniCommand {
+            ini_file,
+            objcet_path,
+            value,
+        }) => {
+            let input_str = value.read();
 
-fn run_hook<F>(
-    self_bin: &Path,
-    directory: &Path,
-    hook_name: &str,
-    args: F,
-) -> Result<(), RunHookError>
-where
-    F: for<'a> FnOnce(&'a mut Command) -> &'a mut Command,
-{
-    let hook = hook_path(directory, hook_name);
+            let ini_file_str = thorc::ini::set_path(
+                &fs::read_to_string(&ini_file).unwrap_or_default(),
+                &objcet_path.strs(),
+                input_str.trim(),
+            );
 
-    if hook.exists() {
-        if hook.is_file() {
-            let mut cmd = std::process::Command::new(&hook);
-            args(&mut cmd);
-            cmd.env("THORC", self_bin);
+            fs::write(&ini_file, ini_file_str).unwrap();
+        }
+        Subcommand::ApplyPatches(ApplyPatchesCommand { spec, target_dir }) => {
+            let spec = PatchSpec::load(&spec).expect("Cannot load patch spec");
 
-            tracing::debug!("Running: {:?}", cmd);
+            spec.apply_to(&target_dir)
+                .unwrap_or_else(|e| err!("Cannot apply patches: {}", e));
+        }
+        Subcommand::ReplaceInFile(ReplaceInFileCommand {
+            pattern,
+            replacement,
+            files,
+            literal,
+            dry_run,
+        }) => {
+            let pattern = if literal {
+                regex::escape(&pattern)
+            } else {
+                pattern
+            };
+            let regex =
+                regex::Regex::new(&pattern).unwrap_or_else(|e| err!("Invalid pattern: {}", e));
 
-            let mut child = cmd.spawn()?;
-            let exit = child.wait()?;
+            for file in &files {
+                let paths = glob::glob(file)
+                    .unwrap_or_else(|e| err!("Invalid glob {}: {}", file, e))
+                    .collect::<Result<Vec<_>, _>>()
+                    .expect("Cannot read matched path");
 
-            if !exit.success() {
-                return Err(RunHookError::StatusNotSuccess(exit));
-            }
-        } else {
-            tracing::warn!("Looks like {} is not a file", hook.display());
-        }
-    } else {
-        tracing::info!("Looks like {} doesn't exist, not running", hook.display());
-    }
+                if paths.is_empty() {
+                    err!("No files match {}", file);
+                }
 
-    Ok(())
-}
+                for path in paths.iter().filter(|it| it.is_file()) {
+                    let contents = fs::read_to_string(path).expect("Cannot read file");
+                    let replaced = if literal {
+                        regex.replace_all(&contents, regex::NoExpand(&replacement))
+                    } else {
+                        regex.replace_all(&contents, replacement.as_str())
+                    };
 
-fn finish_setup(
-    self_bin: &Path,
-    template: &Template,
-    directory: &Path,
-    project_name: &str,
-) -> Result<(), RunHookError> {
-    const SETUP_HOOK_NAME: &'static str = "setup";
+                    if replaced == contents {
+                        continue;
+                    }
 
-    if hook_exists(directory, SETUP_HOOK_NAME) {
-        run_hook(self_bin, directory, SETUP_HOOK_NAME, |command| {
-            command.arg(directory).arg(project_name)
-        })
-    } else {
-        if let Template::Repo {
-            setup: Some(setup_kind),
-            ..
-        } = template
-        {
-            match setup_kind {
-                SetupKind::Rust => run_sh(
-                    r#"#!/usr/bin/env bash
-                        dir="$1"
-                        name="$2"
-    
-                        echo "Setting up for rust" >&2
-                        echo "value = \"$name\"" | $THORC edit-toml "$dir/Cargo.toml" "package/name" || exit $?
-                        "#,
-                    |cmd| cmd.arg(directory).arg(project_name),
-                ),
-                SetupKind::Npm => run_sh(
-                    r#"#!/usr/bin/env bash
-                    dir="$1"
-                    name="$2"
-
-                    echo "Setting up for npm" >&2
-                    echo "\"$name\"" | $THORC edit-json "$dir/package.json" "name" || exit $?
-                    "#,
-                    |cmd| cmd.arg(directory).arg(project_name),
-                ),
+                    if dry_run {
+                        let name = path.display().to_string();
+                        print!(
+                            "{}",
+                            similar::TextDiff::from_lines(contents.as_str(), &replaced)
+                                .unified_diff()
+                                .header(&name, &name)
+                        );
+                    } else {
+                        fs::write(path, replaced.as_bytes()).expect("Cannot write file");
+                    }
+                }
             }
-        } else {
-            tracing::warn!(
-                "No setup hook found for {}; you may need to change some things manually",
-                template.name()
-            );
-            Ok(())
         }
-    }
-}
+        Subcommand::RenamePaths(RenamePathsCommand {
+            directory,
+            pattern,
+            replacement,
+            literal,
+            dry_run,
+        }) => {
+            let pattern = if literal {
+                regex::escape(&pattern)
+            } else {
+                pattern
+            };
+            let regex =
+                regex::Regex::new(&pattern).unwrap_or_else(|e| err!("Invalid pattern: {}", e));
+
+            let renames = thorc::rename::plan(&directory, |name| {
+                let renamed = if literal {
+                    regex.replace_all(name, regex::NoExpand(&replacement))
+                } else {
+                    regex.replace_all(name, replacement.as_str())
+                };
+                Some(renamed.into_owned())
+            })
+            .unwrap_or_else(|e| err!("Cannot rename: {}", e));
 
-fn run_sh<F>(sh: &str, args: F) -> Result<(), RunHookError>
-where
-    F: FnOnce(&mut Command) -> &mut Command,
-{
-    let mut cmd = std::process::Command::new("/usr/bin/env");
-    cmd.stdin(Stdio::piped()).arg("bash").arg("-s").arg("-");
-    args(&mut cmd);
-    cmd.env("THORC", self_bin_path());
+            if dry_run {
+                for rename in &renames {
+                    println!("{} -> {}", rename.from.display(), rename.to.display());
+                }
+            } else {
+                thorc::rename::apply(&renames).expect("Cannot rename");
+            }
+        }
+        Subcommand::InsertAfter(InsertTextCommand { file, marker, text }) => {
+            insert_text(&file, &marker, &text, Position::After);
+        }
+        Subcommand::InsertBefore(InsertTextCommand { file, marker, text }) => {
+            insert_text(&file, &marker, &text, Position::Before);
+        }
+        Subcommand::Render(RenderCommand { files, lock_file }) => {
+            let mut variables = BTreeMap::new();
+            if let Some(lock_file) = &lock_file {
+                let lock = LockFile::load(lock_file).expect("Cannot load lock file");
+                for (name, value) in lock.variables {
+                    variables.insert(name.to_lowercase(), value);
+                }
+            }
+            for (key, value) in std::env::vars() {
+                if let Some(name) = key.strip_prefix("THORC_VAR_") {
+                    variables.insert(name.to_lowercase(), value);
+                }
+            }
 
-    tracing::debug!("Running: {:?}", cmd);
+            for file in &files {
+                let contents = fs::read_to_string(file).expect("Cannot read file");
+                let rendered = thorc::render::render(&contents, &variables)
+                    .unwrap_or_else(|e| err!("Cannot render {}: {}", file.display(), e));
 
-    let mut child = cmd.spawn()?;
+                if rendered != contents {
+                    fs::write(file, rendered).expect("Cannot write file");
+                }
+            }
+        }
+        Subcommand::Cargo(CargoCommand {
+            subcmd:
+                CargoSubcommand::AddWorkspaceMember(AddWorkspaceMemberCommand {
+                    manifest_path,
+                    path,
+                }),
+        }) => {
+            let manifest = fs::read_to_string(&manifest_path).expect("Cannot read manifest");
+            let document = manifest
+                .parse::<toml_edit::Document>()
+                .expect("Cannot parse manifest");
+
+            let member = path.trim_start_matches("./").replace('\\', "/");
+            let op = match document["workspace"]["members"].as_array() {
+                Some(members) if members.iter().any(|it| it.as_str() == Some(&member)) => None,
+                Some(_) => Some(Op::Append(toml_edit::value(member.as_str()))),
+                None => {
+                    let mut members = toml_edit::Array::new();
+                    members.push(member.as_str());
+                    Some(Op::Set(toml_edit::value(members)))
+                }
+            };
 
-    write!(&mut child.stdin.as_ref().unwrap(), "{}", sh)?;
+            if let Some(op) = op {
+                let options = edit::Options {
+                    create_missing: true,
+                    ..edit::Options::default()
+                };
+                let manifest = edit::edit_toml(&manifest, &["workspace", "members"], op, options)
+                    .expect("Cannot patch manifest");
+                fs::write(&manifest_path, manifest).expect("Cannot write manifest");
+            }
+        }
+        Subcommand::EditEnv(EditEnvCommand {
+            env_file,
+            key,
+            value,
+        }) => {
+            let input_str = value.read();
 
-    let exit = child.wait()?;
+            let env_file_str = thorc::dotenv::set(
+                &fs::read_to_string(&env_file).unwrap_or_default(),
+                &key,
+                input_str.trim_end_matches('\n'),
+            );
 
-    if !exit.success() {
-        return Err(RunHookError::StatusNotSuccess(exit));
-    }
+            fs::write(&env_file, env_file_str).unwrap();
+        }
+        Subcommand::EditYaml(EditYamlCommand {
+            yaml_file,
+            objcet_path,
+            value,
+            mode,
+            drop_comments,
+        }) => {
+            let (path, op) = mode.op(&objcet_path, || {
+                serde_yaml::from_str::<serde_yaml::Value>(&value.read())
+                    .expect("Failed to parse input")
+            });
 
-    Ok(())
-}
+            let options = edit::Options {
+                drop_comments,
+                ..mode.options()
+            };
+            let yaml_file_str =
+                edit::edit_yaml(&fs::read_to_string(&yaml_file).unwrap(), &path, op, options)
+                    .unwrap_or_else(|e| err!("Cannot edit {}: {}", yaml_file.display(), e));
 
-fn find_template<'a>(indexes: &'a [TemplateIndex], name: &str) -> Option<&'a Template> {
-    for index in indexes {
-        if let Some(template) = index.find_exact(name) {
-            return Some(template);
+            fs::write(&yaml_file, yaml_file_str).unwrap();
         }
     }
-
-    None
 }
@@ -0,0 +1,57 @@
+//! Inserting lines next to a marker line, for files structured editing
+//! doesn't apply to (READMEs, CI files, `mod.rs`).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Before,
+    After,
+}
+
+/// Inserts the lines of `text` right before / after the first line containing
+/// `marker`, with the same line ending as the marker line.
+///
+/// Returns `None` if no line contains the marker. If the lines of `text` are
+/// already in the file, it is returned unchanged, so running the same
+/// insertion twice is harmless.
+pub fn insert(doc: &str, marker: &str, text: &str, position: Position) -> Option<String> {
+    let mut lines = doc.split_inclusive('\n').collect::<Vec<_>>();
+    let index = lines.iter().position(|line| line.contains(marker))?;
+
+    let new_lines = text.lines().collect::<Vec<_>>();
+    if new_lines.is_empty() || contains_block(&lines, &new_lines) {
+        return Some(doc.to_string());
+    }
+
+    let ending = if lines[index].ends_with("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    let marker_line;
+    if position == Position::After && !lines[index].ends_with('\n') {
+        marker_line = format!("{}{}", lines[index], ending);
+        lines[index] = &marker_line;
+    }
+
+    let new_lines = new_lines
+        .iter()
+        .map(|line| format!("{}{}", line, ending))
+        .collect::<Vec<_>>();
+    let at = match position {
+        Position::Before => index,
+        Position::After => index + 1,
+    };
+    lines.splice(at..at, new_lines.iter().map(String::as_str));
+
+    Some(lines.concat())
+}
+
+fn contains_block(lines: &[&str], block: &[&str]) -> bool {
+    lines.windows(block.len()).any(|window| {
+        window
+            .iter()
+            .zip(block)
+            .all(|(line, new)| line.trim_end_matches(['\r', '\n']) == *new)
+    })
+}
@@ -0,0 +1,53 @@
+//! Callbacks for following what a [`Thorc`](crate::Thorc) is doing, e.g. to
+//! show progress in a custom UI.
+
+use std::path::Path;
+
+use crate::repo_def::RepoDef;
+
+/// A step of finding a template or generating a project.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Phase<'a> {
+    /// looking a template name up in the indexes.
+    Resolving {
+        template: &'a str,
+    },
+    FetchingIndex {
+        name: &'a str,
+    },
+    Downloading {
+        template: &'a str,
+    },
+    Extracting {
+        template: &'a str,
+    },
+    /// copying the template's files into the project directory.
+    Copying {
+        directory: &'a Path,
+    },
+    /// running a hook, or the built-in setup of a template without one.
+    RunningHook {
+        name: &'a str,
+    },
+}
+
+/// Every method does nothing by default.
+pub trait Observer {
+    /// A new phase started; it lasts until the next one, or until
+    /// [`Observer::done`].
+    fn phase(&self, _phase: Phase<'_>) {}
+
+    /// `current` out of `total` steps of the current phase are underway.
+    fn progress(&self, _current: usize, _total: usize) {}
+
+    /// Whatever was in progress is over.
+    fn done(&self) {}
+
+    /// Downloading `template` was redirected from its repository `from` to
+    /// `to`, which it was renamed or transferred to; its index entry could
+    /// name `to` instead.
+    fn moved(&self, _template: &str, _from: &RepoDef, _to: &RepoDef) {}
+}
+
+impl Observer for () {}
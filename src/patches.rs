@@ -0,0 +1,268 @@
+use std::{collections::BTreeMap, fs, io, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dotenv,
+    edit::{self, Op},
+    error::{ApplyPatchesError, EditError, PatchSpecError},
+    ini, utils,
+};
+
+/// A list of edits to files in a project, applied all at once by
+/// `thorc apply-patches`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PatchSpec {
+    #[serde(default, rename = "patch")]
+    pub patches: Vec<Patch>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Patch {
+    /// file to edit, relative to the target directory.
+    pub file: PathBuf,
+    /// object path, with the same syntax as the edit commands.
+    pub path: String,
+
+    /// file format; guessed from the file name if missing.
+    #[serde(default)]
+    pub format: Option<Format>,
+
+    /// create missing tables / objects on the way to the path.
+    #[serde(default)]
+    pub create_missing: bool,
+
+    /// edit YAML files even if that drops their comments.
+    #[serde(default)]
+    pub drop_comments: bool,
+
+    #[serde(flatten)]
+    pub op: PatchOp,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PatchOp {
+    Set {
+        value: toml::Value,
+    },
+    Append {
+        append: toml::Value,
+    },
+    /// inserts before the array element the path points at.
+    Insert {
+        insert: toml::Value,
+    },
+    Merge {
+        merge: toml::Value,
+    },
+    Delete {
+        delete: bool,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Toml,
+    Json,
+    Jsonc,
+    Yaml,
+    Ini,
+    Env,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+
+        if file_name == ".env" || file_name.starts_with(".env.") {
+            return Some(Format::Env);
+        }
+        if file_name == ".editorconfig" || file_name == ".gitconfig" {
+            return Some(Format::Ini);
+        }
+
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "jsonc" => Some(Format::Jsonc),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "ini" | "cfg" | "conf" => Some(Format::Ini),
+            _ => None,
+        }
+    }
+}
+
+impl Patch {
+    pub fn format(&self) -> Option<Format> {
+        self.format.or_else(|| Format::from_path(&self.file))
+    }
+
+    /// Applies the patch to `doc`, the contents of [`Patch::file`].
+    pub fn apply(&self, doc: &str) -> Result<String, EditError> {
+        let format = self
+            .format()
+            .ok_or_else(|| EditError::UnknownFormat(self.file.display().to_string()))?;
+        let segments = edit::parse_path(&self.path)?;
+        let path = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let (path, op) = match &self.op {
+            PatchOp::Set { value } => (&path[..], Op::Set(value)),
+            PatchOp::Append { append } => (&path[..], Op::Append(append)),
+            PatchOp::Insert { insert } => {
+                let (parent, index) = edit::split_index(&path)?;
+                (parent, Op::Insert(index, insert))
+            }
+            PatchOp::Merge { merge } => (&path[..], Op::Merge(merge)),
+            PatchOp::Delete { delete: true } => (&path[..], Op::Delete),
+            PatchOp::Delete { delete: false } => return Ok(doc.to_string()),
+        };
+
+        let options = edit::Options {
+            create_missing: self.create_missing,
+            drop_comments: self.drop_comments,
+        };
+
+        match (format, op) {
+            (Format::Toml, op) => edit::edit_toml(doc, path, op.map(toml_item), options),
+            (Format::Json | Format::Jsonc, op) => {
+                let op = op.map(|value| serde_json::to_string(value).unwrap());
+                edit::edit_json(doc, path, op.as_ref().map(String::as_str), options)
+            }
+            (Format::Yaml, op) => {
+                let op = op.map(|value| serde_yaml::to_value(value).unwrap());
+                edit::edit_yaml(doc, path, op, options)
+            }
+            (Format::Ini, Op::Set(value)) => Ok(ini::set_path(doc, path, &scalar_string(value))),
+            (Format::Env, Op::Set(value)) => {
+                Ok(dotenv::set(doc, &path.join("/"), &scalar_string(value)))
+            }
+            (format, _) => Err(EditError::Unsupported(format)),
+        }
+    }
+}
+
+fn toml_item(value: &toml::Value) -> toml_edit::Item {
+    let mut wrapper = toml::map::Map::new();
+    wrapper.insert("value".to_string(), value.clone());
+
+    let mut doc = toml::to_string(&wrapper)
+        .unwrap()
+        .parse::<toml_edit::Document>()
+        .unwrap();
+    std::mem::replace(&mut doc["value"], toml_edit::Item::None)
+}
+
+/// The value as a plain string, for formats without types.
+fn scalar_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl PatchSpec {
+    pub fn load(path: &Path) -> Result<Self, PatchSpecError> {
+        let contents = fs::read_to_string(path)?;
+        let spec = toml::from_str(&contents)?;
+
+        Ok(spec)
+    }
+
+    /// Applies every patch to the files in `dir`, starting from an empty
+    /// file for those that don't exist. Nothing is written unless every patch
+    /// applies, and then all files are replaced at once, see
+    /// [`utils::write_all_atomic`].
+    pub fn apply_to(&self, dir: &Path) -> Result<(), ApplyPatchesError> {
+        // patched contents, written out only after every patch applied
+        let mut files = BTreeMap::<PathBuf, String>::new();
+
+        for patch in &self.patches {
+            let path = utils::join_within(dir, &patch.file)
+                .ok_or_else(|| ApplyPatchesError::Escapes(patch.file.clone()))?;
+            let text = match files.remove(&path) {
+                Some(text) => text,
+                None => match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                    Err(err) => return Err(err.into()),
+                },
+            };
+
+            let text = patch
+                .apply(&text)
+                .map_err(|source| ApplyPatchesError::Patch {
+                    file: patch.file.clone(),
+                    source,
+                })?;
+            files.insert(path, text);
+        }
+
+        utils::write_all_atomic(
+            files
+                .iter()
+                .map(|(path, text)| (path.as_path(), text.as_bytes())),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::Scratch;
+
+    fn spec(toml: &str) -> PatchSpec {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn patches_missing_files_from_scratch() {
+        let scratch = Scratch::new("patch-missing");
+        spec("[[patch]]\nfile = \"new.toml\"\npath = \"a\"\nvalue = 1\n")
+            .apply_to(scratch.path())
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(scratch.path().join("new.toml")).unwrap(),
+            "a = 1\n"
+        );
+    }
+
+    #[test]
+    fn writes_nothing_unless_every_patch_applies() {
+        let scratch = Scratch::new("patch-all-or-nothing");
+        scratch.write("a.toml", "a = 1\n");
+        scratch.write("dir.toml/file", "");
+
+        // unreadable, outside of the target directory, of no known format
+        type Expected = fn(&ApplyPatchesError) -> bool;
+        let cases: [(&str, Expected); 3] = [
+            ("dir.toml", |err| matches!(err, ApplyPatchesError::Io(_))),
+            ("../b.toml", |err| {
+                matches!(err, ApplyPatchesError::Escapes(_))
+            }),
+            ("b.txt", |err| {
+                matches!(err, ApplyPatchesError::Patch { .. })
+            }),
+        ];
+        for (second, expected) in cases {
+            let err = spec(&format!(
+                "[[patch]]\nfile = \"a.toml\"\npath = \"a\"\nvalue = 2\n\n\
+                 [[patch]]\nfile = \"{}\"\npath = \"b\"\nvalue = 2\n",
+                second
+            ))
+            .apply_to(scratch.path())
+            .unwrap_err();
+
+            assert!(expected(&err), "{}: {}", second, err);
+            assert_eq!(
+                fs::read_to_string(scratch.path().join("a.toml")).unwrap(),
+                "a = 1\n"
+            );
+            assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 2);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Python bindings, for scaffolding tools written in Python that want
+//! thorc's indexes, search and caching.
+//!
+//! Build the extension module with `maturin build --features python`, then:
+//!
+//! ```python
+//! import thorc
+//!
+//! t = thorc.Thorc()
+//! for m in t.find("rust"):
+//!     print(m.index, m.name, m.description)
+//! t.generate("rust-cli", "my-tool", variables={"author": "me"})
+//! ```
+
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+use pyo3::{
+    create_exception,
+    exceptions::PyException,
+    prelude::*,
+    types::{PyDict, PyList},
+    IntoPyObjectExt,
+};
+
+use crate::{
+    generate::{self, GenerateOptions},
+    utils::Conflict,
+    Thorc,
+};
+
+create_exception!(thorc, ThorcError, PyException, "Any failure inside thorc.");
+
+fn py_err(err: impl ToString) -> PyErr {
+    ThorcError::new_err(err.to_string())
+}
+
+/// Converts anything serializable into the equivalent Python objects.
+fn to_py<T: serde::Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    fn convert(py: Python<'_>, value: &serde_json::Value) -> PyResult<PyObject> {
+        use serde_json::Value;
+
+        Ok(match value {
+            Value::Null => py.None(),
+            Value::Bool(b) => b.into_py_any(py)?,
+            Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+                (Some(i), _) => i.into_py_any(py)?,
+                (None, Some(f)) => f.into_py_any(py)?,
+                (None, None) => n.to_string().into_py_any(py)?,
+            },
+            Value::String(s) => s.as_str().into_py_any(py)?,
+            Value::Array(items) => {
+                let list = PyList::empty(py);
+                for item in items {
+                    list.append(convert(py, item)?)?;
+                }
+                list.into_any().unbind()
+            }
+            Value::Object(entries) => {
+                let dict = PyDict::new(py);
+                for (key, value) in entries {
+                    dict.set_item(key, convert(py, value)?)?;
+                }
+                dict.into_any().unbind()
+            }
+        })
+    }
+
+    convert(py, &serde_json::to_value(value).map_err(py_err)?)
+}
+
+/// One search hit, as in `thorc find`.
+#[pyclass(name = "Match", get_all)]
+#[derive(Debug, Clone)]
+pub struct PyMatch {
+    /// name of the index the template was found in.
+    index: String,
+    name: String,
+    version: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    deprecated: bool,
+    /// higher is better; only comparable within one search.
+    score: i64,
+}
+
+#[pymethods]
+impl PyMatch {
+    fn __repr__(&self) -> String {
+        format!("Match(index={:?}, name={:?})", self.index, self.name)
+    }
+}
+
+/// Finds templates and generates projects, with the default config, local
+/// index and cache unless others are given.
+#[pyclass(name = "Thorc", unsendable)]
+pub struct PyThorc(Thorc);
+
+#[pymethods]
+impl PyThorc {
+    #[new]
+    #[pyo3(signature = (config_file=None, local_index_file=None, cache_dir=None))]
+    fn new(
+        config_file: Option<PathBuf>,
+        local_index_file: Option<PathBuf>,
+        cache_dir: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let mut builder = Thorc::builder();
+
+        if let Some(config_file) = config_file {
+            builder = builder.config_file(config_file);
+        }
+        if let Some(local_index_file) = local_index_file {
+            builder = builder.local_index_file(local_index_file);
+        }
+        if let Some(cache_dir) = cache_dir {
+            builder = builder.cache_dir(cache_dir);
+        }
+
+        builder.build().map(PyThorc).map_err(py_err)
+    }
+
+    /// Searches for `term` in the local and remote indexes, or only in
+    /// `indexes` if given; best matches first.
+    #[pyo3(signature = (term, indexes=Vec::new()))]
+    fn find(&self, term: &str, indexes: Vec<String>) -> PyResult<Vec<PyMatch>> {
+        let result = self.0.find(term, &indexes).map_err(py_err)?;
+
+        Ok(result
+            .iter()
+            .map(|m| PyMatch {
+                index: m.index.to_string(),
+                name: m.template.name().to_string(),
+                version: m.template.version().map(|it| it.to_string()),
+                description: m.template.description().cloned(),
+                tags: m.template.tags().to_vec(),
+                deprecated: m.template.is_deprecated(),
+                score: m.score,
+            })
+            .collect())
+    }
+
+    /// The template a (possibly `index:`-qualified) name refers to, as a
+    /// dict in the same shape as in index files.
+    fn resolve(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        let template = self.0.resolve(None, name).map_err(py_err)?;
+
+        to_py(py, &template)
+    }
+
+    /// Generates a project, like `thorc new`, returning its lock file as a
+    /// dict. `on_conflict(src, dest)` is asked about existing files with
+    /// other contents, and returns `"keep"`, `"overwrite"` or `"merge"`;
+    /// without it, they are overwritten.
+    #[pyo3(signature = (
+        template,
+        directory,
+        project_name=None,
+        variables=BTreeMap::new(),
+        allow_dirty=false,
+        allow_deprecated=false,
+        on_conflict=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn generate(
+        &self,
+        py: Python<'_>,
+        template: String,
+        directory: PathBuf,
+        project_name: Option<String>,
+        variables: BTreeMap<String, String>,
+        allow_dirty: bool,
+        allow_deprecated: bool,
+        on_conflict: Option<PyObject>,
+    ) -> PyResult<PyObject> {
+        let mut options = GenerateOptions::new(template, directory);
+        options.project_name = project_name;
+        options.variables = variables;
+        options.allow_dirty = allow_dirty;
+        options.allow_deprecated = allow_deprecated;
+
+        let mut resolver = |src: &std::path::Path, dest: &std::path::Path| {
+            let on_conflict = match &on_conflict {
+                Some(on_conflict) => on_conflict,
+                None => return Ok(Conflict::Overwrite),
+            };
+
+            let answer = on_conflict
+                .call1(py, (src.to_path_buf(), dest.to_path_buf()))
+                .and_then(|it| it.extract::<String>(py))
+                .map_err(io::Error::other)?;
+
+            match answer.as_str() {
+                "keep" => Ok(Conflict::Keep),
+                "overwrite" => Ok(Conflict::Overwrite),
+                "merge" => Ok(Conflict::Merge),
+                other => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("on_conflict returned {:?}", other),
+                )),
+            }
+        };
+
+        let report =
+            generate::generate_project_with(&self.0, options, &mut resolver).map_err(py_err)?;
+
+        to_py(py, &report.lock)
+    }
+}
+
+#[pymodule]
+fn thorc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyThorc>()?;
+    m.add_class::<PyMatch>()?;
+    m.add("ThorcError", m.py().get_type::<ThorcError>())?;
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! A small JSON-over-HTTP protocol for serving templates from a service
+//! rather than a git repo. Relative to a registry's base url:
+//!
+//! - `GET search?q=<term>` returns a [`SearchResponse`]; an empty term lists
+//!   every template.
+//! - `GET templates/<name>` returns a [`RegistryTemplate`], or 404.
+//! - `GET templates/<name>/archive` returns the template as a `.tar.gz`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::DownloadError,
+    http::Downloader,
+    index::{TemplateIndex, SCHEMA_VERSION},
+    template::{exact_name, Metadata, SetupKind, Template},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistryTemplate {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<SetupKind>,
+}
+
+impl<'a> From<&'a Template> for RegistryTemplate {
+    fn from(t: &'a Template) -> Self {
+        Self {
+            name: t.name().to_string(),
+            description: t.description().cloned(),
+            metadata: t.metadata().clone(),
+            setup: t.setup().cloned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchResponse {
+    pub templates: Vec<RegistryTemplate>,
+}
+
+pub struct Registry<'a> {
+    pub url: &'a str,
+}
+
+impl<'a> Registry<'a> {
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), path)
+    }
+
+    pub fn archive_url(&self, name: &str) -> String {
+        self.endpoint(&format!("templates/{}/archive", name))
+    }
+
+    fn to_template(&self, t: RegistryTemplate) -> Template {
+        Template::Archive {
+            url: self.archive_url(&exact_name(&t.name, t.metadata.version.as_ref())),
+            name: t.name,
+            description: t.description,
+            metadata: t.metadata,
+            setup: t.setup,
+        }
+    }
+
+    #[cfg(feature = "network")]
+    pub fn search(&self, term: &str) -> Result<Vec<Template>, DownloadError> {
+        self.search_with(term, &crate::http::ReqwestDownloader)
+    }
+
+    pub fn search_with(
+        &self,
+        term: &str,
+        downloader: &dyn Downloader,
+    ) -> Result<Vec<Template>, DownloadError> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", term)
+            .finish();
+        let body = downloader.fetch_text(&format!("{}?{}", self.endpoint("search"), query))?;
+
+        let resp = serde_json::from_str::<SearchResponse>(&body)?;
+
+        Ok(resp
+            .templates
+            .into_iter()
+            .map(|it| self.to_template(it))
+            .collect())
+    }
+
+    #[cfg(feature = "network")]
+    pub fn get_template(&self, name: &str) -> Result<Option<Template>, DownloadError> {
+        self.get_template_with(name, &crate::http::ReqwestDownloader)
+    }
+
+    pub fn get_template_with(
+        &self,
+        name: &str,
+        downloader: &dyn Downloader,
+    ) -> Result<Option<Template>, DownloadError> {
+        let body = match downloader.fetch_text(&self.endpoint(&format!("templates/{}", name))) {
+            Ok(body) => body,
+            Err(DownloadError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let t = serde_json::from_str::<RegistryTemplate>(&body)?;
+
+        Ok(Some(self.to_template(t)))
+    }
+
+    /// Lists the whole registry as an index.
+    #[cfg(feature = "network")]
+    pub fn index(&self) -> Result<TemplateIndex, DownloadError> {
+        self.index_with(&crate::http::ReqwestDownloader)
+    }
+
+    pub fn index_with(&self, downloader: &dyn Downloader) -> Result<TemplateIndex, DownloadError> {
+        Ok(TemplateIndex {
+            schema_version: SCHEMA_VERSION,
+            for_remote: true,
+            namespaced: false,
+            includes: Vec::new(),
+            templates: self.search_with("", downloader)?.into_iter().collect(),
+        })
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn search_async(&self, term: &str) -> Result<Vec<Template>, DownloadError> {
+        let url = self.endpoint("search");
+        let resp = crate::http::async_client()?
+            .get(&url)
+            .query(&[("q", term)])
+            .send()
+            .await
+            .map_err(DownloadError::network(&url))?;
+        crate::http::check_status(&url, resp.status())?;
+        let body = resp.text().await.map_err(DownloadError::network(&url))?;
+
+        let resp = serde_json::from_str::<SearchResponse>(&body)?;
+
+        Ok(resp
+            .templates
+            .into_iter()
+            .map(|it| self.to_template(it))
+            .collect())
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn index_async(&self) -> Result<TemplateIndex, DownloadError> {
+        Ok(TemplateIndex {
+            schema_version: SCHEMA_VERSION,
+            for_remote: true,
+            namespaced: false,
+            includes: Vec::new(),
+            templates: self.search_async("").await?.into_iter().collect(),
+        })
+    }
+}
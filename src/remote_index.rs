@@ -1,38 +1,880 @@
-use std::{fs, path::{Path, PathBuf}};
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io, mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{error::GetIndexError, index::TemplateIndex, repo_def::RepoDef};
+use crate::{
+    error::{GetIndexError, InvalidRefreshError},
+    http::Downloader,
+    index::TemplateIndex,
+    patches::Format,
+    registry::Registry,
+    repo_def::{self, RepoDef},
+    template::{check_template_name_with, Template},
+    utils,
+};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct RemoteIndex {
     pub name: String,
     #[serde(default)]
     pub description: Option<String>,
 
-    #[serde(flatten)]
-    pub repo: RepoDef,
-
-    // path in repo to index file
+    // path in repo to index file; only written for repos
     #[serde(default = "default_remote_index_path")]
     pub path: PathBuf,
+
+    #[serde(default, skip_serializing_if = "Refresh::is_default")]
+    pub refresh: Refresh,
+
+    // last, see `RepoDef::git_provider`
+    #[serde(flatten)]
+    pub source: IndexSource,
+}
+
+/// How [`RemoteIndex`] is written, see [`IndexSource::repo_path`].
+#[derive(Serialize)]
+struct RemoteIndexRepr<'a> {
+    name: &'a str,
+    description: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Refresh::is_default")]
+    refresh: Refresh,
+    #[serde(flatten)]
+    source: &'a IndexSource,
+}
+
+impl Serialize for RemoteIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RemoteIndexRepr {
+            name: &self.name,
+            description: &self.description,
+            path: self.source.repo_path(&self.path),
+            refresh: self.refresh,
+            source: &self.source,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// How eagerly a cached index (and the indexes it includes) is downloaded
+/// again: `refresh = "always"`, `"never"` or a duration like `"30m"`, `"1h"`
+/// or `"7d"`. Registries aren't cached, so they are always fetched.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Refresh {
+    /// on every use; unchanged url-hosted indexes are still not downloaded
+    /// again, by their ETag.
+    Always,
+    /// once the cached copy is older than this.
+    After(Duration),
+    /// only when there is no cached copy.
+    Never,
+}
+
+/// How long cached indexes and templates are used without asking whether
+/// they changed, unless an index says otherwise.
+pub const DEFAULT_REFRESH: Refresh = Refresh::After(Duration::from_secs(60));
+
+impl Default for Refresh {
+    fn default() -> Self {
+        DEFAULT_REFRESH
+    }
+}
+
+impl Refresh {
+    pub fn is_default(&self) -> bool {
+        *self == DEFAULT_REFRESH
+    }
+
+    /// Whether the copy cached at `path` is missing or due to be downloaded
+    /// again.
+    pub(crate) fn is_stale(self, path: &Path) -> io::Result<bool> {
+        if !path.exists() {
+            return Ok(true);
+        }
+
+        match self {
+            Refresh::Always => Ok(true),
+            Refresh::Never => Ok(false),
+            Refresh::After(ttl) => {
+                let modified = path.metadata()?.modified()?;
+                // too far out to ever pass
+                Ok(modified
+                    .checked_add(ttl)
+                    .is_some_and(|fresh_until| SystemTime::now() > fresh_until))
+            }
+        }
+    }
+}
+
+const REFRESH_UNITS: [(char, u64); 4] = [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)];
+
+impl FromStr for Refresh {
+    type Err = InvalidRefreshError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidRefreshError(s.to_string());
+
+        match s {
+            "always" => return Ok(Refresh::Always),
+            "never" => return Ok(Refresh::Never),
+            _ => {}
+        }
+
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let (_, secs) = REFRESH_UNITS
+            .iter()
+            .find(|(it, _)| *it == unit)
+            .ok_or_else(invalid)?;
+        let amount = s[..s.len() - 1].parse::<u64>().map_err(|_| invalid())?;
+        let secs = amount.checked_mul(*secs).ok_or_else(invalid)?;
+
+        Ok(Refresh::After(Duration::from_secs(secs)))
+    }
+}
+
+impl TryFrom<String> for Refresh {
+    type Error = InvalidRefreshError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Refresh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refresh::Always => write!(f, "always"),
+            Refresh::Never => write!(f, "never"),
+            Refresh::After(ttl) => {
+                let secs = ttl.as_secs();
+                // the largest unit that loses nothing
+                let (unit, per) = REFRESH_UNITS
+                    .iter()
+                    .find(|(_, per)| secs % per == 0)
+                    .unwrap_or(&('s', 1));
+
+                write!(f, "{}{}", secs / per, unit)
+            }
+        }
+    }
+}
+
+impl From<Refresh> for String {
+    fn from(refresh: Refresh) -> Self {
+        refresh.to_string()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum IndexSource {
+    /// index file served over http, e.g. by `thorc serve`.
+    Url {
+        url: String,
+    },
+    /// service speaking the [registry protocol](crate::registry).
+    Registry {
+        registry: String,
+    },
+    Repo(RepoDef),
+}
+
+impl IndexSource {
+    /// Identifies the index file at `path` in this source, to tell when an
+    /// index includes itself.
+    fn id(&self, path: &Path) -> String {
+        match self {
+            IndexSource::Url { url } => url.clone(),
+            IndexSource::Registry { registry } => registry.clone(),
+            IndexSource::Repo(repo) => format!("{}:{}", repo.link(), path.display()),
+        }
+    }
+
+    /// Format of the index file at `path` in this source: by the extension
+    /// of the url or `path`, TOML if there's none.
+    fn format(&self, path: &Path) -> Option<Format> {
+        match self {
+            IndexSource::Url { url } => {
+                let url = url.split(['?', '#']).next().unwrap_or(url);
+                Format::from_path(Path::new(url))
+            }
+            IndexSource::Registry { .. } => None,
+            IndexSource::Repo(_) => Format::from_path(path),
+        }
+    }
+
+    /// `path`, if this source is a repo; other sources have no files to
+    /// choose from, so their path is left out when written.
+    fn repo_path<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        match self {
+            IndexSource::Repo(_) => Some(path),
+            IndexSource::Url { .. } | IndexSource::Registry { .. } => None,
+        }
+    }
 }
 
 fn default_remote_index_path() -> PathBuf {
     PathBuf::from("index.toml")
 }
 
+/// Where the index file at `url` is cached, and where its ETag is.
+fn url_index_paths(cache: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let file = cache.join(format!("{}.index", repo_def::url_cache_file(url)));
+    let etag = file.with_extension("etag");
+    (file, etag)
+}
+
+/// Fetches the index file at `url`, keeping a copy in the cache. Once stale
+/// by `refresh`, the copy is only downloaded again when the server says it
+/// changed, by its ETag.
+fn fetch_url_index(
+    url: &str,
+    cache: &Path,
+    downloader: &dyn Downloader,
+    refresh: Refresh,
+) -> Result<String, GetIndexError> {
+    let (file, etag_f) = url_index_paths(cache, url);
+    if !refresh.is_stale(&file)? {
+        tracing::debug!(
+            url,
+            cache = "hit",
+            "Cached index is fresh, not asking the server"
+        );
+        return Ok(fs::read_to_string(&file)?);
+    }
+
+    // the ETag is only good for as long as the file it belongs to
+    let prev_etag = if file.exists() {
+        fs::read_to_string(&etag_f).ok()
+    } else {
+        None
+    }
+    .and_then(|it| repo_def::etag_for(&it, url).map(str::to_owned));
+
+    let fetched = match downloader.fetch(url, prev_etag.as_deref())? {
+        Some(fetched) => fetched,
+        None => {
+            tracing::debug!(
+                url,
+                cache = "hit",
+                "Index not modified, using the cached copy"
+            );
+            return Ok(fs::read_to_string(&file)?);
+        }
+    };
+    tracing::debug!(
+        url,
+        cache = "miss",
+        bytes = fetched.bytes.len(),
+        "Downloaded index"
+    );
+
+    fs::create_dir_all(cache)?;
+    utils::write_atomic(&file, &fetched.bytes)?;
+    match &fetched.etag {
+        Some(etag) => fs::write(&etag_f, repo_def::etag_record(url, etag))?,
+        None if etag_f.exists() => fs::remove_file(&etag_f)?,
+        None => {}
+    }
+
+    let text = String::from_utf8(fetched.bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    Ok(text)
+}
+
+#[cfg(feature = "async")]
+async fn fetch_url_index_async(
+    url: &str,
+    cache: &Path,
+    refresh: Refresh,
+) -> Result<String, GetIndexError> {
+    let (file, etag_f) = url_index_paths(cache, url);
+    if !refresh.is_stale(&file)? {
+        return Ok(tokio::fs::read_to_string(&file).await?);
+    }
+
+    if !file.exists() && etag_f.exists() {
+        tokio::fs::remove_file(&etag_f).await?;
+    }
+
+    tokio::fs::create_dir_all(cache).await?;
+    repo_def::download_file_async(url, &file, Some(&etag_f)).await?;
+
+    Ok(tokio::fs::read_to_string(&file).await?)
+}
+
+/// How deep includes may nest, counting from the index thorc was pointed at.
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Another index whose templates an index includes, declared as `[[include]]`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IncludedIndex {
+    #[serde(flatten)]
+    pub source: IndexSource,
+
+    // path in repo to index file; only written for repos
+    #[serde(default = "default_remote_index_path")]
+    pub path: PathBuf,
+}
+
+/// How [`IncludedIndex`] is written, see [`IndexSource::repo_path`].
+#[derive(Serialize)]
+struct IncludedIndexRepr<'a> {
+    #[serde(flatten)]
+    source: &'a IndexSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a Path>,
+}
+
+impl Serialize for IncludedIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        IncludedIndexRepr {
+            source: &self.source,
+            path: self.source.repo_path(&self.path),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl IncludedIndex {
+    pub(crate) fn id(&self) -> String {
+        self.source.id(&self.path)
+    }
+}
+
+struct PendingInclude {
+    include: IncludedIndex,
+    id: String,
+    /// ids of the indexes that led to this include, outermost first.
+    chain: Vec<String>,
+}
+
+/// Merges an index with everything it includes, transitively. Templates of
+/// an including index win over those of the indexes it includes, and every
+/// index is merged only once.
+struct IncludeGraph {
+    merged: TemplateIndex,
+    pending: Vec<PendingInclude>,
+    visited: BTreeSet<String>,
+}
+
+impl IncludeGraph {
+    fn new(root_id: String, root: TemplateIndex) -> Self {
+        let mut graph = Self {
+            merged: TemplateIndex {
+                includes: Vec::new(),
+                ..root.clone()
+            },
+            pending: Vec::new(),
+            visited: BTreeSet::from([root_id.clone()]),
+        };
+        graph.queue(root.includes, vec![root_id]);
+        graph
+    }
+
+    fn queue(&mut self, includes: Vec<IncludedIndex>, chain: Vec<String>) {
+        // reversed, so includes are merged in the order they're declared
+        for include in includes.into_iter().rev() {
+            let id = include.id();
+            self.pending.push(PendingInclude {
+                include,
+                id,
+                chain: chain.clone(),
+            });
+        }
+    }
+
+    /// The next include to fetch, skipping ones already merged.
+    fn next(&mut self) -> Result<Option<PendingInclude>, GetIndexError> {
+        while let Some(pending) = self.pending.pop() {
+            if pending.chain.contains(&pending.id) {
+                let mut cycle = pending.chain;
+                cycle.push(pending.id);
+                return Err(GetIndexError::IncludeCycle(cycle.join(" -> ")));
+            }
+
+            if pending.chain.len() > MAX_INCLUDE_DEPTH {
+                return Err(GetIndexError::IncludeTooDeep(pending.id));
+            }
+
+            if self.visited.insert(pending.id.clone()) {
+                return Ok(Some(pending));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn merge(&mut self, pending: PendingInclude, mut index: TemplateIndex) {
+        let mut chain = pending.chain;
+        chain.push(pending.id);
+        self.queue(mem::take(&mut index.includes), chain);
+
+        // `insert` keeps templates that are already there
+        self.merged.templates.extend(index.templates);
+    }
+}
+
+/// Checks an index fetched for the remote index `name`. Local templates in
+/// an index meant for remote use are an error; templates with invalid names
+/// are dropped, and `duplicates` (see [`TemplateIndex::duplicates_in`]) only
+/// warned about.
+fn validate(
+    name: &str,
+    index: &mut TemplateIndex,
+    duplicates: &[String],
+) -> Result<(), GetIndexError> {
+    if index.for_remote {
+        let local = index
+            .templates
+            .iter()
+            .find(|it| matches!(it, Template::Local { .. }));
+        if let Some(local) = local {
+            return Err(GetIndexError::LocalTemplate(local.versioned_name()));
+        }
+    }
+
+    let namespaced = index.namespaced;
+    index.templates.retain(|template| {
+        match check_template_name_with(template.name(), namespaced) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::warn!(
+                    "Index {}: ignoring template {:?}, its name is invalid: {}",
+                    name,
+                    template.name(),
+                    err
+                );
+                false
+            }
+        }
+    });
+
+    for duplicate in duplicates {
+        tracing::warn!(
+            "Index {}: {} is listed more than once; using the first",
+            name,
+            duplicate
+        );
+    }
+
+    Ok(())
+}
+
+/// Fetches the single index file at `path` in `source`, without its includes,
+/// and [`validate`]s it as part of the remote index `name`.
+fn fetch_index_with(
+    name: &str,
+    source: &IndexSource,
+    path: &Path,
+    cache: &Path,
+    downloader: &dyn Downloader,
+    refresh: Refresh,
+) -> Result<TemplateIndex, GetIndexError> {
+    let index_contents = match source {
+        IndexSource::Url { url } => fetch_url_index(url, cache, downloader, refresh)?,
+        IndexSource::Registry { registry } => {
+            let mut index = Registry { url: registry }.index_with(downloader)?;
+            validate(name, &mut index, &[])?;
+            return Ok(index);
+        }
+        #[cfg(feature = "network")]
+        IndexSource::Repo(repo) => {
+            let p = repo.download_with_refresh(cache, downloader, refresh)?;
+            // `path` may come from an include in an index fetched earlier
+            let index_p = utils::join_within(&p, path)
+                .ok_or_else(|| GetIndexError::PathEscapes(path.to_owned()))?;
+
+            fs::read_to_string(index_p)?
+        }
+        #[cfg(not(feature = "network"))]
+        IndexSource::Repo(repo) => {
+            return Err(crate::error::DownloadError::Offline(repo.archive_link()).into());
+        }
+    };
+
+    let format = source.format(path);
+    let mut index = TemplateIndex::from_str_as(&index_contents, format)?;
+    validate(
+        name,
+        &mut index,
+        &TemplateIndex::duplicates_in(&index_contents, format),
+    )?;
+
+    Ok(index)
+}
+
+#[cfg(feature = "async")]
+async fn fetch_index_async(
+    name: &str,
+    source: &IndexSource,
+    path: &Path,
+    cache: &Path,
+    refresh: Refresh,
+) -> Result<TemplateIndex, GetIndexError> {
+    let index_contents = match source {
+        IndexSource::Url { url } => fetch_url_index_async(url, cache, refresh).await?,
+        IndexSource::Registry { registry } => {
+            let mut index = Registry { url: registry }.index_async().await?;
+            validate(name, &mut index, &[])?;
+            return Ok(index);
+        }
+        IndexSource::Repo(repo) => {
+            let p = repo.download_async_with_refresh(cache, refresh).await?;
+            let index_p = utils::join_within(&p, path)
+                .ok_or_else(|| GetIndexError::PathEscapes(path.to_owned()))?;
+
+            tokio::fs::read_to_string(index_p).await?
+        }
+    };
+
+    let format = source.format(path);
+    let mut index = TemplateIndex::from_str_as(&index_contents, format)?;
+    validate(
+        name,
+        &mut index,
+        &TemplateIndex::duplicates_in(&index_contents, format),
+    )?;
+
+    Ok(index)
+}
 
 impl RemoteIndex {
+    #[cfg(feature = "network")]
     pub fn get_index(&self, cache: &Path) -> Result<TemplateIndex, GetIndexError> {
-        let p = self.repo.download(cache)?;
+        self.get_index_with(cache, &crate::http::ReqwestDownloader)
+    }
+
+    /// Fetches the index and everything it includes, merged into one, all
+    /// downloaded again as eagerly as `refresh` says. Repo-hosted indexes
+    /// need the `network` feature, to extract the repo.
+    #[tracing::instrument(level = "debug", skip_all, fields(index = %self.name))]
+    pub fn get_index_with(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<TemplateIndex, GetIndexError> {
+        let root = fetch_index_with(
+            &self.name,
+            &self.source,
+            &self.path,
+            cache,
+            downloader,
+            self.refresh,
+        )?;
+
+        let mut graph = IncludeGraph::new(self.source.id(&self.path), root);
+        while let Some(pending) = graph.next()? {
+            tracing::debug!(
+                include = %pending.id,
+                depth = pending.chain.len(),
+                "Fetching included index"
+            );
+            let IncludedIndex { source, path } = &pending.include;
+            let name = format!("{} (include {})", self.name, pending.id);
+            let index = fetch_index_with(&name, source, path, cache, downloader, self.refresh)?;
+            graph.merge(pending, index);
+        }
+
+        tracing::debug!(templates = graph.merged.templates.len(), "Fetched index");
+
+        Ok(graph.merged)
+    }
+
+    /// Like `get_index`, without blocking the async runtime.
+    #[cfg(feature = "async")]
+    #[tracing::instrument(level = "debug", skip_all, fields(index = %self.name))]
+    pub async fn get_index_async(&self, cache: &Path) -> Result<TemplateIndex, GetIndexError> {
+        let root =
+            fetch_index_async(&self.name, &self.source, &self.path, cache, self.refresh).await?;
+
+        let mut graph = IncludeGraph::new(self.source.id(&self.path), root);
+        while let Some(pending) = graph.next()? {
+            let IncludedIndex { source, path } = &pending.include;
+            let name = format!("{} (include {})", self.name, pending.id);
+            let index = fetch_index_async(&name, source, path, cache, self.refresh).await?;
+            graph.merge(pending, index);
+        }
+
+        Ok(graph.merged)
+    }
+
+    /// Removes everything cached for this index; returns the removed paths.
+    pub fn purge_cache(&self, cache: &Path) -> io::Result<Vec<PathBuf>> {
+        match &self.source {
+            IndexSource::Repo(repo) => repo.purge_cache(cache),
+            IndexSource::Url { url } => {
+                let (file, etag_f) = url_index_paths(cache, url);
+                let mut removed = Vec::new();
+
+                for path in [file, etag_f] {
+                    if path.exists() {
+                        fs::remove_file(&path)?;
+                        removed.push(path);
+                    }
+                }
+
+                Ok(removed)
+            }
+            IndexSource::Registry { .. } => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_refresh() {
+        assert_eq!("always".parse::<Refresh>().unwrap(), Refresh::Always);
+        assert_eq!("never".parse::<Refresh>().unwrap(), Refresh::Never);
+        assert_eq!(
+            "30m".parse::<Refresh>().unwrap(),
+            Refresh::After(Duration::from_secs(30 * 60))
+        );
+        assert_eq!("7d".parse::<Refresh>().unwrap().to_string(), "7d",);
+    }
+
+    #[test]
+    fn rejects_bad_refresh() {
+        for s in ["", "d", "10", "10w", "-1h", "1.5h", "999999999999999d"] {
+            assert!(s.parse::<Refresh>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn huge_refresh_is_never_stale() {
+        let path = std::env::temp_dir().join(format!("thorc-test-{}-stale", std::process::id()));
+        fs::write(&path, "").unwrap();
+
+        let stale = Refresh::After(Duration::from_secs(u64::MAX)).is_stale(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(!stale.unwrap());
+    }
+
+    #[test]
+    fn only_repos_write_their_index_path() {
+        for (source, written) in [
+            ("url = \"https://example.com/index.toml\"", false),
+            ("registry = \"https://example.com\"", false),
+            ("user = \"u\"\nrepo = \"r\"", true),
+        ] {
+            let index: RemoteIndex = toml::from_str(&format!("name = \"i\"\n{}", source)).unwrap();
+            let toml = toml::to_string(&index).unwrap();
+            assert_eq!(toml.contains("path = "), written, "{}", toml);
+
+            let included: IncludedIndex = toml::from_str(source).unwrap();
+            let toml = toml::to_string(&included).unwrap();
+            assert_eq!(toml.contains("path = "), written, "{}", toml);
+        }
+    }
+
+    /// Serves the index files in `indexes` by their url.
+    struct Indexes<'a>(&'a [(&'a str, &'a str)]);
+
+    impl Downloader for Indexes<'_> {
+        fn fetch(
+            &self,
+            url: &str,
+            _: Option<&str>,
+        ) -> Result<Option<crate::http::Fetched>, crate::error::DownloadError> {
+            let (_, index) = self
+                .0
+                .iter()
+                .find(|(it, _)| *it == url)
+                .ok_or_else(|| crate::error::DownloadError::NotFound(url.to_string()))?;
+
+            Ok(Some(crate::http::Fetched {
+                bytes: index.as_bytes().to_vec(),
+                ..Default::default()
+            }))
+        }
+    }
+
+    /// `[[template]]` named `name`, downloaded from `url`.
+    fn archive(name: &str, url: &str) -> String {
+        format!("[[template]]\nname = \"{}\"\nurl = \"{}\"\n", name, url)
+    }
+
+    fn get_url_index(name: &str, indexes: &[(&str, &str)]) -> Result<TemplateIndex, GetIndexError> {
+        let scratch = crate::utils::tests::Scratch::new(name);
+        let index: RemoteIndex = toml::from_str("name = \"i\"\nurl = \"https://i/0\"").unwrap();
+        index.get_index_with(&scratch.path().join("cache"), &Indexes(indexes))
+    }
+
+    fn url_of(index: &TemplateIndex, name: &str) -> String {
+        match index.find_exact(name) {
+            Some(Template::Archive { url, .. }) => url.clone(),
+            other => panic!("{}: {:?}", name, other),
+        }
+    }
 
-        let index_p = p.join(&self.path);
+    #[test]
+    fn includers_win_over_what_they_include() {
+        let root = format!(
+            "[[include]]\nurl = \"https://i/1\"\n\n[[include]]\nurl = \"https://i/2\"\n\n{}",
+            archive("a", "https://root/a")
+        );
+        let first = format!(
+            "[[include]]\nurl = \"https://i/2\"\n\n{}{}",
+            archive("a", "https://1/a"),
+            archive("b", "https://1/b")
+        );
+        let second = format!(
+            "{}{}",
+            archive("b", "https://2/b"),
+            archive("c", "https://2/c")
+        );
+        let indexes = [
+            ("https://i/0", &*root),
+            ("https://i/1", &*first),
+            ("https://i/2", &*second),
+        ];
 
-        let index_contents = fs::read_to_string(index_p)?;
+        let index = get_url_index("include-order", &indexes).unwrap();
 
-        let index = toml::from_str(&index_contents)?;
+        assert_eq!(url_of(&index, "a"), "https://root/a");
+        assert_eq!(url_of(&index, "b"), "https://1/b");
+        assert_eq!(url_of(&index, "c"), "https://2/c");
+        assert!(index.includes.is_empty());
+    }
+
+    #[test]
+    fn indexes_included_twice_are_fetched_once() {
+        // 1 and 2 both include 3; only a cycle back to an includer is an error
+        let index = get_url_index(
+            "include-diamond",
+            &[
+                (
+                    "https://i/0",
+                    "[[include]]\nurl = \"https://i/1\"\n\n[[include]]\nurl = \"https://i/2\"\n",
+                ),
+                ("https://i/1", "[[include]]\nurl = \"https://i/3\"\n"),
+                ("https://i/2", "[[include]]\nurl = \"https://i/3\"\n"),
+                ("https://i/3", ""),
+            ],
+        );
+
+        assert!(index.is_ok(), "{:?}", index);
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let index = get_url_index(
+            "include-cycle",
+            &[
+                ("https://i/0", "[[include]]\nurl = \"https://i/1\"\n"),
+                ("https://i/1", "[[include]]\nurl = \"https://i/0\"\n"),
+            ],
+        );
+
+        assert!(
+            matches!(&index, Err(GetIndexError::IncludeCycle(cycle)) if cycle == "https://i/0 -> https://i/1 -> https://i/0"),
+            "{:?}",
+            index
+        );
+    }
+
+    #[test]
+    fn includes_nest_only_so_deep() {
+        let chain = (0..=MAX_INCLUDE_DEPTH + 1)
+            .map(|i| {
+                (
+                    format!("https://i/{}", i),
+                    format!("[[include]]\nurl = \"https://i/{}\"\n", i + 1),
+                )
+            })
+            .collect::<Vec<_>>();
+        let chain = chain
+            .iter()
+            .map(|(url, index)| (&**url, &**index))
+            .collect::<Vec<_>>();
+
+        let index = get_url_index("include-depth", &chain);
+
+        assert!(
+            matches!(&index, Err(GetIndexError::IncludeTooDeep(id)) if *id == format!("https://i/{}", MAX_INCLUDE_DEPTH + 1)),
+            "{:?}",
+            index
+        );
+    }
+
+    /// Serves `archive` for every archive url; nothing else exists.
+    #[cfg(feature = "network")]
+    struct Archives(Vec<u8>);
+
+    #[cfg(feature = "network")]
+    impl Downloader for Archives {
+        fn fetch(
+            &self,
+            url: &str,
+            _: Option<&str>,
+        ) -> Result<Option<crate::http::Fetched>, crate::error::DownloadError> {
+            if !url.ends_with(".tar.gz") {
+                return Err(crate::error::DownloadError::NotFound(url.to_string()));
+            }
+
+            Ok(Some(crate::http::Fetched {
+                bytes: self.0.clone(),
+                ..Default::default()
+            }))
+        }
+    }
+
+    /// Fetches the index at `path` of a repo whose `index.toml` includes
+    /// `include` from the same repo, next to a `secret.toml` outside of it.
+    #[cfg(feature = "network")]
+    fn get_index(name: &str, path: &str, include: &str) -> Result<TemplateIndex, GetIndexError> {
+        let scratch = crate::utils::tests::Scratch::new(name);
+        let repo = "user = \"u\"\nrepo = \"r\"\ngit_ref = \"main\"";
+        scratch.write(
+            "repo/index.toml",
+            &format!("[[include]]\n{}\npath = \"{}\"\n", repo, include),
+        );
+        scratch.write("repo/other.toml", "");
+        scratch.write("secret.toml", "");
+        let archive = utils::archive_dir(&scratch.path().join("repo"), "r-main").unwrap();
+
+        let index: RemoteIndex =
+            toml::from_str(&format!("name = \"i\"\n{}\npath = \"{}\"", repo, path)).unwrap();
+        index.get_index_with(&scratch.path().join("cache"), &Archives(archive))
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn follows_includes_within_the_repo() {
+        assert!(get_index("include-within", "index.toml", "other.toml").is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn includes_cannot_leave_the_repo() {
+        for include in ["../secret.toml", "../../secret.toml", "/etc/hostname"] {
+            let index = get_index("include-escapes", "index.toml", include);
+            assert!(
+                matches!(&index, Err(GetIndexError::PathEscapes(path)) if path == Path::new(include)),
+                "{}: {:?}",
+                include,
+                index
+            );
+        }
+    }
 
-        Ok(index)
+    #[test]
+    #[cfg(feature = "network")]
+    fn index_paths_cannot_leave_the_repo() {
+        let index = get_index("path-escapes", "../secret.toml", "other.toml");
+        assert!(matches!(index, Err(GetIndexError::PathEscapes(_))));
     }
 }
@@ -0,0 +1,82 @@
+//! Renaming files and directories throughout a project.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::error::RenameError;
+
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Works out the renames of everything under `root` whose name `rename`
+/// changes. Children come before their parents, so the renames can be
+/// applied in order.
+pub fn plan<F>(root: &Path, rename: F) -> Result<Vec<Rename>, RenameError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn walk<F>(dir: &Path, rename: &F, renames: &mut Vec<Rename>) -> Result<(), RenameError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|it| it.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
+            let name = match path.file_name().and_then(|it| it.to_str()) {
+                Some(".git") | None => continue,
+                Some(name) => name,
+            };
+
+            if fs::symlink_metadata(&path)?.is_dir() {
+                walk(&path, rename, renames)?;
+            }
+
+            if let Some(new_name) = rename(name).filter(|it| it != name) {
+                // only ever a sibling, never a path elsewhere
+                if new_name.is_empty()
+                    || new_name == "."
+                    || new_name == ".."
+                    || new_name.contains(['/', '\\'])
+                {
+                    return Err(RenameError::InvalidName(new_name));
+                }
+
+                renames.push(Rename {
+                    to: path.with_file_name(new_name),
+                    from: path,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut renames = Vec::new();
+    walk(root, &rename, &mut renames)?;
+
+    let mut targets = BTreeSet::new();
+    for rename in &renames {
+        if rename.to.exists() || !targets.insert(&rename.to) {
+            return Err(RenameError::Collision(rename.to.clone()));
+        }
+    }
+
+    Ok(renames)
+}
+
+pub fn apply(renames: &[Rename]) -> io::Result<()> {
+    for rename in renames {
+        fs::rename(&rename.from, &rename.to)?;
+    }
+
+    Ok(())
+}
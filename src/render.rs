@@ -0,0 +1,34 @@
+//! A minimal `{{ variable }}` substitution, for hooks that render files
+//! after computing the values they need.
+
+use std::collections::BTreeMap;
+
+use crate::error::RenderError;
+
+/// Replaces every `{{ name }}` in `text` with the value of the variable.
+///
+/// Variable names are matched case-insensitively, the same way they are
+/// exposed to hooks as `THORC_VAR_<NAME>`. Braces that don't enclose a
+/// variable name are left as they are.
+pub fn render(text: &str, variables: &BTreeMap<String, String>) -> Result<String, RenderError> {
+    let placeholder = regex::Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_-]*)\s*\}\}").unwrap();
+
+    let mut rendered = String::with_capacity(text.len());
+    let mut last = 0;
+    for captures in placeholder.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        let name = &captures[1];
+        let value = variables
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .ok_or_else(|| RenderError::UndefinedVariable(name.to_string()))?;
+
+        rendered.push_str(&text[last..whole.start()]);
+        rendered.push_str(value);
+        last = whole.end();
+    }
+    rendered.push_str(&text[last..]);
+
+    Ok(rendered)
+}
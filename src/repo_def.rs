@@ -1,23 +1,68 @@
-use std::{fs, io::{self, Write}, path::{Path, PathBuf}, str::FromStr, time::{Duration, SystemTime}};
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+#[cfg(feature = "network")]
+use std::{process::Command, time::Instant};
 
+#[cfg(feature = "network")]
 use flate2::read::GzDecoder;
-use reqwest::{StatusCode, header};
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+#[cfg(feature = "network")]
 use tar::Archive;
 
-use crate::{error::{DownloadError, NoSuchGitProviderError}, utils::hash};
+#[cfg(feature = "network")]
+use crate::{
+    cancel::CancelToken,
+    error::DownloadError,
+    http::Downloader,
+    remote_index::{Refresh, DEFAULT_REFRESH},
+    utils::{self, hash},
+};
+use crate::{
+    error::{InvalidGitRefError, NoSuchGitProviderError},
+    utils::hash_buffer,
+};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum GitProvider {
+    #[default]
     GitHub,
     GitLab,
+    Codeberg,
+    /// with the organization and project as the user, `org/project`.
+    AzureDevOps,
+    /// any other forge or artifact server, with urls made from templates
+    /// where `{user}`, `{repo}` and `{ref}` are replaced by the repo's.
+    Custom {
+        archive_url_template: String,
+        tree_url_template: String,
+    },
 }
 
 impl GitProvider {
-    fn simple_name(&self) -> &'static str {
+    pub(crate) fn simple_name(&self) -> &'static str {
         match self {
             GitProvider::GitHub => "github",
             GitProvider::GitLab => "gitlab",
+            GitProvider::Codeberg => "codeberg",
+            GitProvider::AzureDevOps => "azure",
+            GitProvider::Custom { .. } => "custom",
+        }
+    }
+
+    /// Where repos are hosted unless a [`RepoDef`] names another host; none
+    /// for [`GitProvider::Custom`], whose url templates say where.
+    pub fn public_host(&self) -> Option<&'static str> {
+        match self {
+            GitProvider::GitHub => Some("github.com"),
+            GitProvider::GitLab => Some("gitlab.com"),
+            GitProvider::Codeberg => Some("codeberg.org"),
+            GitProvider::AzureDevOps => Some("dev.azure.com"),
+            GitProvider::Custom { .. } => None,
         }
     }
 }
@@ -29,6 +74,8 @@ impl FromStr for GitProvider {
         let gp = match s {
             "github" | "GitHub" => GitProvider::GitHub,
             "gitlab" | "GitLab" => GitProvider::GitLab,
+            "codeberg" | "Codeberg" => GitProvider::Codeberg,
+            "azure" | "AzureDevOps" => GitProvider::AzureDevOps,
             _ => return Err(NoSuchGitProviderError),
         };
 
@@ -36,115 +83,1294 @@ impl FromStr for GitProvider {
     }
 }
 
-impl Default for GitProvider {
-    fn default() -> Self {
-        Self::GitHub
+/// How providers are written in indexes and configs: their names, or a
+/// table for [`GitProvider::Custom`], which TOML can't have as an enum.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum GitProviderRepr {
+    Name(String),
+    Custom {
+        archive_url_template: String,
+        tree_url_template: String,
+    },
+}
+
+impl Serialize for GitProvider {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match self {
+            GitProvider::GitHub => "GitHub",
+            GitProvider::GitLab => "GitLab",
+            GitProvider::Codeberg => "Codeberg",
+            GitProvider::AzureDevOps => "AzureDevOps",
+            GitProvider::Custom {
+                archive_url_template,
+                tree_url_template,
+            } => {
+                return GitProviderRepr::Custom {
+                    archive_url_template: archive_url_template.clone(),
+                    tree_url_template: tree_url_template.clone(),
+                }
+                .serialize(serializer)
+            }
+        };
+
+        GitProviderRepr::Name(name.to_string()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GitProvider {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match GitProviderRepr::deserialize(deserializer)? {
+            GitProviderRepr::Name(name) => name.parse().map_err(serde::de::Error::custom),
+            GitProviderRepr::Custom {
+                archive_url_template,
+                tree_url_template,
+            } => Ok(GitProvider::Custom {
+                archive_url_template,
+                tree_url_template,
+            }),
+        }
+    }
+}
+
+/// How a repo's files are fetched: `fetch = "archive"`, `"git"` or `"ssh"`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Fetch {
+    /// downloading the provider's archive of the ref.
+    #[default]
+    Archive,
+    /// a shallow clone with `git`, which must be installed, for refs the
+    /// provider has no archive of, like arbitrary commits on some forges.
+    Git,
+    /// a shallow clone with `git` over SSH, with the user's keys or
+    /// ssh-agent, for private repos that can only be reached that way.
+    Ssh,
+}
+
+impl Fetch {
+    pub fn is_default(&self) -> bool {
+        *self == Fetch::Archive
+    }
+
+    /// Whether the repo is checked out with `git` rather than downloaded.
+    pub fn uses_git(&self) -> bool {
+        matches!(self, Fetch::Git | Fetch::Ssh)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RepoDef {
-    #[serde(default)]
-    pub git_provider: GitProvider,
+    /// a self-hosted instance of the provider, like `gitlab.example.com`;
+    /// the provider's [public host](GitProvider::public_host) if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
 
+    /// the user or organization; on GitLab, possibly a group with
+    /// subgroups, like `group/subgroup`.
     pub user: String,
     pub repo: String,
 
-    #[serde(default = "default_branch")]
+    /// the repo's default branch, found when it is first downloaded, if
+    /// empty.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_git_ref",
+        skip_serializing_if = "String::is_empty"
+    )]
     pub git_ref: String,
+
+    #[serde(default, skip_serializing_if = "Fetch::is_default")]
+    pub fetch: Fetch,
+
+    /// the commit `git_ref` points at, once [resolved](RepoDef::resolve) or
+    /// pinned by a lock file; what is downloaded and cached instead of the
+    /// ref, so that the same key always means the same files.
+    #[serde(skip)]
+    pub commit: Option<String>,
+
+    // last, as a custom provider is a table, and TOML only has those after
+    // the plain values
+    #[serde(default)]
+    pub git_provider: GitProvider,
+}
+
+/// Refs end up in `git` command lines, where one starting with `-` would be
+/// taken for an option.
+pub fn check_git_ref(git_ref: &str) -> Result<(), InvalidGitRefError> {
+    if git_ref.starts_with('-') {
+        return Err(InvalidGitRefError(git_ref.to_string()));
+    }
+
+    Ok(())
+}
+
+fn deserialize_git_ref<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let git_ref = String::deserialize(deserializer)?;
+    check_git_ref(&git_ref).map_err(D::Error::custom)?;
+
+    Ok(git_ref)
 }
 
 impl RepoDef {
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref().or(self.git_provider.public_host())
+    }
+
+    /// `template` with `{user}`, `{repo}` and `{ref}` replaced by this repo's
+    /// and `git_ref`; custom providers have no default branch to find, so it
+    /// is `main`.
+    fn expand(&self, template: &str, git_ref: &str) -> String {
+        let git_ref = match git_ref {
+            "" => FALLBACK_BRANCH,
+            git_ref => git_ref,
+        };
+
+        template
+            .replace("{user}", &self.user)
+            .replace("{repo}", &self.repo)
+            .replace("{ref}", git_ref)
+    }
+
     pub fn link(&self) -> String {
-        match self.git_provider {
+        let host = self.host().unwrap_or_default();
+
+        // the repo's page, which shows the default branch
+        if self.git_ref.is_empty() {
+            match &self.git_provider {
+                GitProvider::AzureDevOps => {
+                    return format!("https://{}/{}/_git/{}", host, self.user, self.repo)
+                }
+                GitProvider::Custom { .. } => {}
+                _ => return format!("https://{}/{}/{}", host, self.user, self.repo),
+            }
+        }
+
+        match &self.git_provider {
             GitProvider::GitHub => format!(
-                "https://github.com/{}/{}/tree/{}",
-                self.user, self.repo, self.git_ref
+                "https://{}/{}/{}/tree/{}",
+                host, self.user, self.repo, self.git_ref
             ),
             GitProvider::GitLab => format!(
-                "https://gitlab.com/{}/{}/-/tree/{}",
-                self.user, self.repo, self.git_ref
+                "https://{}/{}/{}/-/tree/{}",
+                host, self.user, self.repo, self.git_ref
+            ),
+            GitProvider::Codeberg => format!(
+                "https://{}/{}/{}/src/{}",
+                host, self.user, self.repo, self.git_ref
+            ),
+            GitProvider::AzureDevOps => format!(
+                "https://{}/{}/_git/{}?version=GB{}",
+                host, self.user, self.repo, self.git_ref
             ),
+            GitProvider::Custom {
+                tree_url_template, ..
+            } => self.expand(tree_url_template, &self.git_ref),
         }
     }
 
-    fn cache_file(&self) -> String {
-        format!(
-            "{}_{}_{}_{}",
-            self.git_provider.simple_name(),
-            self.user,
-            self.repo,
-            self.git_ref
-        )
+    /// The url `git` clones the repo from; `None` for custom providers.
+    pub fn clone_url(&self) -> Option<String> {
+        let host = self.host().unwrap_or_default();
+
+        let url = match &self.git_provider {
+            GitProvider::GitHub | GitProvider::GitLab | GitProvider::Codeberg => {
+                format!("https://{}/{}/{}.git", host, self.user, self.repo)
+            }
+            GitProvider::AzureDevOps => {
+                format!("https://{}/{}/_git/{}", host, self.user, self.repo)
+            }
+            GitProvider::Custom { .. } => return None,
+        };
+
+        Some(url)
     }
 
-    fn archive_link(&self) -> String {
-        match self.git_provider {
-            GitProvider::GitHub => format!(
-                "https://github.com/{}/{}/archive/{}.tar.gz",
-                self.user, self.repo, self.git_ref
+    /// The url `git` clones the repo from over SSH, as the provider's `git`
+    /// user; `None` for custom providers.
+    pub fn ssh_url(&self) -> Option<String> {
+        let host = self.host().unwrap_or_default();
+
+        let url = match &self.git_provider {
+            GitProvider::GitHub | GitProvider::GitLab | GitProvider::Codeberg => {
+                format!("git@{}:{}/{}.git", host, self.user, self.repo)
+            }
+            GitProvider::AzureDevOps => {
+                format!("git@ssh.{}:v3/{}/{}", host, self.user, self.repo)
+            }
+            GitProvider::Custom { .. } => return None,
+        };
+
+        Some(url)
+    }
+
+    /// The project's path as GitLab's API takes it: `user/repo` with every
+    /// `/` encoded, however many subgroups the user has.
+    fn gitlab_project(&self) -> String {
+        url::form_urlencoded::byte_serialize(format!("{}/{}", self.user, self.repo).as_bytes())
+            .collect()
+    }
+
+    /// The commit the repo was resolved to if it was, else its ref.
+    fn archive_ref(&self) -> &str {
+        self.commit.as_deref().unwrap_or(&self.git_ref)
+    }
+
+    pub(crate) fn cache_file(&self) -> String {
+        format!("{}_{}", self.cache_prefix(), self.archive_ref())
+    }
+
+    /// The part of [`RepoDef::cache_file`] naming the repo, whatever the ref.
+    fn cache_prefix(&self) -> String {
+        let provider = match (&self.git_provider, &self.host) {
+            (
+                GitProvider::Custom {
+                    archive_url_template,
+                    ..
+                },
+                _,
+            ) => {
+                format!(
+                    "custom_{}",
+                    &hash_buffer(archive_url_template.as_bytes())[..16]
+                )
+            }
+            // a port's `:` can't be in Windows file names
+            (_, Some(host)) => {
+                format!(
+                    "{}_{}",
+                    self.git_provider.simple_name(),
+                    host.replace(':', "_")
+                )
+            }
+            // repos on the public host keep the names they were cached by
+            // before hosts could be chosen
+            (_, None) => self.git_provider.simple_name().to_string(),
+        };
+
+        // GitLab subgroups and Azure DevOps projects would be directories
+        format!("{}_{}_{}", provider, self.user.replace('/', "_"), self.repo)
+    }
+
+    /// Where the default branch found for the repo is cached.
+    fn default_branch_path(&self, cache: &Path) -> PathBuf {
+        cache.join(format!("{}.default-branch", self.cache_prefix()))
+    }
+
+    /// Where the commit the ref was last resolved to is cached.
+    fn commit_path(&self, cache: &Path) -> PathBuf {
+        // refs like `release/1.0` would be directories
+        let git_ref = self.git_ref.replace('/', "_");
+        cache.join(format!("{}_{}.commit", self.cache_prefix(), git_ref))
+    }
+
+    /// This repo, with its default branch as the `git_ref` if it has none,
+    /// as last found by [`RepoDef::with_default_branch`]. Unchanged if it
+    /// hasn't been yet.
+    pub(crate) fn with_cached_default_branch(&self, cache: &Path) -> Cow<'_, RepoDef> {
+        if !self.git_ref.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        match fs::read_to_string(self.default_branch_path(cache)) {
+            Ok(branch) => Cow::Owned(RepoDef {
+                git_ref: branch.trim().to_string(),
+                ..self.clone()
+            }),
+            Err(_) => Cow::Borrowed(self),
+        }
+    }
+
+    /// This repo as last [resolved](RepoDef::resolve), without asking
+    /// anything of the network; as far as it was, if at all.
+    #[cfg(feature = "network")]
+    pub(crate) fn with_cached_resolution(&self, cache: &Path) -> Cow<'_, RepoDef> {
+        let repo = self.with_cached_default_branch(cache);
+        if repo.commit.is_some() {
+            return repo;
+        }
+
+        match fs::read_to_string(repo.commit_path(cache)) {
+            Ok(commit) => Cow::Owned(RepoDef {
+                commit: Some(commit.trim().to_string()),
+                ..repo.into_owned()
+            }),
+            Err(_) => repo,
+        }
+    }
+
+    /// This repo with its [default branch](RepoDef::with_default_branch)
+    /// filled in, and the commit its ref points at as its `commit`, asked of
+    /// the provider's API and cached as long as an archive would be. Repos
+    /// fetched with `git` are cached by commit anyway, and custom providers
+    /// have no API, so neither is resolved; neither are refs that are
+    /// commits already. If the API can't tell, e.g. when rate limited, the
+    /// ref is downloaded as it is.
+    #[cfg(feature = "network")]
+    pub(crate) fn resolve(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<Cow<'_, RepoDef>, DownloadError> {
+        if self.commit.is_some() {
+            return Ok(Cow::Borrowed(self));
+        }
+
+        let repo = self.with_default_branch(cache, downloader)?;
+        if repo.fetch.uses_git()
+            || matches!(repo.git_provider, GitProvider::Custom { .. })
+            || is_commit_id(&repo.git_ref)
+        {
+            return Ok(repo);
+        }
+
+        let path = repo.commit_path(cache);
+        if !needs_download(cache, &path, DEFAULT_REFRESH)? {
+            return Ok(Cow::Owned(repo.with_cached_resolution(cache).into_owned()));
+        }
+
+        let commit = match repo.find_commit(downloader) {
+            Ok(commit) => commit,
+            Err(err) => {
+                tracing::warn!(
+                    "Cannot resolve {} of {} to a commit ({})",
+                    repo.git_ref,
+                    repo.link(),
+                    err
+                );
+                return Ok(Cow::Owned(repo.with_cached_resolution(cache).into_owned()));
+            }
+        };
+        tracing::debug!(git_ref = %repo.git_ref, commit = %commit, "Resolved");
+        // it is only asked again next time
+        if let Err(err) = fs::write(&path, &commit) {
+            tracing::warn!("Cannot cache the commit in {} ({})", path.display(), err);
+        }
+
+        Ok(Cow::Owned(RepoDef {
+            commit: Some(commit),
+            ..repo.into_owned()
+        }))
+    }
+
+    /// Asks the provider's API which commit `git_ref` points at.
+    #[cfg(feature = "network")]
+    fn find_commit(&self, downloader: &dyn Downloader) -> Result<String, DownloadError> {
+        let host = self.host().unwrap_or_default();
+        let git_ref =
+            url::form_urlencoded::byte_serialize(self.git_ref.as_bytes()).collect::<String>();
+
+        let (api, pointer) = match &self.git_provider {
+            GitProvider::GitHub if self.host.is_none() => (
+                format!(
+                    "https://api.github.com/repos/{}/{}/commits/{}",
+                    self.user, self.repo, git_ref
+                ),
+                "/sha",
+            ),
+            GitProvider::GitHub => (
+                format!(
+                    "https://{}/api/v3/repos/{}/{}/commits/{}",
+                    host, self.user, self.repo, git_ref
+                ),
+                "/sha",
+            ),
+            GitProvider::GitLab => (
+                format!(
+                    "https://{}/api/v4/projects/{}/repository/commits/{}",
+                    host,
+                    self.gitlab_project(),
+                    git_ref
+                ),
+                "/id",
+            ),
+            GitProvider::Codeberg => (
+                format!(
+                    "https://{}/api/v1/repos/{}/{}/commits?sha={}&limit=1&stat=false",
+                    host, self.user, self.repo, git_ref
+                ),
+                "/0/sha",
+            ),
+            GitProvider::AzureDevOps => (
+                format!(
+                    "https://{}/{}/_apis/git/repositories/{}/commits\
+                     ?searchCriteria.itemVersion.version={}&$top=1&api-version=7.0",
+                    host, self.user, self.repo, git_ref
+                ),
+                "/value/0/commitId",
+            ),
+            GitProvider::Custom { .. } => return Err(DownloadError::NotFound(self.link())),
+        };
+
+        let response: serde_json::Value = serde_json::from_str(&downloader.fetch_text(&api)?)?;
+        response
+            .pointer(pointer)
+            .and_then(|it| it.as_str())
+            .filter(|it| is_commit_id(it))
+            .map(|it| it.to_string())
+            .ok_or_else(|| DownloadError::NotFound(api))
+    }
+
+    /// This repo, with its default branch as the `git_ref` if it has none.
+    /// The branch is asked of the provider's API, or of the repo itself when
+    /// it is fetched with `git`, and cached as long as a downloaded archive
+    /// would be; custom providers have no API, so theirs is `main`.
+    #[cfg(feature = "network")]
+    pub(crate) fn with_default_branch(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<Cow<'_, RepoDef>, DownloadError> {
+        if !self.git_ref.is_empty() {
+            return Ok(Cow::Borrowed(self));
+        }
+
+        let path = self.default_branch_path(cache);
+        if !needs_download(cache, &path, DEFAULT_REFRESH)? {
+            return Ok(self.with_cached_default_branch(cache));
+        }
+
+        let branch = match self.find_default_branch(cache, downloader) {
+            Ok(branch) => branch,
+            // an old answer beats none, e.g. while offline
+            Err(err) if path.exists() => {
+                tracing::warn!(
+                    "Cannot find the default branch of {} ({})",
+                    self.link(),
+                    err
+                );
+                return Ok(self.with_cached_default_branch(cache));
+            }
+            Err(err) => return Err(err),
+        };
+        tracing::debug!(branch = %branch, "Found the default branch");
+        fs::write(&path, &branch)?;
+
+        Ok(Cow::Owned(RepoDef {
+            git_ref: branch,
+            ..self.clone()
+        }))
+    }
+
+    #[cfg(feature = "network")]
+    fn find_default_branch(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<String, DownloadError> {
+        if self.fetch.uses_git() {
+            let url = self.git_url()?;
+            // `ref: refs/heads/<branch>\tHEAD`, then the commit
+            let args = ["ls-remote", "--symref", &url, "HEAD"];
+            let remote = git(cache, &args, &url, &CancelToken::new())?;
+            return remote
+                .lines()
+                .find_map(|it| it.strip_prefix("ref: refs/heads/")?.strip_suffix("\tHEAD"))
+                .map(|it| it.to_string())
+                .ok_or_else(|| DownloadError::Git {
+                    url,
+                    message: "the remote HEAD isn't a branch".to_string(),
+                });
+        }
+
+        let host = self.host().unwrap_or_default();
+        let (api, key) = match &self.git_provider {
+            GitProvider::GitHub if self.host.is_none() => (
+                format!("https://api.github.com/repos/{}/{}", self.user, self.repo),
+                "default_branch",
+            ),
+            // GitHub Enterprise
+            GitProvider::GitHub => (
+                format!("https://{}/api/v3/repos/{}/{}", host, self.user, self.repo),
+                "default_branch",
+            ),
+            GitProvider::GitLab => (
+                format!("https://{}/api/v4/projects/{}", host, self.gitlab_project()),
+                "default_branch",
+            ),
+            GitProvider::Codeberg => (
+                format!("https://{}/api/v1/repos/{}/{}", host, self.user, self.repo),
+                "default_branch",
+            ),
+            GitProvider::AzureDevOps => (
+                format!(
+                    "https://{}/{}/_apis/git/repositories/{}?api-version=7.0",
+                    host, self.user, self.repo
+                ),
+                "defaultBranch",
+            ),
+            GitProvider::Custom { .. } => return Ok(FALLBACK_BRANCH.to_string()),
+        };
+
+        let response: serde_json::Value = serde_json::from_str(&downloader.fetch_text(&api)?)?;
+        response[key]
+            .as_str()
+            // Azure DevOps names the whole ref
+            .map(|it| it.trim_start_matches("refs/heads/").to_string())
+            .ok_or_else(|| DownloadError::NotFound(api))
+    }
+
+    pub(crate) fn archive_link(&self) -> String {
+        let host = self.host().unwrap_or_default();
+
+        match &self.git_provider {
+            GitProvider::GitHub | GitProvider::Codeberg => format!(
+                "https://{}/{}/{}/archive/{}.tar.gz",
+                host,
+                self.user,
+                self.repo,
+                self.archive_ref()
             ),
             GitProvider::GitLab => format!(
-                "https://gitlab.com/api/v4/projects/{}%2F{}/repository/archive.tar.gz?sha={}",
-                self.user, self.repo, self.git_ref
+                "https://{}/api/v4/projects/{}/repository/archive.tar.gz?sha={}",
+                host,
+                self.gitlab_project(),
+                self.archive_ref()
+            ),
+            // only as a zip, and refs are taken for branches unless told
+            GitProvider::AzureDevOps => format!(
+                "https://{}/{}/_apis/git/repositories/{}/items?path=/\
+                 &versionDescriptor.version={}&versionDescriptor.versionType={}\
+                 &$format=zip&download=true&api-version=7.0",
+                host,
+                self.user,
+                self.repo,
+                self.archive_ref(),
+                if self.commit.is_some() {
+                    "commit"
+                } else {
+                    "branch"
+                }
             ),
+            GitProvider::Custom {
+                archive_url_template,
+                ..
+            } => self.expand(archive_url_template, self.archive_ref()),
         }
     }
 
+    #[cfg(feature = "network")]
+    pub(crate) fn archive_path(&self, cache: &Path) -> PathBuf {
+        archive_path(cache, &self.cache_file())
+    }
+
+    #[cfg(feature = "network")]
+    pub(crate) fn archive_commit(&self, cache: &Path) -> io::Result<Option<String>> {
+        let repo = self.with_cached_resolution(cache);
+
+        match repo.fetch {
+            Fetch::Archive if repo.commit.is_some() => Ok(repo.commit.clone()),
+            Fetch::Archive => archive_commit(&repo.archive_path(cache)),
+            Fetch::Git | Fetch::Ssh => {
+                match fs::read_to_string(head_path(cache, &repo.cache_file())) {
+                    Ok(commit) => Ok(Some(commit.trim().to_string())),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(err),
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "network")]
     pub(crate) fn download(&self, cache: &Path) -> Result<PathBuf, DownloadError> {
-        if !cache.exists() {
-            fs::create_dir_all(cache)?;
+        self.download_with(cache, &crate::http::ReqwestDownloader)
+    }
+
+    #[cfg(feature = "network")]
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(user = %self.user, repo = %self.repo, git_ref = %self.git_ref)
+    )]
+    pub fn download_with(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<PathBuf, DownloadError> {
+        self.download_with_refresh(cache, downloader, DEFAULT_REFRESH)
+    }
+
+    /// Like `download_with`, downloading again as eagerly as `refresh` says.
+    #[cfg(feature = "network")]
+    pub(crate) fn download_with_refresh(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+        refresh: Refresh,
+    ) -> Result<PathBuf, DownloadError> {
+        let repo = self.resolve(cache, downloader)?;
+
+        match repo.fetch {
+            Fetch::Archive => download_archive(
+                cache,
+                &repo.cache_file(),
+                &repo.archive_link(),
+                downloader,
+                refresh,
+                &CancelToken::new(),
+            ),
+            Fetch::Git | Fetch::Ssh => repo.clone_with_git(cache, refresh, &CancelToken::new()),
         }
+    }
+
+    /// Checks the ref out with `git` into the cache, where its archive would
+    /// have been extracted, unless the last checkout is fresh enough for
+    /// `refresh`. Over SSH with `fetch = "ssh"`, else over https; either
+    /// way, the same ref is cached in the same place.
+    #[cfg(feature = "network")]
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(user = %self.user, repo = %self.repo, git_ref = %self.git_ref)
+    )]
+    pub(crate) fn clone_with_git(
+        &self,
+        cache: &Path,
+        refresh: Refresh,
+        cancel: &CancelToken,
+    ) -> Result<PathBuf, DownloadError> {
+        let url = self.git_url()?;
 
-        let file = self.cache_file();
-        let tar_file = format!("{}.tar.gz", file);
-        let link = self.archive_link();
+        clone_repo(
+            cache,
+            &self.cache_file(),
+            &url,
+            self.archive_ref(),
+            refresh,
+            cancel,
+        )
+    }
 
-        let path = cache.join(tar_file);
+    /// The url `git` fetches the repo from with this fetch mode.
+    #[cfg(feature = "network")]
+    fn git_url(&self) -> Result<String, DownloadError> {
+        let url = match self.fetch {
+            Fetch::Ssh => self.ssh_url(),
+            _ => self.clone_url(),
+        };
 
-        let etag_f = path.with_extension("etag");
-        if path.exists() {
-            let md = path.metadata()?;
-            let created = md.modified()?;
+        url.ok_or_else(|| DownloadError::Git {
+            url: self.link(),
+            message: "custom providers can't be cloned".to_string(),
+        })
+    }
 
-            if SystemTime::now() > created + Duration::from_secs(60) {
-                download_file(&link, &path, Some(&etag_f))?;
+    /// Like `download`, without blocking the async runtime.
+    #[cfg(feature = "async")]
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(user = %self.user, repo = %self.repo, git_ref = %self.git_ref)
+    )]
+    pub async fn download_async(&self, cache: &Path) -> Result<PathBuf, DownloadError> {
+        self.download_async_with_refresh(cache, DEFAULT_REFRESH)
+            .await
+    }
+
+    /// Like `download_async`, downloading again as eagerly as `refresh` says.
+    #[cfg(feature = "async")]
+    pub(crate) async fn download_async_with_refresh(
+        &self,
+        cache: &Path,
+        refresh: Refresh,
+    ) -> Result<PathBuf, DownloadError> {
+        let repo = {
+            let (repo, cache) = (self.clone(), cache.to_owned());
+            blocking(move || {
+                repo.resolve(&cache, &crate::http::ReqwestDownloader)
+                    .map(|it| it.into_owned())
+            })
+            .await??
+        };
+
+        match repo.fetch {
+            Fetch::Archive => {
+                download_archive_async(cache, &repo.cache_file(), &repo.archive_link(), refresh)
+                    .await
             }
-        } else {
-            download_file(&link, &path, Some(&etag_f))?;
+            Fetch::Git | Fetch::Ssh => {
+                let cache = cache.to_owned();
+                blocking(move || repo.clone_with_git(&cache, refresh, &CancelToken::new())).await?
+            }
+        }
+    }
+
+    /// Removes everything cached for this repo; returns the removed paths.
+    /// That is every commit its ref was resolved to, as older ones are kept
+    /// until then.
+    pub fn purge_cache(&self, cache: &Path) -> io::Result<Vec<PathBuf>> {
+        let repo = self.with_cached_default_branch(cache);
+        let mut removed = purge_cache(cache, &repo.cache_file())?;
+
+        let mut commits = BTreeSet::new();
+        if cache.exists() {
+            let prefix = format!("{}_", repo.cache_prefix());
+            for entry in cache.read_dir()? {
+                let name = entry?.file_name();
+                let rest = name.to_str().and_then(|it| it.strip_prefix(&prefix));
+
+                // up to the `.tar.gz`, `.tar.etag` or `-<hash>` after it
+                if let Some(commit) = rest.and_then(|it| it.split(['.', '-']).next()) {
+                    commits.insert(commit.to_string());
+                }
+            }
+        }
+        for commit in commits.into_iter().filter(|it| is_commit_id(it)) {
+            removed.extend(purge_cache(
+                cache,
+                &format!("{}_{}", repo.cache_prefix(), commit),
+            )?);
+        }
+
+        let mut files = vec![repo.commit_path(cache)];
+        if self.git_ref.is_empty() {
+            files.push(self.default_branch_path(cache));
+        }
+        for path in files {
+            match fs::remove_file(&path) {
+                Ok(()) => removed.push(path),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// The repo `url` belongs to, if it is another one: fetching
+    /// [`RepoDef::archive_link`] was redirected to `url` because this repo was
+    /// renamed or transferred.
+    #[cfg(feature = "network")]
+    pub(crate) fn moved_to(&self, url: &str) -> Option<RepoDef> {
+        let url = reqwest::Url::parse(url).ok()?;
+        let segments = url.path_segments()?.collect::<Vec<_>>();
+
+        // github.com serves archives from codeload.github.com
+        let host = url.host_str()?;
+        if Some(host) != self.host() && (self.host.is_some() || host != "codeload.github.com") {
+            return None;
+        }
+
+        let (user, repo) = match self.git_provider {
+            GitProvider::GitHub | GitProvider::Codeberg if segments.len() > 2 => {
+                (segments[0].to_string(), segments[1].to_string())
+            }
+            GitProvider::GitLab => {
+                // `api/v4/projects/<user>%2F<repo>/...` or `<user>/<repo>/-/...`,
+                // where the user may be a group with subgroups
+                let path = match segments.as_slice() {
+                    ["api", "v4", "projects", project, ..] => {
+                        project.replace("%2F", "/").replace("%2f", "/")
+                    }
+                    _ => segments.split(|it| *it == "-").next()?.join("/"),
+                };
+                let (user, repo) = path.rsplit_once('/')?;
+                (user.to_string(), repo.to_string())
+            }
+            _ => return None,
+        };
+
+        // both providers match names case-insensitively
+        if user.eq_ignore_ascii_case(&self.user) && repo.eq_ignore_ascii_case(&self.repo) {
+            return None;
+        }
+
+        Some(RepoDef {
+            user,
+            repo,
+            ..self.clone()
+        })
+    }
+}
+
+/// Cache file name for an archive that is only known by its url.
+pub(crate) fn url_cache_file(url: &str) -> String {
+    format!("url_{}", &hash_buffer(url.as_bytes())[..16])
+}
+
+pub(crate) fn archive_path(cache: &Path, file: &str) -> PathBuf {
+    cache.join(format!("{}.tar.gz", file))
+}
+
+/// Where the commit last checked out with `git` as `file` is recorded,
+/// instead of an archive.
+pub(crate) fn head_path(cache: &Path, file: &str) -> PathBuf {
+    archive_path(cache, file).with_extension("head")
+}
+
+/// Removes the tarball, etag and extracted directories cached as `file`.
+pub(crate) fn purge_cache(cache: &Path, file: &str) -> io::Result<Vec<PathBuf>> {
+    if !cache.exists() {
+        return Ok(Vec::new());
+    }
+
+    let tarball = archive_path(cache, file);
+    let etag = tarball.with_extension("etag");
+    let head = head_path(cache, file);
+    let extracted_prefix = format!("{}-", file);
+
+    let mut removed = Vec::new();
+
+    for entry in cache.read_dir()? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+
+        // the part after the prefix must be the hash, or this is another ref
+        // that merely starts with the same name (e.g. `main` and `main-2`)
+        let is_extracted = name
+            .strip_prefix(&extracted_prefix)
+            .is_some_and(|hash| !hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()));
+
+        if path.is_dir() && is_extracted {
+            fs::remove_dir_all(&path)?;
+            removed.push(path);
+        } else if path == tarball || path == etag || path == head {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Reads the commit id `git archive` stores in the pax global header of
+/// a tarball, if there is one.
+#[cfg(feature = "network")]
+pub(crate) fn archive_commit(path: &Path) -> io::Result<Option<String>> {
+    if is_zip(path)? {
+        return Ok(None);
+    }
+
+    let tar_gz = fs::File::open(path)?;
+    let tar = GzDecoder::new(tar_gz);
+    let mut a = Archive::new(tar);
+
+    // the global header, when present, is always the first entry
+    let mut entry = match a.entries()?.next() {
+        Some(entry) => entry?,
+        None => return Ok(None),
+    };
+
+    if !entry.header().entry_type().is_pax_global_extensions() {
+        return Ok(None);
+    }
+
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+
+            if extension.key() == Ok("comment") {
+                return Ok(extension.value().ok().map(|it| it.to_string()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Downloads the archive at `link` into the cache as `file`, and extracts it
+/// next to it, keyed by the archive's hash.
+#[cfg(feature = "network")]
+#[tracing::instrument(level = "debug", skip(cache, downloader, cancel))]
+pub(crate) fn download_archive(
+    cache: &Path,
+    file: &str,
+    link: &str,
+    downloader: &dyn Downloader,
+    refresh: Refresh,
+    cancel: &CancelToken,
+) -> Result<PathBuf, DownloadError> {
+    let fetch = || {
+        fetch_archive(cache, file, link, downloader, refresh, cancel)
+            .map(|it| it.map(|it| it.digest))
+    };
+
+    match extract_archive(cache, file, fetch()?.as_deref(), cancel) {
+        Err(err) => {
+            discard_unextractable(cache, file, err)?;
+            extract_archive(cache, file, fetch()?.as_deref(), cancel)
         }
+        extracted => extracted,
+    }
+}
 
-        let hash = hash(&path);
+/// An archive [`download_file`] downloaded.
+#[cfg(feature = "network")]
+pub(crate) struct Downloaded {
+    /// the archive's hash, computed on the way so it needn't be read again.
+    pub(crate) digest: String,
+    /// where the archive came from, if redirected away from its link.
+    pub(crate) url: Option<String>,
+}
 
-        let out_dir = cache.join(format!("{}-{}", file, hash));
+/// Downloads the archive at `link` into the cache as `file`, unless a copy
+/// that is fresh enough for `refresh` is already there. Returns `None` if
+/// it wasn't downloaded.
+#[cfg(feature = "network")]
+#[tracing::instrument(level = "debug", skip(cache, downloader, cancel))]
+pub(crate) fn fetch_archive(
+    cache: &Path,
+    file: &str,
+    link: &str,
+    downloader: &dyn Downloader,
+    refresh: Refresh,
+    cancel: &CancelToken,
+) -> Result<Option<Downloaded>, DownloadError> {
+    let path = archive_path(cache, file);
+
+    if needs_download(cache, &path, refresh)? {
+        download_file(
+            downloader,
+            link,
+            &path,
+            Some(&path.with_extension("etag")),
+            cancel,
+        )
+    } else {
+        tracing::debug!(cache = "hit", "Cached archive is fresh, not downloading");
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "async")]
+#[tracing::instrument(level = "debug", skip(cache))]
+pub(crate) async fn download_archive_async(
+    cache: &Path,
+    file: &str,
+    link: &str,
+    refresh: Refresh,
+) -> Result<PathBuf, DownloadError> {
+    let path = archive_path(cache, file);
+
+    let stale = {
+        let (cache, path) = (cache.to_owned(), path.clone());
+        blocking(move || needs_download(&cache, &path, refresh)).await??
+    };
+    let digest = if stale {
+        download_file_async(link, &path, Some(&path.with_extension("etag"))).await?
+    } else {
+        tracing::debug!(cache = "hit", "Cached archive is fresh, not downloading");
+        None
+    };
+
+    let extract = |digest: Option<String>| {
+        let (cache, file) = (cache.to_owned(), file.to_owned());
+        blocking(move || extract_archive(&cache, &file, digest.as_deref(), &CancelToken::new()))
+    };
+
+    match extract(digest).await? {
+        Err(err) => {
+            discard_unextractable(cache, file, err)?;
+            let etag = path.with_extension("etag");
+            let digest = download_file_async(link, &path, Some(&etag)).await?;
+            extract(digest).await?
+        }
+        extracted => extracted,
+    }
+}
+
+/// Runs filesystem-heavy work off the async runtime's worker threads.
+#[cfg(feature = "async")]
+async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(io::Error::other)
+}
+
+/// Checks `git_ref` of the repo at `url` out into the cache with a shallow
+/// `git` fetch, which unlike `git clone --branch` also takes commit ids.
+/// The checkout is kept like an extracted archive cached as `file`, keyed by
+/// its commit, without the `.git` directory.
+#[cfg(feature = "network")]
+#[tracing::instrument(level = "debug", skip(cache, cancel))]
+pub(crate) fn clone_repo(
+    cache: &Path,
+    file: &str,
+    url: &str,
+    git_ref: &str,
+    refresh: Refresh,
+    cancel: &CancelToken,
+) -> Result<PathBuf, DownloadError> {
+    let head = head_path(cache, file);
+
+    if !needs_download(cache, &head, refresh)? {
+        let commit = fs::read_to_string(&head)?;
+        let out_dir = cache.join(format!("{}-{}", file, commit.trim()));
 
         if out_dir.exists() {
+            tracing::debug!(cache = "hit", out_dir = %out_dir.display(), "Checkout is fresh");
             return Ok(out_dir);
         }
+    }
+
+    let start = Instant::now();
+
+    let tmp = cache.join(format!("{}.git-tmp", file));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(&tmp)?;
+
+    let checked_out = (|| {
+        let git = |args: &[&str]| git(&tmp, args, url, cancel);
+
+        git(&["init", "--quiet"])?;
+        git(&["fetch", "--quiet", "--depth", "1", "--", url, git_ref])?;
+        git(&["checkout", "--quiet", "FETCH_HEAD"])?;
+        let commit = git(&["rev-parse", "HEAD"])?;
+        fs::remove_dir_all(tmp.join(".git"))?;
+
+        Ok(commit)
+    })();
+
+    let commit = match checked_out {
+        Ok(commit) => commit,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&tmp);
+            return Err(cancelled_or(cancel, err));
+        }
+    };
+
+    let out_dir = cache.join(format!("{}-{}", file, commit));
+    if out_dir.exists() {
+        fs::remove_dir_all(&tmp)?;
+    } else {
+        fs::rename(&tmp, &out_dir)?;
+    }
+    fs::write(&head, &commit)?;
+
+    tracing::debug!(
+        cache = "miss",
+        out_dir = %out_dir.display(),
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "Checked out"
+    );
+
+    Ok(out_dir)
+}
+
+/// Runs `git` in `dir`, returning what it printed; `url` is the repo, for
+/// errors. Never asks for http credentials, which would hang without a
+/// terminal; SSH keys come from the agent, or ssh asks for their passphrase.
+#[cfg(feature = "network")]
+fn git(
+    dir: &Path,
+    args: &[&str],
+    url: &str,
+    cancel: &CancelToken,
+) -> Result<String, DownloadError> {
+    cancel.check()?;
+
+    let git_error = |message: String| DownloadError::Git {
+        url: url.to_owned(),
+        message,
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|err| git_error(format!("cannot run git: {}", err)))?;
+
+    if !output.status.success() {
+        return Err(git_error(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether the archive at `path` is missing or stale by `refresh`.
+#[cfg(feature = "network")]
+fn needs_download(cache: &Path, path: &Path, refresh: Refresh) -> io::Result<bool> {
+    if !cache.exists() {
+        fs::create_dir_all(cache)?;
+    }
+
+    refresh.is_stale(path)
+}
+
+/// Throws away the archive cached as `file` and its etag after extracting it
+/// failed with `err`, so that it is downloaded again: a truncated download or
+/// a corrupted file would otherwise fail the same way until the cache is
+/// purged. Returns `err` instead if extracting was cancelled.
+#[cfg(feature = "network")]
+pub(crate) fn discard_unextractable(
+    cache: &Path,
+    file: &str,
+    err: DownloadError,
+) -> Result<(), DownloadError> {
+    if let DownloadError::Cancelled = err {
+        return Err(err);
+    }
+
+    let path = archive_path(cache, file);
+    tracing::warn!(
+        "Cannot extract {} ({}), downloading it again",
+        path.display(),
+        err
+    );
+
+    for path in [path.with_extension("etag"), path] {
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
 
-        fs::create_dir_all(&out_dir)?;
+    Ok(())
+}
 
-        let tar_gz = fs::File::open(&path)?;
-        let tar = GzDecoder::new(tar_gz);
-        let mut a = Archive::new(tar);
-        a.unpack(&out_dir)?;
+/// Extracts the archive cached as `file` next to it, keyed by its hash;
+/// `digest` is that hash if already known from downloading it.
+#[cfg(feature = "network")]
+#[tracing::instrument(level = "debug", skip(cache, cancel))]
+pub(crate) fn extract_archive(
+    cache: &Path,
+    file: &str,
+    digest: Option<&str>,
+    cancel: &CancelToken,
+) -> Result<PathBuf, DownloadError> {
+    let path = archive_path(cache, file);
+    let hash = match digest {
+        Some(digest) => digest.to_owned(),
+        None => hash(&path)?,
+    };
 
-        flatten(&out_dir)?;
+    let out_dir = cache.join(format!("{}-{}", file, hash));
 
-        Ok(out_dir)
+    if out_dir.exists() {
+        tracing::debug!(cache = "hit", out_dir = %out_dir.display(), "Already extracted");
+        return Ok(out_dir);
     }
+
+    let start = Instant::now();
+
+    fs::create_dir_all(&out_dir)?;
+
+    let extracted = (|| {
+        if is_zip(&path)? {
+            unpack_zip(&path, &out_dir, cancel)?;
+        } else {
+            let tar_gz = fs::File::open(&path)?;
+            let tar = GzDecoder::new(tar_gz);
+            let mut a = Archive::new(tar);
+            for entry in a.entries()? {
+                cancel.check()?;
+                entry?.unpack_in(&out_dir)?;
+            }
+        }
+
+        flatten(&out_dir)
+    })();
+
+    // don't leave a half-extracted directory behind to be mistaken for a
+    // complete one next time
+    if let Err(err) = extracted {
+        let _ = fs::remove_dir_all(&out_dir);
+        return Err(cancelled_or(cancel, err.into()));
+    }
+
+    tracing::debug!(
+        cache = "miss",
+        out_dir = %out_dir.display(),
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "Extracted"
+    );
+
+    Ok(out_dir)
+}
+
+/// Whether the archive at `path` is a zip rather than a `.tar.gz`, as
+/// providers like Azure DevOps serve them; it is cached under the same name
+/// either way.
+#[cfg(feature = "network")]
+fn is_zip(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let read = io::Read::read(&mut fs::File::open(path)?, &mut magic)?;
+
+    Ok(read == magic.len() && magic == *b"PK\x03\x04")
+}
+
+/// Unpacks the zip at `path` into `out_dir`, skipping entries that would end
+/// up outside of it.
+#[cfg(feature = "network")]
+fn unpack_zip(path: &Path, out_dir: &Path, cancel: &CancelToken) -> io::Result<()> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(path)?)?;
+
+    for i in 0..zip.len() {
+        cancel.check()?;
+
+        let mut entry = zip.by_index(i)?;
+        let dest = match entry.enclosed_name() {
+            Some(name) => out_dir.join(name),
+            None => {
+                tracing::warn!("Skipping {:?} in {}", entry.name(), path.display());
+                continue;
+            }
+        };
+
+        if entry.is_dir() {
+            fs::create_dir_all(&dest)?;
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut fs::File::create(&dest)?)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&dest, fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    Ok(())
 }
 
-fn default_branch() -> String {
-    "main".to_string()
+/// Whether `git_ref` is a full SHA-1 or SHA-256 commit id, which needn't be
+/// resolved.
+fn is_commit_id(git_ref: &str) -> bool {
+    matches!(git_ref.len(), 40 | 64) && git_ref.chars().all(|c| c.is_ascii_hexdigit())
 }
 
+/// The branch custom providers' `{ref}` stands for when the repo has no
+/// `git_ref`.
+const FALLBACK_BRANCH: &str = "main";
+
+/// Moves the contents of the directory forge archives wrap everything in
+/// (`repo-ref/`) up into `out_dir`. Archives with anything else at the top,
+/// like many direct-url tarballs, are left as they are.
+#[cfg(feature = "network")]
 fn flatten(out_dir: &Path) -> io::Result<()> {
-    // has only one child
-    let entry = out_dir.read_dir()?.next().unwrap()?;
+    let mut entries = out_dir.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+
+    if entries.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "empty archive"));
+    }
+
+    if entries.len() > 1 || !entries[0].file_type()?.is_dir() {
+        tracing::debug!(out_dir = %out_dir.display(), "No wrapping directory to flatten");
+        return Ok(());
+    }
+
+    // out of the way, in case it has a child by the same name
+    let wrapper = out_dir.join(format!(
+        ".{}.flatten",
+        entries[0].file_name().to_string_lossy()
+    ));
+    fs::rename(entries.remove(0).path(), &wrapper)?;
 
-    let children = entry
-        .path()
+    let children = wrapper
         .read_dir()?
         .map(|child| {
             let child = child?;
@@ -158,48 +1384,445 @@ fn flatten(out_dir: &Path) -> io::Result<()> {
         fs::rename(src, dest)?;
     }
 
-    fs::remove_dir(entry.path())?;
+    fs::remove_dir(wrapper)?;
 
     Ok(())
 }
 
-fn download_file(url: &str, path: &Path, etag_f: Option<&Path>) -> Result<(), DownloadError> {
-    let prev_etag = etag_f.and_then(|it| {
-        if it.exists() {
-            fs::read_to_string(it).ok()
-        } else {
-            None
+/// What an `.etag` sidecar holds: the url it was fetched from, then its
+/// ETag, so that one fetched from elsewhere (another host, a provider whose
+/// archive urls changed) is never sent along.
+pub(crate) fn etag_record(url: &str, etag: &str) -> String {
+    format!("{}\n{}", url, etag)
+}
+
+/// The ETag in an `.etag` sidecar's `contents`, if it was recorded for
+/// `url`; see [`etag_record`]. Sidecars from before urls were recorded hold
+/// only the ETag, and are ignored too.
+pub(crate) fn etag_for<'a>(contents: &'a str, url: &str) -> Option<&'a str> {
+    match contents.split_once('\n') {
+        Some((recorded, etag)) if recorded == url => Some(etag),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "network")]
+#[tracing::instrument(level = "debug", skip(downloader, path, etag_f, cancel))]
+fn download_file(
+    downloader: &dyn Downloader,
+    url: &str,
+    path: &Path,
+    etag_f: Option<&Path>,
+    cancel: &CancelToken,
+) -> Result<Option<Downloaded>, DownloadError> {
+    let prev_etag = etag_f
+        .and_then(|it| fs::read_to_string(it).ok())
+        .and_then(|it| etag_for(&it, url).map(str::to_owned));
+
+    // streamed into a temporary file as it arrives: an interrupted download
+    // must not leave a truncated archive in the cache
+    let tmp = utils::TempFile::new(path);
+    let mut file = io::BufWriter::new(fs::File::create(tmp.tmp_path())?);
+
+    let start = Instant::now();
+    let received = match downloader
+        .fetch_into(url, prev_etag.as_deref(), &mut file, cancel)
+        .map_err(|err| cancelled_or(cancel, err))?
+    {
+        Some(received) => received,
+        None => {
+            tracing::debug!(
+                cache = "hit",
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "Not modified since the last download"
+            );
+            return Ok(None);
+        }
+    };
+    tracing::debug!(
+        cache = "miss",
+        bytes = received.len,
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "Downloaded"
+    );
+
+    file.into_inner().map_err(io::IntoInnerError::into_error)?;
+    cancel.check().map_err(|_| DownloadError::Cancelled)?;
+    tmp.commit()?;
+
+    // only once the archive is in place, or a later fetch could be told
+    // nothing changed while the archive is missing
+    if let Some(etag) = &received.etag {
+        if let Some(etag_f) = etag_f {
+            fs::write(etag_f, etag_record(url, etag))?;
         }
-    });
+    }
+
+    Ok(Some(Downloaded {
+        digest: received.digest,
+        url: received.url,
+    }))
+}
+
+/// [`DownloadError::Cancelled`] if `cancel` was cancelled, which is then
+/// what made the operation fail, or else `err`.
+#[cfg(feature = "network")]
+fn cancelled_or(cancel: &CancelToken, err: DownloadError) -> DownloadError {
+    if cancel.is_cancelled() {
+        DownloadError::Cancelled
+    } else {
+        err
+    }
+}
 
-    let cl = reqwest::blocking::Client::new();
-    let req = cl.get(url);
+#[cfg(feature = "async")]
+pub(crate) async fn download_file_async(
+    url: &str,
+    path: &Path,
+    etag_f: Option<&Path>,
+) -> Result<Option<String>, DownloadError> {
+    let prev_etag = match etag_f {
+        Some(etag_f) => tokio::fs::read_to_string(etag_f).await.ok(),
+        None => None,
+    }
+    .and_then(|it| etag_for(&it, url).map(str::to_owned));
+
+    use reqwest::{header, StatusCode};
+    use tokio::io::AsyncWriteExt;
+
+    let req = crate::http::async_client()?.get(url);
     let req = prev_etag
         .iter()
         .fold(req, |req, etag| req.header(header::IF_NONE_MATCH, etag));
-    let resp = req.send()?.error_for_status()?;
+    let mut resp = req.send().await.map_err(DownloadError::network(url))?;
 
     if resp.status() == StatusCode::NOT_MODIFIED {
-        return Ok(());
+        return Ok(None);
+    }
+    crate::http::check_status(url, resp.status())?;
+
+    let etag = resp
+        .headers()
+        .get(header::ETAG)
+        .and_then(|it| it.to_str().ok())
+        .map(str::to_owned);
+
+    // as in `download_file`: dropping the future midway must not leave a
+    // truncated archive, nor an etag for an archive that isn't there; the
+    // temporary file goes with the future
+    let tmp = utils::TempFile::new(path);
+    let mut file = tokio::fs::File::create(tmp.tmp_path()).await?;
+    let mut hasher = utils::StreamHasher::new();
+    while let Some(chunk) = resp.chunk().await.map_err(DownloadError::network(url))? {
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
     }
+    file.flush().await?;
+    drop(file);
+    tmp.commit()?;
 
-    let etag = {
-        let headers = resp.headers();
-        headers
-            .get(header::ETAG)
-            .and_then(|it| Some(it.to_str().unwrap()))
+    if let (Some(etag), Some(etag_f)) = (etag, etag_f) {
+        tokio::fs::write(etag_f, etag_record(url, &etag)).await?;
+    }
+
+    Ok(Some(hasher.finish()))
+}
+
+#[cfg(all(test, feature = "network"))]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{Fetched, Received},
+        utils::tests::{dotted_template, Scratch},
     };
 
-    if let Some(etag) = etag {
-        if let Some(etag_f) = etag_f {
-            fs::write(etag_f, etag)?;
+    const DOTFILES: [&str; 3] = [".gitignore", ".editorconfig", ".github/workflows/ci.yml"];
+
+    #[test]
+    fn flatten_keeps_dotfiles() {
+        let scratch = Scratch::new("flatten-dotfiles");
+        dotted_template(&scratch, "out/repo-main");
+        let out = scratch.path().join("out");
+
+        flatten(&out).unwrap();
+
+        assert!(!out.join("repo-main").exists());
+        for file in DOTFILES {
+            assert!(out.join(file).is_file(), "{} is missing", file);
         }
     }
 
-    let mut f = fs::File::create(path)?;
+    #[test]
+    fn flatten_leaves_loose_dotfiles_alone() {
+        let scratch = Scratch::new("flatten-loose");
+        scratch.write("out/.gitignore", "/target\n");
+        scratch.write("out/src/main.rs", "fn main() {}\n");
+        let out = scratch.path().join("out");
 
-    let bytes = resp.bytes()?;
-    f.write_all(&bytes)?;
+        flatten(&out).unwrap();
 
-    Ok(())
+        assert!(out.join(".gitignore").is_file());
+        assert!(out.join("src/main.rs").is_file());
+    }
+
+    #[test]
+    fn extract_keeps_dotfiles() {
+        let scratch = Scratch::new("extract-dotfiles");
+        dotted_template(&scratch, "template");
+        let cache = scratch.path().join("cache");
+        fs::create_dir_all(&cache).unwrap();
+        let archive = utils::archive_dir(&scratch.path().join("template"), "repo-main").unwrap();
+        fs::write(archive_path(&cache, "dotted"), archive).unwrap();
+
+        let out = extract_archive(&cache, "dotted", None, &CancelToken::new()).unwrap();
+
+        for file in DOTFILES {
+            let extracted = fs::read(out.join(file)).unwrap();
+            assert_eq!(
+                extracted,
+                fs::read(scratch.path().join("template").join(file)).unwrap()
+            );
+        }
+    }
+
+    /// Serves `body` in two halves, failing or cancelling `cancel` in
+    /// between if asked to.
+    struct Halves {
+        body: &'static [u8],
+        fail: bool,
+        cancel: Option<CancelToken>,
+    }
+
+    impl Downloader for Halves {
+        fn fetch(&self, _: &str, _: Option<&str>) -> Result<Option<Fetched>, DownloadError> {
+            unreachable!("archives are streamed")
+        }
+
+        fn fetch_into(
+            &self,
+            _: &str,
+            _: Option<&str>,
+            out: &mut dyn io::Write,
+            cancel: &CancelToken,
+        ) -> Result<Option<Received>, DownloadError> {
+            let (first, second) = self.body.split_at(self.body.len() / 2);
+            out.write_all(first)?;
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset").into());
+            }
+            if let Some(it) = &self.cancel {
+                it.cancel();
+            }
+            cancel.check()?;
+            out.write_all(second)?;
+
+            Ok(Some(Received {
+                etag: Some("\"1\"".to_string()),
+                digest: hash_buffer(self.body),
+                len: self.body.len() as u64,
+                url: None,
+            }))
+        }
+    }
+
+    fn download(
+        scratch: &Scratch,
+        downloader: &Halves,
+        cancel: &CancelToken,
+    ) -> Result<Option<Downloaded>, DownloadError> {
+        let path = scratch.path().join("archive.tar.gz");
+        download_file(
+            downloader,
+            "http://host/a.tar.gz",
+            &path,
+            Some(&path.with_extension("etag")),
+            cancel,
+        )
+    }
+
+    fn cached(scratch: &Scratch) -> Vec<String> {
+        let mut names = fs::read_dir(scratch.path())
+            .unwrap()
+            .map(|it| it.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn downloads_stream_into_the_cache() {
+        let scratch = Scratch::new("download-streamed");
+        let downloader = Halves {
+            body: b"the whole archive",
+            fail: false,
+            cancel: None,
+        };
+
+        let downloaded = download(&scratch, &downloader, &CancelToken::new())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(downloaded.digest, hash_buffer(b"the whole archive"));
+        assert_eq!(cached(&scratch), ["archive.tar.etag", "archive.tar.gz"]);
+        assert_eq!(
+            fs::read(scratch.path().join("archive.tar.gz")).unwrap(),
+            b"the whole archive"
+        );
+    }
+
+    #[test]
+    fn failed_downloads_leave_nothing_behind() {
+        let scratch = Scratch::new("download-failed");
+        let downloader = Halves {
+            body: b"the whole archive",
+            fail: true,
+            cancel: None,
+        };
+
+        assert!(download(&scratch, &downloader, &CancelToken::new()).is_err());
+        assert!(cached(&scratch).is_empty());
+    }
+
+    #[test]
+    fn cancelled_downloads_leave_nothing_behind() {
+        let scratch = Scratch::new("download-cancelled");
+        let cancel = CancelToken::new();
+        let downloader = Halves {
+            body: b"the whole archive",
+            fail: false,
+            cancel: Some(cancel.clone()),
+        };
+
+        assert!(matches!(
+            download(&scratch, &downloader, &cancel),
+            Err(DownloadError::Cancelled)
+        ));
+        assert!(cached(&scratch).is_empty());
+    }
+
+    #[test]
+    fn failed_downloads_keep_the_previous_archive() {
+        let scratch = Scratch::new("download-kept");
+        scratch.write("archive.tar.gz", "the old archive");
+        let downloader = Halves {
+            body: b"the whole archive",
+            fail: true,
+            cancel: None,
+        };
+
+        assert!(download(&scratch, &downloader, &CancelToken::new()).is_err());
+        assert_eq!(cached(&scratch), ["archive.tar.gz"]);
+        assert_eq!(
+            fs::read(scratch.path().join("archive.tar.gz")).unwrap(),
+            b"the old archive"
+        );
+    }
+
+    #[test]
+    fn cancelled_extraction_leaves_nothing_behind() {
+        let scratch = Scratch::new("extract-cancelled");
+        dotted_template(&scratch, "template");
+        let cache = scratch.path().join("cache");
+        fs::create_dir_all(&cache).unwrap();
+        let archive = utils::archive_dir(&scratch.path().join("template"), "repo-main").unwrap();
+        fs::write(archive_path(&cache, "dotted"), archive).unwrap();
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let extracted = extract_archive(&cache, "dotted", None, &cancel);
+
+        assert!(matches!(extracted, Err(DownloadError::Cancelled)));
+        let left = fs::read_dir(&cache)
+            .unwrap()
+            .map(|it| it.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(left, ["dotted.tar.gz"]);
+    }
+
+    #[test]
+    fn refs_cannot_look_like_options() {
+        assert!(check_git_ref("main").is_ok());
+        assert!(check_git_ref("release/1.0").is_ok());
+        assert!(check_git_ref("--upload-pack=touch pwned").is_err());
+
+        let repo = |git_ref: &str| {
+            toml::from_str::<RepoDef>(&format!(
+                "user = \"u\"\nrepo = \"r\"\ngit_ref = {:?}",
+                git_ref
+            ))
+        };
+        assert!(repo("main").is_ok());
+        assert!(repo("-c").is_err());
+    }
+
+    /// A git repo with a single commit, in `dir` below `scratch`.
+    fn git_repo(scratch: &Scratch, dir: &str) -> String {
+        scratch.write(&format!("{}/README.md", dir), "hello\n");
+        let dir = scratch.path().join(dir);
+        let cancel = CancelToken::new();
+        let url = dir.to_string_lossy().into_owned();
+        for args in [
+            &["init", "--quiet", "--initial-branch", "main"][..],
+            &["add", "README.md"],
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "-m",
+                "init",
+            ],
+        ] {
+            git(&dir, args, &url, &cancel).unwrap();
+        }
+
+        url
+    }
+
+    #[test]
+    fn clones_with_git() {
+        let scratch = Scratch::new("clone");
+        let url = git_repo(&scratch, "repo");
+        let cache = scratch.path().join("cache");
+
+        let out = clone_repo(
+            &cache,
+            "r",
+            &url,
+            "main",
+            Refresh::Always,
+            &CancelToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(out.join("README.md")).unwrap(),
+            "hello\n"
+        );
+        assert!(!out.join(".git").exists());
+    }
+
+    #[test]
+    fn refs_are_never_git_options() {
+        let scratch = Scratch::new("clone-option");
+        let url = git_repo(&scratch, "repo");
+        let cache = scratch.path().join("cache");
+        let pwned = scratch.path().join("pwned");
+        let git_ref = format!("--upload-pack=touch {}", pwned.display());
+
+        let cloned = clone_repo(
+            &cache,
+            "r",
+            &url,
+            &git_ref,
+            Refresh::Always,
+            &CancelToken::new(),
+        );
+
+        assert!(matches!(cloned, Err(DownloadError::Git { .. })));
+        assert!(!pwned.exists());
+        assert!(!cache.join("r.git-tmp").exists());
+    }
 }
@@ -1,17 +1,84 @@
-use std::ops::Deref;
+use std::{borrow::Cow, ops::Deref};
 
+/// Either a reference or an owned value, like [`Cow`] but without needing
+/// `T: Clone` except to take ownership.
+#[derive(Debug, Clone)]
 pub enum RO<'a, T> {
     Ref(&'a T),
     Owned(T),
 }
 
+impl<'a, T> RO<'a, T> {
+    pub fn is_owned(&self) -> bool {
+        matches!(self, RO::Owned(_))
+    }
+}
+
+impl<'a, T: Clone> RO<'a, T> {
+    /// The owned value, cloning it if this is a reference.
+    pub fn into_owned(self) -> T {
+        match self {
+            RO::Ref(r) => r.clone(),
+            RO::Owned(r) => r,
+        }
+    }
+
+    /// Mutable access to the value, cloning it first if this is a reference.
+    pub fn to_mut(&mut self) -> &mut T {
+        if let RO::Ref(r) = *self {
+            *self = RO::Owned(r.clone());
+        }
+
+        match self {
+            RO::Ref(_) => unreachable!(),
+            RO::Owned(r) => r,
+        }
+    }
+}
+
 impl<'a, T> Deref for RO<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
         match self {
-            RO::Ref(r) => *r,
+            RO::Ref(r) => r,
             RO::Owned(r) => r,
         }
     }
 }
+
+impl<'a, T> AsRef<T> for RO<'a, T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> From<&'a T> for RO<'a, T> {
+    fn from(r: &'a T) -> Self {
+        RO::Ref(r)
+    }
+}
+
+impl<'a, T> From<T> for RO<'a, T> {
+    fn from(r: T) -> Self {
+        RO::Owned(r)
+    }
+}
+
+impl<'a, T: Clone> From<Cow<'a, T>> for RO<'a, T> {
+    fn from(cow: Cow<'a, T>) -> Self {
+        match cow {
+            Cow::Borrowed(r) => RO::Ref(r),
+            Cow::Owned(r) => RO::Owned(r),
+        }
+    }
+}
+
+impl<'a, T: Clone> From<RO<'a, T>> for Cow<'a, T> {
+    fn from(ro: RO<'a, T>) -> Self {
+        match ro {
+            RO::Ref(r) => Cow::Borrowed(r),
+            RO::Owned(r) => Cow::Owned(r),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Building an index from a directory of templates, e.g. a monorepo with
+//! one template per subdirectory. A directory is a template if it has a
+//! manifest at [`MANIFEST_PATH`], or in another hooks directory if asked to
+//! look there.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ScanError,
+    hooks::DEFAULT_HOOKS_DIR,
+    template::{check_template_name, Metadata, Template},
+};
+
+/// Where a template's manifest is, relative to the template, next to its
+/// hooks.
+pub const MANIFEST_PATH: &str = "thor/template.toml";
+
+/// Name of the manifest in a template's hooks directory.
+pub const MANIFEST_FILE_NAME: &str = "template.toml";
+
+/// What a template says about itself; everything is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TemplateManifest {
+    /// defaults to the name of the template's directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl TemplateManifest {
+    pub fn load(path: &Path) -> Result<Self, ScanError> {
+        let contents = fs::read_to_string(path).map_err(|source| ScanError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        toml::from_str(&contents).map_err(|source| ScanError::Manifest {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// The local template at `dir` this manifest describes.
+    pub fn template(self, dir: &Path) -> Result<Template, ScanError> {
+        let name = match self.name {
+            Some(name) => name,
+            None => dir
+                .file_name()
+                .map(|it| it.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        check_template_name(&name).map_err(|source| ScanError::InvalidName {
+            path: dir.to_owned(),
+            source,
+        })?;
+
+        Ok(Template::Local {
+            name,
+            description: self.description,
+            metadata: self.metadata,
+            path: dir.to_owned(),
+        })
+    }
+}
+
+/// Finds every template under `dir`, at any depth; templates inside other
+/// templates, and hidden directories, are skipped. Paths are absolute.
+pub fn scan_dir(dir: &Path) -> Result<Vec<Template>, ScanError> {
+    scan_dir_with(dir, DEFAULT_HOOKS_DIR)
+}
+
+/// Like [`scan_dir`], with manifests in `hooks_dir` instead of `thor`;
+/// templates found this way record it, unless they name another one.
+pub fn scan_dir_with(dir: &Path, hooks_dir: &str) -> Result<Vec<Template>, ScanError> {
+    let dir = dir.canonicalize().map_err(|source| ScanError::Io {
+        path: dir.to_owned(),
+        source,
+    })?;
+
+    let mut templates = Vec::new();
+    scan_into(&dir, hooks_dir, &mut templates)?;
+
+    let mut seen = BTreeSet::new();
+    for t in templates.iter() {
+        if !seen.insert((t.name(), t.version())) {
+            return Err(ScanError::DuplicateName(t.versioned_name()));
+        }
+    }
+
+    Ok(templates)
+}
+
+fn scan_into(dir: &Path, hooks_dir: &str, templates: &mut Vec<Template>) -> Result<(), ScanError> {
+    let manifest = dir.join(hooks_dir).join(MANIFEST_FILE_NAME);
+    if manifest.is_file() {
+        let mut manifest = TemplateManifest::load(&manifest)?;
+        if hooks_dir != DEFAULT_HOOKS_DIR && manifest.metadata.hooks_dir.is_none() {
+            manifest.metadata.hooks_dir = Some(hooks_dir.to_string());
+        }
+        templates.push(manifest.template(dir)?);
+        return Ok(());
+    }
+
+    let io_err = |source| ScanError::Io {
+        path: dir.to_owned(),
+        source,
+    };
+
+    let mut subdirs = Vec::<PathBuf>::new();
+    for entry in fs::read_dir(dir).map_err(io_err)? {
+        let entry = entry.map_err(io_err)?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+        if !hidden && entry.file_type().map_err(io_err)?.is_dir() {
+            subdirs.push(entry.path());
+        }
+    }
+    // so the templates come out in a stable order
+    subdirs.sort();
+
+    for subdir in subdirs {
+        scan_into(&subdir, hooks_dir, templates)?;
+    }
+
+    Ok(())
+}
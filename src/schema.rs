@@ -0,0 +1,80 @@
+//! Upgrading the versioned files thorc reads (indexes, the config) from the
+//! layouts older versions wrote.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::error::{SchemaError, TomlError};
+
+/// Upgrades a file's table by one version, in place.
+pub(crate) type Migration = fn(&mut toml::value::Table);
+
+/// Brings `table` up to the current version, `migrations.len()`, where
+/// `migrations[i]` upgrades from version `i` to `i + 1` and a missing
+/// `schema_version` is version 0. Returns the version `table` was at.
+pub(crate) fn migrate(
+    table: &mut toml::value::Table,
+    migrations: &[Migration],
+) -> Result<u32, SchemaError> {
+    let current = migrations.len() as u32;
+
+    let version = match table.get("schema_version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| SchemaError::InvalidVersion)?
+        }
+        Some(_) => return Err(SchemaError::InvalidVersion),
+    };
+
+    if version > current {
+        return Err(SchemaError::TooNew {
+            found: version,
+            supported: current,
+        });
+    }
+
+    for migrate in &migrations[version as usize..] {
+        migrate(table);
+    }
+    table.insert(
+        "schema_version".to_string(),
+        toml::Value::Integer(current.into()),
+    );
+
+    Ok(version)
+}
+
+/// Keeps `contents`, what the file at `path` held at `version`, next to it as
+/// `<file>.v<version>.bak` before it is migrated; returns the backup's path.
+pub(crate) fn backup(path: &Path, version: u32, contents: &str) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    fs::write(&backup, contents)?;
+
+    Ok(PathBuf::from(backup))
+}
+
+/// Parses a TOML file, migrating it as [`migrate`] does; returns it and the
+/// version it was at. Files already at the current version are deserialized
+/// straight from `contents`, so that errors point at the offending line.
+pub(crate) fn from_toml<T: DeserializeOwned>(
+    contents: &str,
+    migrations: &[Migration],
+) -> Result<(T, u32), SchemaError> {
+    let mut table = toml::from_str::<toml::value::Table>(contents)
+        .map_err(|err| TomlError::new(err, contents))?;
+    let version = migrate(&mut table, migrations)?;
+
+    let value = if version == migrations.len() as u32 {
+        toml::from_str(contents)
+    } else {
+        toml::Value::Table(table).try_into()
+    }
+    .map_err(|err| TomlError::new(err, contents))?;
+
+    Ok((value, version))
+}
@@ -0,0 +1,58 @@
+//! Config values encrypted with [age](https://age-encryption.org), so that
+//! configs holding tokens can be kept in a public dotfiles repository.
+//!
+//! A value is encrypted if it is an ASCII-armored age file, e.g. from
+//! `printf %s "$TOKEN" | age -a -r <recipient>`, pasted into a `'''` string.
+//! It is decrypted with the `age` (or `rage`) binary and the identity file
+//! the config's `[secrets]` names. Variables and index urls may be
+//! encrypted; decrypted variables are left out of lock files.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::error::SecretError;
+
+/// The first line of an ASCII-armored age file.
+pub const ARMOR_HEADER: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// The program decrypting values unless the config names another.
+pub const DEFAULT_PROGRAM: &str = "age";
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.trim_start().starts_with(ARMOR_HEADER)
+}
+
+/// Decrypts `value` by running `program --decrypt --identity <identity>`.
+pub fn decrypt(value: &str, program: &str, identity: &Path) -> Result<String, SecretError> {
+    let mut child = Command::new(program)
+        .arg("--decrypt")
+        .arg("--identity")
+        .arg(identity)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| SecretError::Spawn {
+            program: program.to_string(),
+            source,
+        })?;
+
+    // dropped right after, closing stdin so the program sees the end
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(value.trim().as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(SecretError::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|_| SecretError::NotUtf8)
+}
@@ -0,0 +1,228 @@
+use std::{fs, io::Cursor, path::Path};
+
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+use crate::{
+    error::ServeError,
+    index::{TemplateIndex, SCHEMA_VERSION},
+    registry::{RegistryTemplate, SearchResponse},
+    repo_def,
+    template::Template,
+    utils::archive_dir,
+};
+
+/// Serves `indexes` over http until the process is stopped.
+///
+/// Each index is published at `/<name>/index.toml`, with every template
+/// rewritten to an archive served (and cached) at
+/// `/<name>/archive/<template>.tar.gz`, so clients need no git access.
+///
+/// `/<name>` is also a base url for the [registry protocol](crate::registry).
+pub fn serve(
+    addr: &str,
+    public_url: Option<&str>,
+    indexes: &[(String, TemplateIndex)],
+    cache: &Path,
+) -> Result<(), ServeError> {
+    let server = Server::http(addr).map_err(|err| ServeError::Bind(err.to_string()))?;
+
+    tracing::info!("Serving {} index(es) on {}", indexes.len(), addr);
+
+    for request in server.incoming_requests() {
+        let base_url = match public_url {
+            Some(public_url) => public_url.trim_end_matches('/').to_string(),
+            None => request
+                .headers()
+                .iter()
+                .find(|it| it.field.equiv("Host"))
+                .map(|it| format!("http://{}", it.value))
+                .unwrap_or_else(|| format!("http://{}", addr)),
+        };
+
+        let response = match respond(&request, &base_url, indexes, cache) {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::warn!("Cannot serve {}: {}", request.url(), err);
+                Response::from_string(err.to_string()).with_status_code(500)
+            }
+        };
+
+        if let Err(err) = request.respond(response) {
+            tracing::warn!("Cannot send response: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+fn respond(
+    request: &Request,
+    base_url: &str,
+    indexes: &[(String, TemplateIndex)],
+    cache: &Path,
+) -> Result<Response<Cursor<Vec<u8>>>, ServeError> {
+    if request.method() != &Method::Get {
+        return Ok(Response::from_string("method not allowed").with_status_code(405));
+    }
+
+    let url = match request_url(request.url()) {
+        Some(url) => url,
+        None => return Ok(Response::from_string("bad request").with_status_code(400)),
+    };
+    let segments = url
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .collect::<Vec<_>>();
+
+    let find_index = |name: &str| {
+        indexes
+            .iter()
+            .find(|(it, _)| it == name)
+            .map(|(_, index)| index)
+    };
+
+    let response = match segments.as_slice() {
+        [""] => {
+            let names = indexes
+                .iter()
+                .map(|(name, _)| format!("{}\n", name))
+                .collect::<String>();
+            Response::from_string(names)
+        }
+        [name, "index.toml"] => match find_index(name) {
+            Some(index) => {
+                let index_url = format!("{}/{}", base_url, name);
+                let published = toml::to_string_pretty(&published_index(index, &index_url))?;
+
+                Response::from_string(published).with_header(content_type("application/toml"))
+            }
+            None => not_found(),
+        },
+        [name, "search"] => match find_index(name) {
+            Some(index) => {
+                let term = url
+                    .query_pairs()
+                    .find(|(key, _)| key == "q")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap_or_default();
+                let found = index.find(&term);
+
+                let templates = found
+                    .iter()
+                    .map(|(template, _, _)| RegistryTemplate::from(template))
+                    .collect();
+
+                json_response(&SearchResponse { templates })?
+            }
+            None => not_found(),
+        },
+        [name, "templates", template] => {
+            match find_index(name).and_then(|index| index.find_exact(template)) {
+                Some(template) => json_response(&RegistryTemplate::from(template))?,
+                None => not_found(),
+            }
+        }
+        [name, "templates", template, "archive"] => {
+            match find_index(name).and_then(|index| index.find_exact(template)) {
+                Some(template) => Response::from_data(template_archive(template, cache)?)
+                    .with_header(content_type("application/gzip")),
+                None => not_found(),
+            }
+        }
+        [name, "archive", file] => {
+            let template = file
+                .strip_suffix(".tar.gz")
+                .and_then(|template| find_index(name)?.find_exact(template));
+
+            match template {
+                Some(template) => Response::from_data(template_archive(template, cache)?)
+                    .with_header(content_type("application/gzip")),
+                None => not_found(),
+            }
+        }
+        _ => not_found(),
+    };
+
+    Ok(response)
+}
+
+/// The url of a request for `target`, if it makes one.
+fn request_url(target: &str) -> Option<Url> {
+    Url::parse(&format!("http://localhost{}", target)).ok()
+}
+
+/// The index as clients see it: every template points back at this server.
+fn published_index(index: &TemplateIndex, index_url: &str) -> TemplateIndex {
+    let templates = index
+        .templates
+        .iter()
+        .map(|t| Template::Archive {
+            name: t.name().to_string(),
+            description: t.description().cloned(),
+            metadata: t.metadata().clone(),
+            url: format!("{}/archive/{}.tar.gz", index_url, t.exact_name()),
+            setup: t.setup().cloned(),
+        })
+        .collect();
+
+    TemplateIndex {
+        schema_version: SCHEMA_VERSION,
+        for_remote: true,
+        namespaced: index.namespaced,
+        // clients fetch these themselves
+        includes: index.includes.clone(),
+        templates,
+    }
+}
+
+fn template_archive(template: &Template, cache: &Path) -> Result<Vec<u8>, ServeError> {
+    let archive = match template {
+        // there is no archive of a checkout to pass on
+        Template::Repo { repo, name, .. } if repo.fetch.uses_git() => {
+            archive_dir(&repo.download(cache)?, name)?
+        }
+        Template::Repo { repo, .. } => {
+            repo.download(cache)?;
+            fs::read(repo.with_cached_resolution(cache).archive_path(cache))?
+        }
+        Template::Local { name, path, .. } => archive_dir(path, name)?,
+        Template::Archive { url, .. } => {
+            template.download(cache)?;
+            fs::read(repo_def::archive_path(
+                cache,
+                &repo_def::url_cache_file(url),
+            ))?
+        }
+    };
+
+    Ok(archive)
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> Result<Response<Cursor<Vec<u8>>>, ServeError> {
+    Ok(Response::from_string(serde_json::to_string(value)?)
+        .with_header(content_type("application/json")))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
+
+fn not_found() -> Response<Cursor<Vec<u8>>> {
+    Response::from_string("not found").with_status_code(404)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_request_targets_make_no_url() {
+        assert_eq!(
+            request_url("/corp/search?q=axum").unwrap().path(),
+            "/corp/search"
+        );
+        assert!(request_url(":port").is_none());
+    }
+}
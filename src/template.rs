@@ -1,13 +1,19 @@
 use std::{
-    borrow::Borrow,
+    io,
     path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "network")]
 use crate::{
-    error::{CheckTemplateNameError, DownloadError},
-    repo_def::RepoDef,
+    cancel::CancelToken, error::DownloadError, http::Downloader, remote_index::DEFAULT_REFRESH,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    error::{BrokenTemplateError, CheckTemplateNameError, ThorcVersionError},
+    repo_def::{self, RepoDef},
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,9 +23,8 @@ pub enum Template {
         name: String,
         #[serde(default, skip_serializing_if = "Option::is_none")]
         description: Option<String>,
-
         #[serde(flatten)]
-        repo: RepoDef,
+        metadata: Metadata,
 
         /// issue the template was added from.
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -28,19 +33,41 @@ pub enum Template {
         /// setup kind
         #[serde(default, skip_serializing_if = "Option::is_none")]
         setup: Option<SetupKind>,
+
+        // last, see `RepoDef::git_provider`
+        #[serde(flatten)]
+        repo: RepoDef,
     },
     Local {
         name: String,
         #[serde(default, skip_serializing_if = "Option::is_none")]
         description: Option<String>,
+        #[serde(flatten)]
+        metadata: Metadata,
 
         path: PathBuf,
     },
+    Archive {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+        #[serde(flatten)]
+        metadata: Metadata,
+
+        /// link to a `.tar.gz` of the template, e.g. one served by `thorc serve`.
+        url: String,
+
+        /// setup kind
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        setup: Option<SetupKind>,
+    },
 }
 
+/// Templates are identified by their name and version; an index may list
+/// several versions of the same template.
 impl PartialEq for Template {
     fn eq(&self, other: &Self) -> bool {
-        self.name().eq(other.name())
+        self.name().eq(other.name()) && self.version().eq(&other.version())
     }
 }
 impl Eq for Template {}
@@ -53,21 +80,73 @@ impl PartialOrd for Template {
 
 impl Ord for Template {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.name().cmp(other.name())
-    }
-}
-
-impl Borrow<str> for Template {
-    fn borrow(&self) -> &str {
         self.name()
+            .cmp(other.name())
+            .then_with(|| self.version().cmp(&other.version()))
     }
 }
 
 impl Template {
+    #[cfg(feature = "network")]
     pub fn download(&self, cache: &Path) -> Result<PathBuf, DownloadError> {
+        self.download_with(cache, &crate::http::ReqwestDownloader)
+    }
+
+    #[cfg(feature = "network")]
+    pub fn download_with(
+        &self,
+        cache: &Path,
+        downloader: &dyn Downloader,
+    ) -> Result<PathBuf, DownloadError> {
         match self {
-            Template::Repo { repo, .. } => repo.download(cache),
+            Template::Repo { repo, .. } => repo.download_with(cache, downloader),
             Template::Local { path, .. } => Ok(path.clone()),
+            Template::Archive { url, .. } => repo_def::download_archive(
+                cache,
+                &repo_def::url_cache_file(url),
+                url,
+                downloader,
+                DEFAULT_REFRESH,
+                &CancelToken::new(),
+            ),
+        }
+    }
+
+    /// The name the template's archive is cached as, and where it is
+    /// downloaded from; `None` for local templates.
+    pub(crate) fn archive(&self) -> Option<(String, String)> {
+        match self {
+            Template::Repo { repo, .. } => Some((repo.cache_file(), repo.archive_link())),
+            Template::Local { .. } => None,
+            Template::Archive { url, .. } => Some((repo_def::url_cache_file(url), url.clone())),
+        }
+    }
+
+    /// Removes everything cached for this template; returns the removed paths.
+    pub fn purge_cache(&self, cache: &Path) -> io::Result<Vec<PathBuf>> {
+        match self {
+            Template::Repo { repo, .. } => repo.purge_cache(cache),
+            Template::Local { .. } => Ok(Vec::new()),
+            Template::Archive { url, .. } => {
+                repo_def::purge_cache(cache, &repo_def::url_cache_file(url))
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "network"), allow(unused_variables))]
+    pub fn commit(&self, cache: &Path) -> io::Result<Option<String>> {
+        match self {
+            #[cfg(feature = "network")]
+            Template::Repo { repo, .. } => repo.archive_commit(cache),
+            Template::Local { .. } => Ok(None),
+            #[cfg(feature = "network")]
+            Template::Archive { url, .. } => repo_def::archive_commit(&repo_def::archive_path(
+                cache,
+                &repo_def::url_cache_file(url),
+            )),
+            // nothing is ever downloaded without the network feature
+            #[cfg(not(feature = "network"))]
+            _ => Ok(None),
         }
     }
 
@@ -75,6 +154,15 @@ impl Template {
         match self {
             Template::Repo { name, .. } => name,
             Template::Local { name, .. } => name,
+            Template::Archive { name, .. } => name,
+        }
+    }
+
+    pub fn set_name(&mut self, new_name: String) {
+        match self {
+            Template::Repo { name, .. } => *name = new_name,
+            Template::Local { name, .. } => *name = new_name,
+            Template::Archive { name, .. } => *name = new_name,
         }
     }
 
@@ -82,13 +170,166 @@ impl Template {
         match self {
             Template::Repo { description, .. } => description.as_ref(),
             Template::Local { description, .. } => description.as_ref(),
+            Template::Archive { description, .. } => description.as_ref(),
         }
     }
 
+    pub fn metadata(&self) -> &Metadata {
+        match self {
+            Template::Repo { metadata, .. } => metadata,
+            Template::Local { metadata, .. } => metadata,
+            Template::Archive { metadata, .. } => metadata,
+        }
+    }
+
+    pub fn version(&self) -> Option<&semver::Version> {
+        self.metadata().version.as_ref()
+    }
+
+    /// `name@version`, or just the name if the template isn't versioned.
+    pub fn versioned_name(&self) -> String {
+        match self.version() {
+            Some(version) => format!("{}@{}", self.name(), version),
+            None => self.name().to_string(),
+        }
+    }
+
+    /// A name that resolves to exactly this template, even when there are
+    /// newer versions: `name@=version`.
+    pub fn exact_name(&self) -> String {
+        exact_name(self.name(), self.version())
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.metadata().tags
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.metadata().deprecated || self.superseded_by().is_some()
+    }
+
+    pub fn superseded_by(&self) -> Option<&str> {
+        self.metadata().superseded_by.as_deref()
+    }
+
+    pub fn hooks_dir(&self) -> Option<&str> {
+        self.metadata().hooks_dir.as_deref()
+    }
+
+    /// `deprecated`, and what to use instead if known.
+    pub fn deprecation_notice(&self) -> Option<String> {
+        if !self.is_deprecated() {
+            return None;
+        }
+
+        Some(match self.superseded_by() {
+            Some(replacement) => format!("deprecated, use {} instead", replacement),
+            None => "deprecated".to_string(),
+        })
+    }
+
+    /// Fails if the template needs a newer thorc than this one.
+    pub fn check_thorc_version(&self) -> Result<(), ThorcVersionError> {
+        let required = match &self.metadata().min_thorc_version {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+        let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+
+        if current < *required {
+            return Err(ThorcVersionError {
+                template: self.name().to_string(),
+                required: required.clone(),
+                current,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the template, downloaded to `path`, has no files, or lacks
+    /// the manifest its setup kind edits.
+    pub fn check_contents(&self, path: &Path) -> Result<(), BrokenTemplateError> {
+        let has_files = WalkDir::new(path)
+            .into_iter()
+            .filter_map(Result::ok)
+            .any(|it| !it.file_type().is_dir());
+        if !has_files {
+            return Err(BrokenTemplateError::Empty {
+                template: self.versioned_name(),
+            });
+        }
+
+        if let Some(setup) = self.setup() {
+            if !path.join(setup.manifest()).is_file() {
+                return Err(BrokenTemplateError::MissingManifest {
+                    template: self.versioned_name(),
+                    setup: setup.name(),
+                    manifest: setup.manifest(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the template has every one of `tags`, ignoring case.
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags().iter().any(|it| it.eq_ignore_ascii_case(tag)))
+    }
+
+    pub fn setup(&self) -> Option<&SetupKind> {
+        match self {
+            Template::Repo { setup, .. } => setup.as_ref(),
+            Template::Local { .. } => None,
+            Template::Archive { setup, .. } => setup.as_ref(),
+        }
+    }
+
+    /// Everything known about the template, one `key: value` per line.
+    pub fn details(&self) -> String {
+        let source = match self {
+            Template::Repo { repo, .. } => repo.link(),
+            Template::Local { path, .. } => path.display().to_string(),
+            Template::Archive { url, .. } => url.clone(),
+        };
+        let metadata = self.metadata();
+
+        let mut fields = vec![
+            ("name", Some(self.name().to_string())),
+            ("source", Some(source)),
+        ];
+        fields.extend([
+            ("version", self.version().map(|it| it.to_string())),
+            ("description", self.description().cloned()),
+            (
+                "tags",
+                Some(metadata.tags.join(", ")).filter(|it| !it.is_empty()),
+            ),
+            ("author", metadata.author.clone()),
+            ("license", metadata.license.clone()),
+            ("homepage", metadata.homepage.clone()),
+            ("status", self.deprecation_notice()),
+            (
+                "min thorc version",
+                metadata.min_thorc_version.as_ref().map(|it| it.to_string()),
+            ),
+            ("setup", self.setup().map(|it| format!("{:?}", it))),
+        ]);
+        if let Template::Repo { issue, .. } = self {
+            fields.push(("issue", issue.map(|it| it.to_string())));
+        }
+
+        fields
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{}: {}\n", key, value?)))
+            .collect()
+    }
+
     pub fn one_line_summary(&self) -> String {
         match self {
             Template::Repo {
-                name,
                 description,
                 repo,
                 issue,
@@ -100,26 +341,81 @@ impl Template {
                     (Some(desc), Some(issue)) => format!(" {} [{}]", desc, issue),
                     (Some(desc), None) => format!(" {}", desc),
                     (None, Some(issue)) => format!("[for issue {}]", issue),
-                    (None, None) => format!(""),
+                    (None, None) => String::new(),
+                };
+                let commit_text = match &repo.commit {
+                    Some(commit) => format!(" @ {}", commit),
+                    None => String::new(),
                 };
-                format!("{} => {}{}", name, repo.link(), extra_text)
+                format!(
+                    "{} => {}{}{}",
+                    self.versioned_name(),
+                    repo.link(),
+                    commit_text,
+                    extra_text
+                )
             }
             Template::Local {
-                name,
-                description,
-                path,
+                description, path, ..
             } => {
                 let desc_text = description.as_ref();
                 let extra_text = match desc_text {
                     Some(desc) => format!(" {}", desc),
-                    None => format!(""),
+                    None => String::new(),
                 };
-                format!("{} => {}{}", name, path.display(), extra_text)
+                format!(
+                    "{} => {}{}",
+                    self.versioned_name(),
+                    path.display(),
+                    extra_text
+                )
+            }
+            Template::Archive {
+                description, url, ..
+            } => {
+                let extra_text = match description {
+                    Some(desc) => format!(" {}", desc),
+                    None => String::new(),
+                };
+                format!("{} => {}{}", self.versioned_name(), url, extra_text)
             }
         }
     }
 }
 
+/// Optional information about a template, shared by every kind of template.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Metadata {
+    /// templates with the same name but different versions may be listed
+    /// side by side, e.g. pointing at different refs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<semver::Version>,
+    /// keywords to find the template by.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// SPDX expression, e.g. `MIT OR Apache-2.0`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    /// oldest thorc that can generate projects from the template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_thorc_version: Option<semver::Version>,
+    /// deprecated templates are still listed, but `new` refuses them unless
+    /// asked not to.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deprecated: bool,
+    /// the template to use instead; implies `deprecated`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<String>,
+    /// directory the template's hooks and manifest are in, relative to it,
+    /// for templates following another convention, like `.scaffold`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks_dir: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum SetupKind {
@@ -127,15 +423,229 @@ pub enum SetupKind {
     Npm,
 }
 
+impl SetupKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SetupKind::Rust => "rust",
+            SetupKind::Npm => "npm",
+        }
+    }
+
+    /// The file, at the template's root, the setup edits.
+    pub fn manifest(&self) -> &'static str {
+        match self {
+            SetupKind::Rust => "Cargo.toml",
+            SetupKind::Npm => "package.json",
+        }
+    }
+}
+
+/// `name@=version`, or just `name` without a version.
+pub fn exact_name(name: &str, version: Option<&semver::Version>) -> String {
+    match version {
+        Some(version) => format!("{}@={}", name, version),
+        None => name.to_string(),
+    }
+}
+
+/// Which versions of a template will do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSpec {
+    Latest,
+    /// a semver requirement: `1.2` means `^1.2`, `=1.2.3` exactly `1.2.3`.
+    Req(semver::VersionReq),
+}
+
+impl VersionSpec {
+    pub fn parse(s: &str) -> Result<Self, semver::Error> {
+        match s {
+            "latest" => Ok(VersionSpec::Latest),
+            s => Ok(VersionSpec::Req(semver::VersionReq::parse(s)?)),
+        }
+    }
+
+    /// Unversioned templates only match [`VersionSpec::Latest`].
+    pub fn matches(&self, version: Option<&semver::Version>) -> bool {
+        match self {
+            VersionSpec::Latest => true,
+            VersionSpec::Req(req) => version.is_some_and(|it| req.matches(it)),
+        }
+    }
+}
+
+/// A template name, optionally qualified with the index it should be taken
+/// from: `corp:axum-service`, and with the version: `axum-service@1.2` or
+/// `axum-service@latest`. A `/` is part of the name, as in `org/cli.rust`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualifiedName<'a> {
+    pub index: Option<&'a str>,
+    pub name: &'a str,
+    pub version: Option<&'a str>,
+}
+
+impl<'a> QualifiedName<'a> {
+    pub fn parse(s: &'a str) -> Self {
+        let (index, name) = match s.split_once(':') {
+            Some((index, name)) => (Some(index), name),
+            None => (None, s),
+        };
+
+        match name.split_once('@') {
+            Some((name, version)) => Self {
+                index,
+                name,
+                version: Some(version),
+            },
+            None => Self {
+                index,
+                name,
+                version: None,
+            },
+        }
+    }
+}
+
+/// Like [`check_template_name`], but also accepts an index qualifier and a
+/// [`VersionSpec`].
+pub fn check_qualified_template_name(
+    name: &str,
+) -> Result<QualifiedName<'_>, CheckTemplateNameError> {
+    let qualified = QualifiedName::parse(name);
+
+    if let Some(index) = qualified.index {
+        check_name_chars(index, false)?;
+    }
+
+    // report positions relative to the whole name
+    let offset = match qualified.index {
+        Some(index) => index.chars().count() + 1,
+        None => 0,
+    };
+    check_template_name_with(qualified.name, true).map_err(|err| match err {
+        CheckTemplateNameError::InvalidCharacter { c, index, .. } => {
+            CheckTemplateNameError::InvalidCharacter {
+                name: name.to_string(),
+                c,
+                index: index + offset,
+            }
+        }
+        err => err,
+    })?;
+
+    if let Some(version) = qualified.version {
+        VersionSpec::parse(version)
+            .map_err(|_| CheckTemplateNameError::InvalidVersion(version.to_string()))?;
+    }
+
+    Ok(qualified)
+}
+
+/// The longest template name accepted.
+pub const MAX_TEMPLATE_NAME_LEN: usize = 64;
+
+/// Names templates can't have, as they mean something else where a template
+/// name is expected.
+pub const RESERVED_TEMPLATE_NAMES: &[&str] = &["local", "all"];
+
+/// Template names are ASCII letters, digits, `-` and `_`, at most
+/// [`MAX_TEMPLATE_NAME_LEN`] long, don't start with `-` and aren't one of the
+/// [`RESERVED_TEMPLATE_NAMES`].
 pub fn check_template_name(name: &str) -> Result<(), CheckTemplateNameError> {
-    if let Some((index, c)) = name.chars().enumerate().find(|(_, it)| {
-        !('a'..='z').contains(it)
-            && !('A'..='Z').contains(it)
-            && !('0'..='9').contains(it)
-            && !"-_".contains(*it)
-    }) {
-        return Err(CheckTemplateNameError::InvalidCharacter { c, index });
+    check_template_name_with(name, false)
+}
+
+/// Like [`check_template_name`]; if `namespaced`, names may also have `.`
+/// and `/` between their parts, as in `org/cli.rust`.
+pub fn check_template_name_with(
+    name: &str,
+    namespaced: bool,
+) -> Result<(), CheckTemplateNameError> {
+    if RESERVED_TEMPLATE_NAMES.contains(&name) {
+        return Err(CheckTemplateNameError::Reserved(name.to_string()));
+    }
+
+    check_name_chars(name, namespaced)
+}
+
+/// The rules index names follow too.
+fn check_name_chars(name: &str, namespaced: bool) -> Result<(), CheckTemplateNameError> {
+    let len = name.chars().count();
+    if len > MAX_TEMPLATE_NAME_LEN {
+        return Err(CheckTemplateNameError::TooLong {
+            name: name.to_string(),
+            max: MAX_TEMPLATE_NAME_LEN,
+        });
+    }
+
+    if name.starts_with('-') {
+        return Err(CheckTemplateNameError::LeadingDash(name.to_string()));
+    }
+
+    let is_separator = |c: char| namespaced && "./".contains(c);
+    let mut prev = None;
+    for (index, c) in name.chars().enumerate() {
+        // separators only ever between two parts
+        let valid = c.is_ascii_alphanumeric()
+            || "-_".contains(c)
+            || (is_separator(c) && index > 0 && index + 1 < len && !prev.is_some_and(is_separator));
+
+        if !valid {
+            return Err(CheckTemplateNameError::InvalidCharacter {
+                name: name.to_string(),
+                c,
+                index,
+            });
+        }
+        prev = Some(c);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slashes_belong_to_the_name() {
+        assert_eq!(
+            QualifiedName::parse("org/cli.rust"),
+            QualifiedName {
+                index: None,
+                name: "org/cli.rust",
+                version: None,
+            }
+        );
+        assert_eq!(
+            QualifiedName::parse("corp:org/cli.rust@1.2"),
+            QualifiedName {
+                index: Some("corp"),
+                name: "org/cli.rust",
+                version: Some("1.2"),
+            }
+        );
+    }
+
+    #[test]
+    fn reports_positions_in_the_whole_name() {
+        let err = check_qualified_template_name("corp:org/cli!").unwrap_err();
+        assert!(matches!(
+            err,
+            CheckTemplateNameError::InvalidCharacter {
+                c: '!',
+                index: 12,
+                ..
+            }
+        ));
+
+        let err = check_qualified_template_name("org/cli!").unwrap_err();
+        assert!(matches!(
+            err,
+            CheckTemplateNameError::InvalidCharacter {
+                c: '!',
+                index: 7,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+//! Helpers for exercising downloads, extraction and generation without the
+//! network: a local http server serving fixture index files and template
+//! archives. Only built with the `test-utils` feature.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use tiny_http::{Header, Response, Server, StatusCode};
+
+use crate::{error::ServeError, index::TemplateIndex, utils};
+
+type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// An http server on a free local port, serving whatever it's given at the
+/// paths it's given, with ETags; anything else is a 404. Stops when dropped.
+pub struct MockServer {
+    url: String,
+    server: Arc<Server>,
+    files: Files,
+    requests: Arc<Mutex<Vec<String>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start() -> Result<Self, ServeError> {
+        let server =
+            Server::http("127.0.0.1:0").map_err(|err| ServeError::Bind(err.to_string()))?;
+        let server = Arc::new(server);
+        let url = format!("http://{}", server.server_addr());
+
+        let files = Files::default();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = {
+            let (server, files, requests) = (server.clone(), files.clone(), requests.clone());
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let path = request
+                        .url()
+                        .split('?')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string();
+                    requests.lock().unwrap().push(path.clone());
+
+                    let if_none_match = request
+                        .headers()
+                        .iter()
+                        .find(|it| it.field.equiv("If-None-Match"))
+                        .map(|it| it.value.to_string());
+
+                    let response = match files.lock().unwrap().get(&path) {
+                        Some(bytes) => {
+                            let etag = format!("\"{}\"", &utils::hash_buffer(bytes)[..16]);
+                            let header = Header::from_bytes("ETag", etag.as_str()).unwrap();
+
+                            if if_none_match.as_deref() == Some(etag.as_str()) {
+                                Response::from_data(Vec::new())
+                                    .with_status_code(StatusCode(304))
+                                    .with_header(header)
+                            } else {
+                                Response::from_data(bytes.clone()).with_header(header)
+                            }
+                        }
+                        None => Response::from_data(Vec::new()).with_status_code(StatusCode(404)),
+                    };
+
+                    let _ = request.respond(response);
+                }
+            })
+        };
+
+        Ok(Self {
+            url,
+            server,
+            files,
+            requests,
+            handle: Some(handle),
+        })
+    }
+
+    /// Base url of the server, without a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The url `path` is served at; `path` starts with a `/`.
+    pub fn url_for(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// Serves `bytes` at `path`, replacing whatever was there.
+    pub fn serve(&self, path: &str, bytes: impl Into<Vec<u8>>) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), bytes.into());
+    }
+
+    /// Stops serving `path`; it's a 404 from now on.
+    pub fn remove(&self, path: &str) {
+        self.files.lock().unwrap().remove(path);
+    }
+
+    /// Serves `index` as TOML at `path`.
+    pub fn serve_index(&self, path: &str, index: &TemplateIndex) -> Result<(), ServeError> {
+        self.serve(path, toml::to_string_pretty(index)?);
+
+        Ok(())
+    }
+
+    /// Serves `dir` as a `.tar.gz` at `path`, with everything under a single
+    /// `prefix` directory, like forge-generated archives.
+    pub fn serve_dir_archive(
+        &self,
+        path: &str,
+        dir: &Path,
+        prefix: &str,
+    ) -> Result<(), ServeError> {
+        self.serve(path, utils::archive_dir(dir, prefix)?);
+
+        Ok(())
+    }
+
+    /// The paths requested so far, oldest first.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
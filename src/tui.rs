@@ -0,0 +1,221 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use thorc::{index::TemplateIndex, template::Template};
+
+enum Mode {
+    Browse,
+    Search,
+    Directory,
+}
+
+struct App<'a> {
+    cache: &'a Path,
+    entries: Vec<(&'a str, &'a Template)>,
+    mode: Mode,
+    filter: String,
+    directory: String,
+    list_state: ListState,
+    /// file tree of the last previewed template, by template name.
+    preview: Option<(String, Vec<String>)>,
+}
+
+impl<'a> App<'a> {
+    fn visible(&self) -> Vec<(&'a str, &'a Template)> {
+        self.entries
+            .iter()
+            .filter(|(_, t)| {
+                t.name().contains(&self.filter)
+                    || t.description().is_some_and(|d| d.contains(&self.filter))
+            })
+            .copied()
+            .collect()
+    }
+
+    fn selected(&self) -> Option<(&'a str, &'a Template)> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.visible().get(i).copied())
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let len = self.visible().len();
+        if len == 0 {
+            self.list_state.select(None);
+            return;
+        }
+
+        let current = self.list_state.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, len as isize - 1);
+        self.list_state.select(Some(next as usize));
+    }
+
+    fn load_preview(&mut self) {
+        if let Some((_, template)) = self.selected() {
+            let tree = match template.download(self.cache) {
+                Ok(path) => file_tree(&path).unwrap_or_else(|err| vec![format!("error: {}", err)]),
+                Err(err) => vec![format!("error: {}", err)],
+            };
+
+            self.preview = Some((template.name().to_string(), tree));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(3)])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[0]);
+
+        let items = self
+            .visible()
+            .into_iter()
+            .map(|(index, t)| ListItem::new(format!("[{}] {}", index, t.name())))
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Templates"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, columns[0], &mut self.list_state);
+
+        let mut lines = Vec::new();
+        if let Some((_, template)) = self.selected() {
+            lines.push(template.one_line_summary());
+            lines.push(String::new());
+
+            match &self.preview {
+                Some((name, tree)) if name == template.name() => lines.extend(tree.iter().cloned()),
+                _ => lines.push("press p to preview files".to_string()),
+            }
+        }
+        let preview = Paragraph::new(lines.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title("Preview"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(preview, columns[1]);
+
+        let status = match self.mode {
+            Mode::Browse => "q: quit  j/k: move  /: search  p: preview  g: generate".to_string(),
+            Mode::Search => format!("search: {}", self.filter),
+            Mode::Directory => format!("generate into: {}", self.directory),
+        };
+        let status = Paragraph::new(status).block(Block::default().borders(Borders::ALL));
+        frame.render_widget(status, rows[1]);
+    }
+}
+
+/// Browses the given indexes; returns the template to generate and the target
+/// directory, if the user picked one.
+pub fn run(
+    indexes: &[(&str, &TemplateIndex)],
+    cache: &Path,
+) -> io::Result<Option<(Template, PathBuf)>> {
+    let entries = indexes
+        .iter()
+        .flat_map(|&(name, index)| index.templates.iter().map(move |t| (name, t)))
+        .collect::<Vec<_>>();
+
+    let mut app = App {
+        cache,
+        entries,
+        mode: Mode::Browse,
+        filter: String::new(),
+        directory: String::new(),
+        list_state: ListState::default(),
+        preview: None,
+    };
+    app.move_selection(0);
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+) -> io::Result<Option<(Template, PathBuf)>> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+
+        match app.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+                KeyCode::Char('/') => app.mode = Mode::Search,
+                KeyCode::Char('p') | KeyCode::Enter => app.load_preview(),
+                KeyCode::Char('g') if app.selected().is_some() => app.mode = Mode::Directory,
+                _ => {}
+            },
+            Mode::Search => {
+                match key.code {
+                    KeyCode::Enter | KeyCode::Esc => app.mode = Mode::Browse,
+                    KeyCode::Backspace => {
+                        app.filter.pop();
+                    }
+                    KeyCode::Char(c) => app.filter.push(c),
+                    _ => {}
+                }
+                app.list_state.select(Some(0));
+                app.move_selection(0);
+            }
+            Mode::Directory => match key.code {
+                KeyCode::Esc => app.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    app.directory.pop();
+                }
+                KeyCode::Enter if !app.directory.is_empty() => {
+                    if let Some((_, template)) = app.selected() {
+                        return Ok(Some((template.clone(), PathBuf::from(&app.directory))));
+                    }
+                }
+                KeyCode::Char(c) => app.directory.push(c),
+                _ => {}
+            },
+        }
+    }
+}
+
+fn file_tree(root: &Path) -> io::Result<Vec<String>> {
+    fn walk(dir: &Path, depth: usize, lines: &mut Vec<String>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|it| it.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.is_dir() {
+                lines.push(format!("{}{}/", "  ".repeat(depth), name));
+                walk(&path, depth + 1, lines)?;
+            } else {
+                lines.push(format!("{}{}", "  ".repeat(depth), name));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut lines = Vec::new();
+    walk(root, 0, &mut lines)?;
+    Ok(lines)
+}
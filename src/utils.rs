@@ -1,49 +1,833 @@
-use std::{fs, path::{Path, PathBuf}};
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
 
-use sha::{sha512::Sha512, utils::{Digest, DigestExt}};
+#[cfg(feature = "network")]
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha512};
+use similar::{ChangeTag, TextDiff};
+use walkdir::WalkDir;
+
+use crate::{cancel::CancelToken, error::CopyError};
 
 pub fn hash_buffer(buf: &[u8]) -> String {
-    Sha512::default().digest(buf).to_hex()
+    let mut hasher = StreamHasher::new();
+    hasher.update(buf);
+    hasher.finish()
 }
 
-pub fn hash(path: &Path) -> String {
-    let buf = fs::read(path).unwrap();
+/// A sha512 fed piece by piece, e.g. while a download streams past, so
+/// large data is never held or read twice just to hash it. Gives the same
+/// digest as [`hash_buffer`] over all pieces at once.
+#[derive(Default)]
+pub struct StreamHasher(Sha512);
+
+impl StreamHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    /// Feeds everything `reader` yields, a chunk at a time.
+    pub fn update_from<R: Read>(&mut self, mut reader: R) -> Result<(), std::io::Error> {
+        let mut chunk = vec![0; HASH_CHUNK_SIZE];
 
-    hash_buffer(&buf)
+        loop {
+            match reader.read(&mut chunk)? {
+                0 => return Ok(()),
+                n => self.update(&chunk[..n]),
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.0
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
 }
 
-// https://stackoverflow.com/a/60406693
-pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<(), std::io::Error> {
-    let mut stack = Vec::new();
-    stack.push(PathBuf::from(from.as_ref()));
+/// Hashes everything `reader` yields, a chunk at a time.
+pub fn hash_reader<R: Read>(reader: R) -> Result<String, std::io::Error> {
+    let mut hasher = StreamHasher::new();
+    hasher.update_from(reader)?;
+
+    Ok(hasher.finish())
+}
 
-    let output_root = PathBuf::from(to.as_ref());
-    let input_root = PathBuf::from(from.as_ref()).components().count();
+const HASH_CHUNK_SIZE: usize = 1 << 20;
 
-    while let Some(working_path) = stack.pop() {
-        let src: PathBuf = working_path.components().skip(input_root).collect();
+/// Hashes the file at `path` in fixed-size chunks, so multi-GB archives
+/// don't have to fit in memory.
+pub fn hash(path: &Path) -> Result<String, std::io::Error> {
+    hash_reader(fs::File::open(path)?)
+}
 
-        let dest = if src.components().count() == 0 {
-            output_root.clone()
-        } else {
-            output_root.join(&src)
-        };
-        if fs::metadata(&dest).is_err() {
-            fs::create_dir_all(&dest)?;
-        }
+/// Hashes a whole directory tree: every file's relative path and contents,
+/// in a stable order. Each is hashed as a record of the kind of file, then
+/// the length and bytes of the path and of the contents, so no two trees
+/// hash the same records. Symlinks aren't followed; their target is their
+/// contents.
+pub fn hash_dir(path: &Path) -> Result<String, std::io::Error> {
+    let mut files = Vec::new();
+    let mut stack = vec![path.to_path_buf()];
 
-        for entry in fs::read_dir(working_path)? {
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(dir)? {
             let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                stack.push(path);
+            if entry.file_type()?.is_dir() {
+                stack.push(entry.path());
             } else {
-                let filename = path.file_name().unwrap();
-                let dest_path = dest.join(filename);
-                fs::copy(&path, &dest_path)?;
+                files.push(entry.path());
+            }
+        }
+    }
+
+    files.sort();
+
+    let mut hasher = StreamHasher::new();
+    for file in files {
+        let rel = file
+            .strip_prefix(path)
+            .unwrap()
+            .components()
+            .map(|it| it.as_os_str().as_encoded_bytes())
+            .collect::<Vec<_>>()
+            .join(&b'/');
+
+        let is_symlink = fs::symlink_metadata(&file)?.file_type().is_symlink();
+        hasher.update(if is_symlink { b"l" } else { b"f" });
+        hasher.update(&(rel.len() as u64).to_le_bytes());
+        hasher.update(&rel);
+
+        if is_symlink {
+            let target = fs::read_link(&file)?;
+            let target = target.as_os_str().as_encoded_bytes();
+            hasher.update(&(target.len() as u64).to_le_bytes());
+            hasher.update(target);
+        } else {
+            let contents = fs::File::open(&file)?;
+            let len = contents.metadata()?.len();
+            hasher.update(&len.to_le_bytes());
+            // exactly as many bytes as counted, should the file grow meanwhile
+            hasher.update_from(contents.take(len))?;
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Packs `dir` into an in-memory `.tar.gz` whose entries all live under a
+/// single `prefix` directory, like forge-generated archives.
+#[cfg(feature = "network")]
+pub fn archive_dir(dir: &Path, prefix: &str) -> Result<Vec<u8>, std::io::Error> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    builder.append_dir_all(prefix, dir)?;
+    builder.into_inner()?.finish()
+}
+
+/// What to do with a file that already exists at the destination with
+/// different contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    Keep,
+    Overwrite,
+    /// write both versions, with git-style conflict markers around the
+    /// differing lines.
+    Merge,
+}
+
+/// Asked about a destination file (the second path) that already exists
+/// with contents other than the source file's, see [`copy_filtered`].
+pub type OnConflict<'a> = &'a mut dyn FnMut(&Path, &Path) -> Result<Conflict, std::io::Error>;
+
+/// Merges two texts line by line, wrapping every differing hunk in
+/// git-style conflict markers.
+pub fn merge_with_markers(existing: &str, incoming: &str) -> String {
+    fn push_lines(out: &mut String, lines: &str) {
+        out.push_str(lines);
+        if !lines.is_empty() && !lines.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    let diff = TextDiff::from_lines(existing, incoming);
+    let mut out = String::new();
+
+    for op in diff.ops() {
+        let mut ours = String::new();
+        let mut theirs = String::new();
+
+        for change in diff.iter_changes(op) {
+            match change.tag() {
+                ChangeTag::Equal => out.push_str(change.value()),
+                ChangeTag::Delete => ours.push_str(change.value()),
+                ChangeTag::Insert => theirs.push_str(change.value()),
+            }
+        }
+
+        if !ours.is_empty() || !theirs.is_empty() {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str("<<<<<<< existing\n");
+            push_lines(&mut out, &ours);
+            out.push_str("=======\n");
+            push_lines(&mut out, &theirs);
+            out.push_str(">>>>>>> template\n");
+        }
+    }
+
+    out
+}
+
+/// Copies the directory `from` into `to`, creating it and every directory
+/// inside, empty ones included, and overwriting existing files. Returns how
+/// many files and directories were copied, not counting `to` itself.
+pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<usize, CopyError> {
+    copy_filtered(from, to, None, |_| true, &CancelToken::new())
+}
+
+/// Like [`copy`], but asks `on_conflict` (with the source and destination
+/// paths) what to do about every destination file that already exists with
+/// different contents. Files it keeps aren't counted as copied.
+pub fn copy_with<U, V, F>(from: U, to: V, on_conflict: F) -> Result<usize, CopyError>
+where
+    U: AsRef<Path>,
+    V: AsRef<Path>,
+    F: FnMut(&Path, &Path) -> Result<Conflict, std::io::Error>,
+{
+    copy_cancellable(from, to, on_conflict, &CancelToken::new())
+}
+
+/// Like [`copy_with`], but stops before the next file once `cancel` is
+/// cancelled, failing with [`std::io::ErrorKind::Interrupted`].
+pub fn copy_cancellable<U, V, F>(
+    from: U,
+    to: V,
+    mut on_conflict: F,
+    cancel: &CancelToken,
+) -> Result<usize, CopyError>
+where
+    U: AsRef<Path>,
+    V: AsRef<Path>,
+    F: FnMut(&Path, &Path) -> Result<Conflict, std::io::Error>,
+{
+    copy_filtered(from, to, Some(&mut on_conflict), |_| true, cancel)
+}
+
+/// Like [`copy_cancellable`], but only copies the files and directories for
+/// whose path, relative to `from`, `filter` returns true; a directory left
+/// out is left out with everything in it. Hidden files are copied like any
+/// other unless filtered out. Symlinks are copied as symlinks, and only if
+/// they lead to somewhere inside `from`, see [`CopyError::Escapes`].
+/// Without `on_conflict`, existing files are overwritten without even being
+/// compared, like [`copy`] does.
+pub fn copy_filtered<U, V, P>(
+    from: U,
+    to: V,
+    mut on_conflict: Option<OnConflict<'_>>,
+    mut filter: P,
+    cancel: &CancelToken,
+) -> Result<usize, CopyError>
+where
+    U: AsRef<Path>,
+    V: AsRef<Path>,
+    P: FnMut(&Path) -> bool,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    let mut copied = 0;
+
+    let mut walk = WalkDir::new(from).into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry.map_err(|err| {
+            let src = err.path().unwrap_or(from).to_path_buf();
+            let dest = src
+                .strip_prefix(from)
+                .ok()
+                .and_then(|it| join_within(to, it))
+                .unwrap_or_else(|| to.to_path_buf());
+            CopyError::io(src, dest)(err.into())
+        })?;
+        let src = entry.path();
+        // walkdir yields `from` joined with the path below it
+        let rel = src.strip_prefix(from).unwrap();
+        let dest = join_within(to, rel).ok_or_else(|| CopyError::Escapes {
+            path: rel.to_path_buf(),
+            root: to.to_path_buf(),
+        })?;
+        let fail = CopyError::io(src.to_path_buf(), dest.clone());
+
+        cancel.check().map_err(&fail)?;
+
+        if entry.depth() > 0 && !filter(rel) {
+            if entry.file_type().is_dir() {
+                walk.skip_current_dir();
+            }
+            continue;
+        }
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest).map_err(&fail)?;
+        } else if entry.file_type().is_symlink() {
+            let target = link_target_within(from, src)
+                .map_err(&fail)?
+                .ok_or_else(|| CopyError::Escapes {
+                    path: rel.to_path_buf(),
+                    root: from.to_path_buf(),
+                })?;
+            if dest.symlink_metadata().is_ok() {
+                if fs::read_link(&dest).ok().as_ref() == Some(&target) {
+                    continue;
+                }
+                if let Some(on_conflict) = &mut on_conflict {
+                    if let Conflict::Keep = on_conflict(src, &dest).map_err(&fail)? {
+                        continue;
+                    }
+                }
+                fs::remove_file(&dest).map_err(&fail)?;
+            }
+            symlink(&target, &dest, src.is_dir()).map_err(&fail)?;
+        } else {
+            let conflict = match &mut on_conflict {
+                Some(on_conflict)
+                    if dest.is_file() && files_differ(src, &dest).map_err(&fail)? =>
+                {
+                    on_conflict(src, &dest).map_err(&fail)?
+                }
+                _ => Conflict::Overwrite,
+            };
+
+            match conflict {
+                Conflict::Keep => continue,
+                Conflict::Overwrite => {
+                    fs::copy(src, &dest).map_err(&fail)?;
+                }
+                Conflict::Merge => {
+                    let existing = fs::read_to_string(&dest).map_err(&fail)?;
+                    let incoming = fs::read_to_string(src).map_err(&fail)?;
+                    let merged = merge_with_markers(&existing, &incoming);
+                    fs::write(&dest, merged).map_err(&fail)?;
+                }
+            }
+        }
+
+        if entry.depth() > 0 {
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Whether the files `a` and `b` have different contents: right away if
+/// their sizes differ, otherwise by comparing them a chunk at a time.
+fn files_differ(a: &Path, b: &Path) -> Result<bool, std::io::Error> {
+    const CHUNK: u64 = 64 * 1024;
+
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(true);
+    }
+
+    let (mut a, mut b) = (fs::File::open(a)?, fs::File::open(b)?);
+    let (mut chunk_a, mut chunk_b) = (Vec::new(), Vec::new());
+    loop {
+        chunk_a.clear();
+        chunk_b.clear();
+        (&mut a).take(CHUNK).read_to_end(&mut chunk_a)?;
+        (&mut b).take(CHUNK).read_to_end(&mut chunk_b)?;
+
+        if chunk_a != chunk_b {
+            return Ok(true);
+        } else if chunk_a.is_empty() {
+            return Ok(false);
+        }
+    }
+}
+
+/// The target of the symlink `link` in `root`, if it is relative and, with
+/// every symlink on the way resolved, leads to something inside `root`; a
+/// copy of `root` then has it lead to the copy of the same thing. Dangling
+/// links lead nowhere, so they aren't inside either.
+fn link_target_within(root: &Path, link: &Path) -> Result<Option<PathBuf>, std::io::Error> {
+    let target = fs::read_link(link)?;
+    if !target.is_relative() {
+        return Ok(None);
+    }
+
+    let inside = match (fs::canonicalize(root), fs::canonicalize(link)) {
+        (Ok(root), Ok(resolved)) => resolved.starts_with(root),
+        _ => false,
+    };
+
+    Ok(inside.then_some(target))
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> Result<(), std::io::Error> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_: &Path, _: &Path, _: bool) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported",
+    ))
+}
+
+/// `root` joined with the relative `path`, unless `path` could lead outside
+/// of `root`: if it has `..`, a root or (on Windows) a prefix like `C:`.
+pub fn join_within(root: &Path, path: &Path) -> Option<PathBuf> {
+    let contained = path
+        .components()
+        .all(|it| matches!(it, Component::Normal(_) | Component::CurDir));
+
+    contained.then(|| root.join(path))
+}
+
+/// Runs `f` holding an exclusive advisory lock on `<path>.lock`, so that other
+/// processes doing the same for `path` wait for it. Locking `path` itself
+/// wouldn't do, as [`write_atomic`] replaces it. The lock file is left behind.
+pub fn with_lock<T, E, F>(path: &Path, f: F) -> Result<T, E>
+where
+    E: From<std::io::Error>,
+    F: FnOnce() -> Result<T, E>,
+{
+    let mut lock_name = path.file_name().unwrap_or_default().to_os_string();
+    lock_name.push(".lock");
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_file_name(lock_name))?;
+    lock.lock()?;
+
+    // closing `lock`, even while unwinding, releases it too
+    let result = f();
+    let _ = lock.unlock();
+
+    result
+}
+
+/// Writes `contents` to a temporary file next to `path`, then renames it over
+/// `path`, so readers never see a half-written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    let tmp = TempFile::new(path);
+    fs::write(tmp.tmp_path(), contents)?;
+
+    tmp.commit()
+}
+
+/// Like [`write_atomic`] for several files at once: every file is written to
+/// a temporary file first, and only then are they renamed over theirs. If
+/// writing or renaming fails, the files already replaced get their previous
+/// contents back, or are removed again if they didn't exist.
+pub fn write_all_atomic<'a>(
+    files: impl IntoIterator<Item = (&'a Path, &'a [u8])>,
+) -> Result<(), std::io::Error> {
+    let mut written = Vec::new();
+    for (path, contents) in files {
+        let tmp = TempFile::new(path);
+        fs::write(tmp.tmp_path(), contents)?;
+
+        let previous = match fs::read(path) {
+            Ok(previous) => Some(previous),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        written.push((path, tmp, previous));
+    }
+
+    let mut replaced: Vec<(&Path, Option<Vec<u8>>)> = Vec::new();
+    for (path, tmp, previous) in written {
+        if let Err(err) = tmp.commit() {
+            for (path, previous) in replaced {
+                let _ = match previous {
+                    Some(previous) => write_atomic(path, &previous),
+                    None => fs::remove_file(path),
+                };
             }
+
+            return Err(err);
         }
+        replaced.push((path, previous));
     }
 
     Ok(())
 }
+
+/// The temporary file next to `path` that [`TempFile::commit`] renames over
+/// it; for files too large to write in one go. Removed again if dropped
+/// before then, e.g. when the write fails or is cancelled midway.
+pub(crate) struct TempFile {
+    tmp: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl TempFile {
+    pub(crate) fn new(path: &Path) -> Self {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+
+        Self {
+            tmp: path.with_file_name(tmp_name),
+            path: path.to_owned(),
+            committed: false,
+        }
+    }
+
+    /// Where to write the contents.
+    pub(crate) fn tmp_path(&self) -> &Path {
+        &self.tmp
+    }
+
+    pub(crate) fn commit(mut self) -> Result<(), std::io::Error> {
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A directory of its own for a test, removed again when dropped.
+    pub(crate) struct Scratch(PathBuf);
+
+    impl Scratch {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("thorc-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+            &self.0
+        }
+
+        /// Writes `contents` to `rel` below the directory, creating the
+        /// directories it is in.
+        pub(crate) fn write(&self, rel: &str, contents: &str) {
+            let path = self.0.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A template with dotfiles at its root and below it, and a `.git`.
+    pub(crate) fn dotted_template(scratch: &Scratch, at: &str) {
+        scratch.write(&format!("{}/.gitignore", at), "/target\n");
+        scratch.write(&format!("{}/.editorconfig", at), "root = true\n");
+        scratch.write(&format!("{}/.github/workflows/ci.yml", at), "on: push\n");
+        scratch.write(&format!("{}/src/main.rs", at), "fn main() {}\n");
+        scratch.write(&format!("{}/.git/HEAD", at), "ref: refs/heads/main\n");
+    }
+
+    #[test]
+    fn hashes_sha512() {
+        assert_eq!(
+            hash_buffer(b"abc"),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+    }
+
+    #[test]
+    fn joins_only_paths_staying_within_the_root() {
+        let root = Path::new("root");
+        assert_eq!(
+            join_within(root, Path::new("a/./b")),
+            Some(root.join("a/./b"))
+        );
+        assert_eq!(join_within(root, Path::new("")), Some(root.to_path_buf()));
+
+        for path in ["..", "a/../../b", "a/..", "/etc/passwd"] {
+            assert_eq!(join_within(root, Path::new(path)), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn hashes_streams_like_buffers() {
+        let data = (0..3 * HASH_CHUNK_SIZE)
+            .map(|it| it as u8)
+            .collect::<Vec<_>>();
+
+        assert_eq!(hash_reader(&data[..]).unwrap(), hash_buffer(&data));
+    }
+
+    #[test]
+    fn temp_files_only_replace_once_committed() {
+        let scratch = Scratch::new("temp-file");
+        let path = scratch.path().join("file");
+        scratch.write("file", "old");
+
+        let tmp = TempFile::new(&path);
+        fs::write(tmp.tmp_path(), "new").unwrap();
+        drop(tmp);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 1);
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn writes_all_files_or_none() {
+        let scratch = Scratch::new("write-all");
+        scratch.write("a", "old");
+        scratch.write("dir/file", "");
+        let (a, b, dir) = (
+            scratch.path().join("a"),
+            scratch.path().join("b"),
+            scratch.path().join("dir"),
+        );
+
+        let files = [(a.as_path(), &b"new"[..]), (&b, b"new"), (&dir, b"new")];
+        assert!(write_all_atomic(files).is_err());
+        assert_eq!(fs::read_to_string(&a).unwrap(), "old");
+        assert!(!b.exists());
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 2);
+
+        write_all_atomic(files.into_iter().take(2)).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "new");
+        assert_eq!(fs::read_dir(scratch.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn copy_keeps_dotfiles() {
+        let scratch = Scratch::new("copy-dotfiles");
+        dotted_template(&scratch, "from");
+        let (from, to) = (scratch.path().join("from"), scratch.path().join("to"));
+
+        copy(&from, &to).unwrap();
+
+        for file in [
+            ".gitignore",
+            ".editorconfig",
+            ".github/workflows/ci.yml",
+            ".git/HEAD",
+        ] {
+            assert_eq!(
+                fs::read(to.join(file)).unwrap(),
+                fs::read(from.join(file)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn copy_filtered_skips_what_is_left_out() {
+        let scratch = Scratch::new("copy-filtered");
+        dotted_template(&scratch, "from");
+        let to = scratch.path().join("to");
+
+        let copied = copy_filtered(
+            scratch.path().join("from"),
+            &to,
+            None,
+            |path| path.file_name() != Some(".git".as_ref()),
+            &CancelToken::new(),
+        )
+        .unwrap();
+
+        assert!(!to.join(".git").exists());
+        assert!(to.join(".gitignore").is_file());
+        assert!(to.join(".github/workflows/ci.yml").is_file());
+        // .gitignore, .editorconfig, .github, its workflows, ci.yml, src and main.rs
+        assert_eq!(copied, 7);
+    }
+
+    #[test]
+    fn hashes_trees_by_their_records() {
+        let scratch = Scratch::new("hash-dir");
+        scratch.write("one/a", "1");
+        scratch.write("one/b", "2");
+        // the same bytes, were paths and contents only put one after another
+        scratch.write("two/a", "1b\u{0}2");
+
+        let one = hash_dir(&scratch.path().join("one")).unwrap();
+        assert_ne!(one, hash_dir(&scratch.path().join("two")).unwrap());
+
+        scratch.write("three/a", "1");
+        scratch.write("three/b", "2");
+        assert_eq!(one, hash_dir(&scratch.path().join("three")).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hashes_symlinks_without_following_them() {
+        let scratch = Scratch::new("hash-symlinks");
+        scratch.write("dir/a", "1");
+        std::os::unix::fs::symlink(".", scratch.path().join("dir/self")).unwrap();
+        let linked = hash_dir(&scratch.path().join("dir")).unwrap();
+
+        scratch.write("files/a", "1");
+        scratch.write("files/self", ".");
+        assert_ne!(linked, hash_dir(&scratch.path().join("files")).unwrap());
+    }
+
+    #[test]
+    fn copy_stops_once_cancelled() {
+        let scratch = Scratch::new("copy-cancelled");
+        dotted_template(&scratch, "from");
+        let to = scratch.path().join("to");
+        let cancel = CancelToken::new();
+
+        let mut seen = 0;
+        let copied = copy_filtered(
+            scratch.path().join("from"),
+            &to,
+            None,
+            |_| {
+                seen += 1;
+                cancel.cancel();
+                true
+            },
+            &cancel,
+        );
+
+        assert!(
+            matches!(&copied, Err(CopyError::Io { source, .. }) if source.kind() == std::io::ErrorKind::Interrupted),
+            "{:?}",
+            copied
+        );
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn compares_files_by_size_then_chunks() {
+        let scratch = Scratch::new("files-differ");
+        let big = "a".repeat(200 * 1024);
+        let mut changed = big.clone();
+        changed.replace_range(150 * 1024..150 * 1024 + 1, "b");
+        scratch.write("big", &big);
+        scratch.write("same", &big);
+        scratch.write("changed", &changed);
+        scratch.write("short", &big[1..]);
+
+        let path = |name| scratch.path().join(name);
+        assert!(!files_differ(&path("big"), &path("same")).unwrap());
+        assert!(files_differ(&path("big"), &path("changed")).unwrap());
+        assert!(files_differ(&path("big"), &path("short")).unwrap());
+    }
+
+    #[test]
+    fn copy_only_asks_about_files_that_differ() {
+        let scratch = Scratch::new("copy-conflicts");
+        dotted_template(&scratch, "from");
+        let (from, to) = (scratch.path().join("from"), scratch.path().join("to"));
+        dotted_template(&scratch, "to");
+        scratch.write("to/src/main.rs", "fn main() { todo!() }\n");
+
+        let mut asked = Vec::new();
+        copy_with(&from, &to, |_, dest| {
+            asked.push(dest.strip_prefix(&to).unwrap().to_path_buf());
+            Ok(Conflict::Keep)
+        })
+        .unwrap();
+        assert_eq!(asked, [Path::new("src/main.rs")]);
+        assert_eq!(
+            fs::read_to_string(to.join("src/main.rs")).unwrap(),
+            "fn main() { todo!() }\n"
+        );
+
+        copy(&from, &to).unwrap();
+        assert_eq!(
+            fs::read_to_string(to.join("src/main.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn copy_keeps_symlinks_inside_the_template() {
+        use std::os::unix::fs::symlink;
+
+        let scratch = Scratch::new("copy-symlinks");
+        dotted_template(&scratch, "from");
+        let (from, to) = (scratch.path().join("from"), scratch.path().join("to"));
+        symlink("main.rs", from.join("src/lib.rs")).unwrap();
+        symlink("src", from.join("source")).unwrap();
+        symlink("../.gitignore", from.join("src/.gitignore")).unwrap();
+
+        copy(&from, &to).unwrap();
+
+        assert_eq!(
+            fs::read_link(to.join("src/lib.rs")).unwrap(),
+            Path::new("main.rs")
+        );
+        assert_eq!(fs::read_link(to.join("source")).unwrap(), Path::new("src"));
+        assert_eq!(
+            fs::read_to_string(to.join("source/.gitignore")).unwrap(),
+            "/target\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn copy_refuses_symlinks_leading_out_of_the_template() {
+        use std::os::unix::fs::symlink;
+
+        let scratch = Scratch::new("copy-escaping-symlinks");
+        scratch.write("secret", "");
+        let outside = scratch.path().join("secret");
+        for (i, target) in [
+            Path::new("../secret"),
+            &outside,
+            Path::new("missing"),
+            Path::new("src/../../secret"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let from = scratch.path().join(format!("from{}", i));
+            dotted_template(&scratch, &format!("from{}", i));
+            symlink(target, from.join("link")).unwrap();
+
+            let copied = copy(&from, scratch.path().join(format!("to{}", i)));
+            assert!(
+                matches!(&copied, Err(CopyError::Escapes { path, .. }) if path == Path::new("link")),
+                "{}: {:?}",
+                target.display(),
+                copied
+            );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use common::Scratch;
+use thorc::{
+    error::DownloadError,
+    generate::{generate_project, generate_project_with, ConflictResolver, GenerateOptions},
+    http::{Downloader, Fetched},
+    index::TemplateIndex,
+    template::Template,
+    Thorc,
+};
+
+fn local_template(scratch: &Scratch) -> Template {
+    scratch.write("template/.gitignore", "/target\n");
+    scratch.write("template/.editorconfig", "root = true\n");
+    scratch.write("template/src/main.rs", "fn main() {}\n");
+    scratch.write("template/.git/HEAD", "ref: refs/heads/main\n");
+
+    Template::Local {
+        name: "dotted".to_string(),
+        description: None,
+        metadata: Default::default(),
+        path: scratch.path().join("template"),
+    }
+}
+
+fn generate(scratch: &Scratch, config: &str) {
+    scratch.write("config.toml", config);
+    let thorc = Thorc::builder()
+        .config_file(scratch.path().join("config.toml"))
+        .local_index_file(scratch.path().join("index.toml"))
+        .cache_dir(scratch.path().join("cache"))
+        .build()
+        .unwrap();
+
+    let template = local_template(scratch);
+    thorc
+        .generate(
+            &template,
+            &scratch.path().join("out"),
+            "out",
+            &BTreeMap::new(),
+            None,
+        )
+        .unwrap();
+}
+
+#[test]
+fn local_templates_keep_dotfiles_and_git_dirs() {
+    let scratch = Scratch::new("keep-git");
+    generate(&scratch, "");
+
+    let out = scratch.path().join("out");
+    assert!(out.join(".gitignore").is_file());
+    assert!(out.join(".editorconfig").is_file());
+    assert!(out.join(".git/HEAD").is_file());
+}
+
+#[test]
+fn skip_git_dirs_leaves_out_only_git_dirs() {
+    let scratch = Scratch::new("skip-git");
+    generate(&scratch, "skip_git_dirs = true\n");
+
+    let out = scratch.path().join("out");
+    assert!(out.join(".gitignore").is_file());
+    assert!(out.join(".editorconfig").is_file());
+    assert!(out.join("src/main.rs").is_file());
+    assert!(!out.join(".git").exists());
+}
+
+/// Serves `index`, counting the fetches.
+struct CountingDownloader {
+    index: String,
+    fetches: Arc<AtomicUsize>,
+}
+
+impl Downloader for CountingDownloader {
+    fn fetch(&self, _: &str, _: Option<&str>) -> Result<Option<Fetched>, DownloadError> {
+        self.fetches.fetch_add(1, Ordering::SeqCst);
+
+        Ok(Some(Fetched {
+            bytes: self.index.clone().into_bytes(),
+            ..Default::default()
+        }))
+    }
+}
+
+/// Both the local index and the remote index `remote` have the template
+/// `dotted`; returns a [`Thorc`] for them and the number of remote fetches.
+fn thorc_with_duplicate(scratch: &Scratch) -> (Thorc, Arc<AtomicUsize>) {
+    let mut index = TemplateIndex::default();
+    index.templates.insert(local_template(scratch));
+    index.save(&scratch.path().join("index.toml")).unwrap();
+
+    scratch.write(
+        "config.toml",
+        "[[remote_index]]\nname = \"remote\"\nurl = \"http://remote.invalid/index.toml\"\n",
+    );
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let thorc = Thorc::builder()
+        .config_file(scratch.path().join("config.toml"))
+        .local_index_file(scratch.path().join("index.toml"))
+        .cache_dir(scratch.path().join("cache"))
+        .downloader(CountingDownloader {
+            index: std::fs::read_to_string(scratch.path().join("index.toml")).unwrap(),
+            fetches: fetches.clone(),
+        })
+        .build()
+        .unwrap();
+
+    (thorc, fetches)
+}
+
+#[test]
+fn generating_fetches_no_index_after_the_template_is_found() {
+    let scratch = Scratch::new("no-duplicate-check");
+    let (thorc, fetches) = thorc_with_duplicate(&scratch);
+
+    generate_project(
+        &thorc,
+        GenerateOptions::new("dotted", scratch.path().join("out")),
+    )
+    .unwrap();
+
+    assert_eq!(fetches.load(Ordering::SeqCst), 0);
+    assert!(scratch.path().join("out/src/main.rs").is_file());
+}
+
+#[test]
+fn resolvers_checking_duplicates_choose_among_every_index() {
+    struct Second(Vec<String>);
+
+    impl ConflictResolver for Second {
+        fn checks_duplicates(&self) -> bool {
+            true
+        }
+
+        fn duplicate_template(
+            &mut self,
+            _: &str,
+            candidates: &[(&str, &Template)],
+        ) -> Option<usize> {
+            self.0 = candidates
+                .iter()
+                .map(|(index, _)| index.to_string())
+                .collect();
+            Some(1)
+        }
+    }
+
+    let scratch = Scratch::new("duplicate-check");
+    let (thorc, fetches) = thorc_with_duplicate(&scratch);
+
+    let mut resolver = Second(Vec::new());
+    generate_project_with(
+        &thorc,
+        GenerateOptions::new("dotted", scratch.path().join("out")),
+        &mut resolver,
+    )
+    .unwrap();
+
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    assert_eq!(resolver.0, ["local", "remote"]);
+}
+
+#[test]
+fn lock_files_of_templates_are_left_out() {
+    let scratch = Scratch::new("template-lock");
+    scratch.write("config.toml", "");
+    let thorc = Thorc::builder()
+        .config_file(scratch.path().join("config.toml"))
+        .local_index_file(scratch.path().join("index.toml"))
+        .cache_dir(scratch.path().join("cache"))
+        .build()
+        .unwrap();
+    let template = local_template(&scratch);
+    scratch.write("template/thorc.lock", "from the template\n");
+
+    let out = scratch.path().join("out");
+    let mut asked = Vec::new();
+    for _ in 0..2 {
+        thorc
+            .generate_with(
+                &template,
+                &out,
+                "out",
+                &BTreeMap::new(),
+                None,
+                |_: &std::path::Path, dest: &std::path::Path| {
+                    asked.push(dest.to_owned());
+                    Ok(thorc::utils::Conflict::Overwrite)
+                },
+            )
+            .unwrap();
+    }
+
+    assert!(asked.is_empty(), "{:?}", asked);
+    let lock = std::fs::read_to_string(out.join("thorc.lock")).unwrap();
+    assert!(lock.starts_with("project_name = 'out'"), "{}", lock);
+}
+
+#[test]
+fn cancelled_generation_removes_the_new_directory() {
+    let scratch = Scratch::new("cancelled");
+    scratch.write("config.toml", "");
+    let cancel = thorc::cancel::CancelToken::new();
+    let thorc = Thorc::builder()
+        .config_file(scratch.path().join("config.toml"))
+        .local_index_file(scratch.path().join("index.toml"))
+        .cache_dir(scratch.path().join("cache"))
+        .cancel_token(cancel.clone())
+        .build()
+        .unwrap();
+    let template = local_template(&scratch);
+
+    cancel.cancel();
+    let out = scratch.path().join("out");
+    let generated = thorc.generate(&template, &out, "out", &BTreeMap::new(), None);
+
+    assert!(
+        matches!(generated, Err(thorc::Error::Cancelled)),
+        "{:?}",
+        generated
+    );
+    assert!(!out.exists());
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::Scratch;
+use thorc::{index::TemplateIndex, template::Template, test_utils::MockServer, Thorc};
+
+fn archive_template(server: &MockServer) -> Template {
+    Template::Archive {
+        name: "dotted".to_string(),
+        description: None,
+        metadata: Default::default(),
+        url: server.url_for("/dotted.tar.gz"),
+        setup: None,
+    }
+}
+
+/// Serves the template in `scratch`'s `template` directory.
+fn serve_template(server: &MockServer, scratch: &Scratch) {
+    server
+        .serve_dir_archive(
+            "/dotted.tar.gz",
+            &scratch.path().join("template"),
+            "dotted-main",
+        )
+        .unwrap();
+}
+
+fn thorc(scratch: &Scratch, config: &str) -> Thorc {
+    scratch.write("config.toml", config);
+
+    Thorc::builder()
+        .config_file(scratch.path().join("config.toml"))
+        .local_index_file(scratch.path().join("index.toml"))
+        .cache_dir(scratch.path().join("cache"))
+        .build()
+        .unwrap()
+}
+
+/// Makes the file at `path` look like it was written an hour ago, long
+/// enough for thorc to ask the server again whether it changed.
+fn age(path: &Path) {
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(an_hour_ago)
+        .unwrap();
+}
+
+#[test]
+fn downloads_extracts_and_generates_from_a_remote_index() {
+    let scratch = Scratch::new("mock-generate");
+    scratch.write("template/.gitignore", "/target\n");
+    scratch.write("template/src/main.rs", "fn main() {}\n");
+
+    let server = MockServer::start().unwrap();
+    serve_template(&server, &scratch);
+    let mut index = TemplateIndex::default();
+    index.templates.insert(archive_template(&server));
+    server.serve_index("/index.toml", &index).unwrap();
+
+    let thorc = thorc(
+        &scratch,
+        &format!(
+            "[[remote_index]]\nname = \"mock\"\nurl = \"{}\"\n",
+            server.url_for("/index.toml")
+        ),
+    );
+    let template = thorc.resolve(None, "dotted").unwrap();
+    let out = scratch.path().join("out");
+    thorc
+        .generate(&template, &out, "out", &BTreeMap::new(), None)
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(out.join(".gitignore")).unwrap(),
+        "/target\n"
+    );
+    assert_eq!(
+        fs::read_to_string(out.join("src/main.rs")).unwrap(),
+        "fn main() {}\n"
+    );
+    assert_eq!(server.requests(), ["/index.toml", "/dotted.tar.gz"]);
+}
+
+#[test]
+fn unchanged_archives_are_not_downloaded_again() {
+    let scratch = Scratch::new("mock-etag");
+    scratch.write("template/README.md", "first\n");
+
+    let server = MockServer::start().unwrap();
+    serve_template(&server, &scratch);
+    let thorc = thorc(&scratch, "");
+    let template = archive_template(&server);
+
+    let first = thorc.download(&template).unwrap();
+    let archive = fs::read_dir(scratch.path().join("cache"))
+        .unwrap()
+        .map(|it| it.unwrap().path())
+        .find(|it| it.to_string_lossy().ends_with(".tar.gz"))
+        .unwrap();
+
+    // a fresh copy isn't even asked about
+    assert_eq!(thorc.download(&template).unwrap(), first);
+    assert_eq!(server.requests().len(), 1);
+
+    // a stale one is, and the server's 304 leaves it as it was
+    age(&archive);
+    let aged = archive.metadata().unwrap().modified().unwrap();
+    assert_eq!(thorc.download(&template).unwrap(), first);
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(archive.metadata().unwrap().modified().unwrap(), aged);
+
+    // until the archive changes
+    scratch.write("template/README.md", "second\n");
+    serve_template(&server, &scratch);
+    age(&archive);
+    let second = thorc.download(&template).unwrap();
+    assert_eq!(server.requests().len(), 3);
+    assert_ne!(second, first);
+    assert_eq!(
+        fs::read_to_string(second.join("README.md")).unwrap(),
+        "second\n"
+    );
+}